@@ -0,0 +1,568 @@
+//! Exploratory analysis helpers
+//!
+//! These helpers synthesize SQL over a registered table for common data
+//! exploration tasks, so users don't have to write the queries by hand.
+
+use crate::engine::QueryEngine;
+use crate::error::{Result, SqlStreamError};
+use crate::sql::{normalize_table_name, quote_ident};
+use datafusion::arrow::array::{
+    Array, ArrayRef, BooleanArray, Float64Array, Int64Array, StringArray,
+};
+use datafusion::arrow::compute::cast;
+use datafusion::arrow::datatypes::{DataType, Field, Schema};
+use datafusion::arrow::record_batch::RecordBatch;
+use std::sync::Arc;
+use tracing::debug;
+
+/// Look up a column in a schema, returning a helpful error if it is missing
+pub fn require_column<'a>(schema: &'a Schema, column: &str) -> Result<&'a Field> {
+    schema
+        .field_with_name(column)
+        .map_err(|_| SqlStreamError::ColumnNotFound {
+            column: column.to_string(),
+            available: schema.fields().iter().map(|f| f.name().clone()).collect(),
+        })
+}
+
+/// Look up a column and check it has a numeric type
+fn require_numeric<'a>(schema: &'a Schema, column: &str) -> Result<&'a Field> {
+    let field = require_column(schema, column)?;
+    if !field.data_type().is_numeric() {
+        return Err(SqlStreamError::InvalidOption(format!(
+            "Column '{}' is not numeric (type {})",
+            column,
+            field.data_type()
+        )));
+    }
+    Ok(field)
+}
+
+/// Names of all numeric columns in a schema, in schema order
+pub fn numeric_columns(schema: &Schema) -> Vec<String> {
+    schema
+        .fields()
+        .iter()
+        .filter(|f| f.data_type().is_numeric())
+        .map(|f| f.name().clone())
+        .collect()
+}
+
+/// Describe a schema as a table of column names, types and nullability
+///
+/// # Errors
+///
+/// Returns an error if the description batch cannot be built
+pub fn schema_table(schema: &Schema) -> Result<RecordBatch> {
+    let fields = schema.fields();
+    let names: StringArray = fields.iter().map(|f| Some(f.name().as_str())).collect();
+    let types: StringArray = fields
+        .iter()
+        .map(|f| Some(f.data_type().to_string()))
+        .collect();
+    let nullable: BooleanArray = fields.iter().map(|f| Some(f.is_nullable())).collect();
+
+    let description = Schema::new(vec![
+        Field::new("column_name", DataType::Utf8, false),
+        Field::new("data_type", DataType::Utf8, false),
+        Field::new("is_nullable", DataType::Boolean, false),
+    ]);
+    Ok(RecordBatch::try_new(
+        Arc::new(description),
+        vec![Arc::new(names), Arc::new(types), Arc::new(nullable)],
+    )?)
+}
+
+/// Whether MIN and MAX are meaningful for values of a type
+fn is_orderable(data_type: &DataType) -> bool {
+    data_type.is_numeric()
+        || data_type.is_temporal()
+        || matches!(
+            data_type,
+            DataType::Boolean | DataType::Utf8 | DataType::LargeUtf8 | DataType::Utf8View
+        )
+}
+
+/// SQL profiling one column of a table in a single aggregation
+///
+/// The row holds the non-null count, null count, distinct count, minimum,
+/// maximum and mean. Statistics that don't apply to the column's type are
+/// NULL: the mean is only computed for numeric columns, min and max only for
+/// orderable ones, and distinct values are not counted for nested types.
+fn profile_query(field: &Field, table_name: &str) -> String {
+    let col = quote_ident(field.name());
+    let data_type = field.data_type();
+    let distinct = if data_type.is_nested() {
+        "CAST(NULL AS BIGINT)".to_string()
+    } else {
+        format!("COUNT(DISTINCT {col})")
+    };
+    let (min, max) = if is_orderable(data_type) {
+        (
+            format!("CAST(MIN({col}) AS VARCHAR)"),
+            format!("CAST(MAX({col}) AS VARCHAR)"),
+        )
+    } else {
+        let null = "CAST(NULL AS VARCHAR)".to_string();
+        (null.clone(), null)
+    };
+    let avg = if data_type.is_numeric() {
+        format!("AVG(CAST({col} AS DOUBLE))")
+    } else {
+        "CAST(NULL AS DOUBLE)".to_string()
+    };
+    format!(
+        "SELECT COUNT({col}), COUNT(*) - COUNT({col}), {distinct}, {min}, {max}, {avg} \
+         FROM {table_name}"
+    )
+}
+
+/// Profile every column of a table
+///
+/// The result has one row per column, in schema order, with its name and
+/// type, non-null `count`, `null_count`, `distinct_count`, `min`, `max` and
+/// `avg`. One aggregation query is run per column; statistics that don't
+/// apply to a column's type are NULL. Min and max are shown as
+/// text so columns of every type fit in one table.
+///
+/// # Errors
+///
+/// Returns an error if the table does not exist or a query fails
+pub async fn profile(engine: &QueryEngine, table_name: &str) -> Result<RecordBatch> {
+    let schema = engine.table_schema(table_name).await?;
+    let fields = schema.fields();
+
+    let mut counts = Vec::with_capacity(fields.len());
+    let mut null_counts = Vec::with_capacity(fields.len());
+    let mut distinct_counts = Vec::with_capacity(fields.len());
+    let mut mins = Vec::with_capacity(fields.len());
+    let mut maxes = Vec::with_capacity(fields.len());
+    let mut avgs = Vec::with_capacity(fields.len());
+    for field in fields {
+        let sql = profile_query(field, table_name);
+        debug!("Profile query: {}", sql);
+        let batches = engine.execute_query(&sql).await?.collect().await?;
+        let batch = batches
+            .iter()
+            .find(|b| b.num_rows() > 0)
+            .ok_or_else(|| SqlStreamError::QueryExecution("Query returned no rows".to_string()))?;
+
+        let int = |index: usize| -> Result<Option<i64>> {
+            let values = cast(batch.column(index), &DataType::Int64)?;
+            let values = values
+                .as_any()
+                .downcast_ref::<Int64Array>()
+                .ok_or_else(|| {
+                    SqlStreamError::QueryExecution("Expected Int64 counts".to_string())
+                })?;
+            Ok(values.is_valid(0).then(|| values.value(0)))
+        };
+        let text = |index: usize| -> Result<Option<String>> {
+            let values = cast(batch.column(index), &DataType::Utf8)?;
+            let values = values
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .ok_or_else(|| {
+                    SqlStreamError::QueryExecution("Expected text values".to_string())
+                })?;
+            Ok(values.is_valid(0).then(|| values.value(0).to_string()))
+        };
+        let values = batch.column(5);
+        let values = values
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .ok_or_else(|| SqlStreamError::QueryExecution("Expected a Float64 mean".to_string()))?;
+
+        counts.push(int(0)?);
+        null_counts.push(int(1)?);
+        distinct_counts.push(int(2)?);
+        mins.push(text(3)?);
+        maxes.push(text(4)?);
+        avgs.push(values.is_valid(0).then(|| values.value(0)));
+    }
+
+    let names: StringArray = fields.iter().map(|f| Some(f.name().as_str())).collect();
+    let types: StringArray = fields
+        .iter()
+        .map(|f| Some(f.data_type().to_string()))
+        .collect();
+    let description = Schema::new(vec![
+        Field::new("column_name", DataType::Utf8, false),
+        Field::new("data_type", DataType::Utf8, false),
+        Field::new("count", DataType::Int64, true),
+        Field::new("null_count", DataType::Int64, true),
+        Field::new("distinct_count", DataType::Int64, true),
+        Field::new("min", DataType::Utf8, true),
+        Field::new("max", DataType::Utf8, true),
+        Field::new("avg", DataType::Float64, true),
+    ]);
+    Ok(RecordBatch::try_new(
+        Arc::new(description),
+        vec![
+            Arc::new(names),
+            Arc::new(types),
+            Arc::new(Int64Array::from(counts)),
+            Arc::new(Int64Array::from(null_counts)),
+            Arc::new(Int64Array::from(distinct_counts)),
+            Arc::new(StringArray::from(mins)),
+            Arc::new(StringArray::from(maxes)),
+            Arc::new(Float64Array::from(avgs)),
+        ],
+    )?)
+}
+
+/// SQL for the Pearson correlation of two columns over pairwise-complete rows
+///
+/// `corr` itself skips rows where either value is NULL.
+fn corr_expr(x: &str, y: &str) -> String {
+    let (x, y) = (quote_ident(x), quote_ident(y));
+    format!("corr(CAST({x} AS DOUBLE), CAST({y} AS DOUBLE))")
+}
+
+/// Compute the Pearson correlation coefficient between two numeric columns
+///
+/// Only rows where both columns are non-null contribute. Returns `None` when
+/// the coefficient is undefined (fewer than two complete pairs).
+///
+/// # Errors
+///
+/// Returns an error if either column is missing or not numeric, or the query fails
+pub async fn correlation(
+    engine: &QueryEngine,
+    table_name: &str,
+    x: &str,
+    y: &str,
+) -> Result<Option<f64>> {
+    let schema = engine.table_schema(table_name).await?;
+    require_numeric(&schema, x)?;
+    require_numeric(&schema, y)?;
+
+    let sql = format!(
+        "SELECT {} FROM {}",
+        corr_expr(x, y),
+        quote_ident(&normalize_table_name(table_name))
+    );
+    let values = single_row(engine, &sql).await?;
+    Ok(values.first().copied().flatten())
+}
+
+/// Compute the correlation matrix over every numeric column of a table
+///
+/// The result has a `column` name column followed by one Float64 column per
+/// numeric column. All pairs are computed in a single aggregation query.
+///
+/// # Errors
+///
+/// Returns an error if the table has no numeric columns or the query fails
+pub async fn correlation_matrix(engine: &QueryEngine, table_name: &str) -> Result<RecordBatch> {
+    let schema = engine.table_schema(table_name).await?;
+    let columns = numeric_columns(&schema);
+    if columns.is_empty() {
+        return Err(SqlStreamError::InvalidOption(format!(
+            "Table '{}' has no numeric columns",
+            table_name
+        )));
+    }
+
+    // Only compute the upper triangle; the matrix is symmetric
+    let mut pairs = Vec::new();
+    for i in 0..columns.len() {
+        for j in i..columns.len() {
+            pairs.push((i, j));
+        }
+    }
+
+    let select = pairs
+        .iter()
+        .map(|&(i, j)| corr_expr(&columns[i], &columns[j]))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let sql = format!(
+        "SELECT {} FROM {}",
+        select,
+        quote_ident(&normalize_table_name(table_name))
+    );
+    debug!("Correlation matrix query: {}", sql);
+    let values = single_row(engine, &sql).await?;
+
+    let n = columns.len();
+    let mut matrix = vec![vec![None; n]; n];
+    for (&(i, j), value) in pairs.iter().zip(values) {
+        matrix[i][j] = value;
+        matrix[j][i] = value;
+    }
+
+    let mut fields = vec![Field::new("column", DataType::Utf8, false)];
+    let mut arrays: Vec<ArrayRef> = vec![Arc::new(StringArray::from(columns.clone()))];
+    for (j, name) in columns.iter().enumerate() {
+        fields.push(Field::new(name, DataType::Float64, true));
+        let values: Float64Array = matrix.iter().map(|row| row[j]).collect();
+        arrays.push(Arc::new(values));
+    }
+
+    Ok(RecordBatch::try_new(Arc::new(Schema::new(fields)), arrays)?)
+}
+
+/// Count occurrences of each distinct value of a column, most frequent first
+///
+/// Values are rendered as text with NULL shown as `NULL`. Ties are ordered by
+/// value. At most `top` values are returned.
+///
+/// # Errors
+///
+/// Returns an error if the column does not exist or the query fails
+pub async fn value_frequencies(
+    engine: &QueryEngine,
+    table_name: &str,
+    column: &str,
+    top: usize,
+) -> Result<Vec<(String, i64)>> {
+    let schema = engine.table_schema(table_name).await?;
+    require_column(&schema, column)?;
+
+    let sql = format!(
+        "SELECT CAST({col} AS VARCHAR) AS \"value\", COUNT(*) AS \"frequency\" FROM {table} \
+         GROUP BY {col} ORDER BY \"frequency\" DESC, \"value\" LIMIT {top}",
+        col = quote_ident(column),
+        table = table_name,
+        top = top
+    );
+    let batches = engine.execute_query(&sql).await?.collect().await?;
+
+    let mut frequencies = Vec::new();
+    for batch in &batches {
+        let values = cast(batch.column(0), &DataType::Utf8)?;
+        let values = values
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .ok_or_else(|| SqlStreamError::QueryExecution("Expected text values".to_string()))?;
+        let counts = batch
+            .column(1)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .ok_or_else(|| SqlStreamError::QueryExecution("Expected Int64 counts".to_string()))?;
+
+        for row in 0..batch.num_rows() {
+            let value = if values.is_null(row) {
+                "NULL".to_string()
+            } else {
+                values.value(row).to_string()
+            };
+            frequencies.push((value, counts.value(row)));
+        }
+    }
+
+    Ok(frequencies)
+}
+
+/// SQL listing key combinations that occur more than once, most repeated first
+///
+/// With no `columns`, whole rows are compared.
+///
+/// # Errors
+///
+/// Returns an error if a named column does not exist
+pub fn duplicates_query(schema: &Schema, table_name: &str, columns: &[String]) -> Result<String> {
+    for column in columns {
+        require_column(schema, column)?;
+    }
+    let columns = if columns.is_empty() {
+        schema.fields().iter().map(|f| f.name().clone()).collect()
+    } else {
+        columns.to_vec()
+    };
+
+    let keys = columns
+        .iter()
+        .map(|c| quote_ident(c))
+        .collect::<Vec<_>>()
+        .join(", ");
+    Ok(format!(
+        "SELECT {keys}, COUNT(*) AS \"duplicate_count\" FROM {table} GROUP BY {keys} \
+         HAVING COUNT(*) > 1 ORDER BY \"duplicate_count\" DESC",
+        keys = keys,
+        table = table_name
+    ))
+}
+
+/// Find rows, or combinations of `columns`, that appear more than once
+///
+/// Each result row holds the key values and a `duplicate_count` column.
+/// An empty result means there are no duplicates.
+///
+/// # Errors
+///
+/// Returns an error if a column does not exist or the query fails
+pub async fn find_duplicates(
+    engine: &QueryEngine,
+    table_name: &str,
+    columns: &[String],
+) -> Result<Vec<RecordBatch>> {
+    let schema = engine.table_schema(table_name).await?;
+    let sql = duplicates_query(&schema, table_name, columns)?;
+    debug!("Duplicates query: {}", sql);
+    Ok(engine.execute_query(&sql).await?.collect().await?)
+}
+
+/// Longest label shown in a bar chart before it is truncated
+const MAX_LABEL_WIDTH: usize = 30;
+
+/// Render value frequencies as a horizontal bar chart
+///
+/// Each line shows the value, its count and a bar scaled so the most frequent
+/// value fills the remaining space within `width` columns.
+pub fn render_bar_chart(frequencies: &[(String, i64)], width: usize) -> String {
+    let label_width = frequencies
+        .iter()
+        .map(|(value, _)| value.chars().count().min(MAX_LABEL_WIDTH))
+        .max()
+        .unwrap_or(0);
+    let count_width = frequencies
+        .iter()
+        .map(|(_, count)| count.to_string().len())
+        .max()
+        .unwrap_or(0);
+    let max_count = frequencies.iter().map(|(_, c)| *c).max().unwrap_or(0);
+    let bar_width = width.saturating_sub(label_width + count_width + 2).max(1);
+
+    frequencies
+        .iter()
+        .map(|(value, count)| {
+            let label: String = if value.chars().count() > MAX_LABEL_WIDTH {
+                let mut label: String = value.chars().take(MAX_LABEL_WIDTH - 1).collect();
+                label.push('…');
+                label
+            } else {
+                value.clone()
+            };
+            let bar_len = if max_count > 0 {
+                ((*count as f64 / max_count as f64) * bar_width as f64).round() as usize
+            } else {
+                0
+            };
+            let bar_len = if *count > 0 { bar_len.max(1) } else { 0 };
+
+            format!(
+                "{:<label_width$} {:>count_width$} {}",
+                label,
+                count,
+                "█".repeat(bar_len)
+            )
+            .trim_end()
+            .to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Run a query returning a single row of Float64 columns
+async fn single_row(engine: &QueryEngine, sql: &str) -> Result<Vec<Option<f64>>> {
+    let batches = engine.execute_query(sql).await?.collect().await?;
+    let batch = batches
+        .iter()
+        .find(|b| b.num_rows() > 0)
+        .ok_or_else(|| SqlStreamError::QueryExecution("Query returned no rows".to_string()))?;
+
+    batch
+        .columns()
+        .iter()
+        .map(|column| {
+            let values = column
+                .as_any()
+                .downcast_ref::<Float64Array>()
+                .ok_or_else(|| {
+                    SqlStreamError::QueryExecution(format!(
+                        "Expected a Float64 result, got {}",
+                        column.data_type()
+                    ))
+                })?;
+            Ok(values.is_valid(0).then(|| values.value(0)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_query_by_type() {
+        let query = profile_query(&Field::new("age", DataType::Int64, true), "employees");
+        assert!(query.contains("COUNT(DISTINCT \"age\")"));
+        assert!(query.contains("CAST(MIN(\"age\") AS VARCHAR)"));
+        assert!(query.contains("AVG(CAST(\"age\" AS DOUBLE))"));
+        assert!(query.ends_with("FROM employees"));
+
+        let query = profile_query(&Field::new("name", DataType::Utf8, true), "employees");
+        assert!(query.contains("CAST(MAX(\"name\") AS VARCHAR)"));
+        assert!(query.contains("CAST(NULL AS DOUBLE)"));
+
+        let tags = DataType::List(Arc::new(Field::new("item", DataType::Utf8, true)));
+        let query = profile_query(&Field::new("tags", tags, true), "users");
+        assert!(!query.contains("DISTINCT"));
+        assert!(!query.contains("MIN("));
+    }
+
+    #[test]
+    fn test_corr_expr() {
+        assert_eq!(
+            corr_expr("age", "salary"),
+            "corr(CAST(\"age\" AS DOUBLE), CAST(\"salary\" AS DOUBLE))"
+        );
+    }
+
+    #[test]
+    fn test_render_bar_chart() {
+        let frequencies = vec![
+            ("New York".to_string(), 10),
+            ("LA".to_string(), 5),
+            ("Chicago".to_string(), 1),
+        ];
+        let chart = render_bar_chart(&frequencies, 40);
+        let lines: Vec<_> = chart.lines().collect();
+
+        assert_eq!(lines.len(), 3);
+        // Labels are padded to the widest value, counts right-aligned
+        assert!(lines[0].starts_with("New York 10 "));
+        assert!(lines[1].starts_with("LA        5 "));
+        // The top value fills the bar area; the rest scale against it
+        assert_eq!(lines[0].matches('█').count(), 28);
+        assert_eq!(lines[1].matches('█').count(), 14);
+        assert_eq!(lines[2].matches('█').count(), 3);
+    }
+
+    #[test]
+    fn test_duplicates_query() {
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::Int64, true),
+            Field::new("email", DataType::Utf8, true),
+        ]);
+
+        let sql = duplicates_query(&schema, "users", &["email".to_string()]).unwrap();
+        assert!(sql.starts_with("SELECT \"email\", COUNT(*) AS \"duplicate_count\" FROM users"));
+        assert!(sql.contains("GROUP BY \"email\" HAVING COUNT(*) > 1"));
+
+        let sql = duplicates_query(&schema, "users", &[]).unwrap();
+        assert!(sql.contains("GROUP BY \"id\", \"email\""));
+
+        assert!(duplicates_query(&schema, "users", &["name".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_schema_table() {
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("name", DataType::Utf8, true),
+        ]);
+        let table = schema_table(&schema).unwrap();
+        assert_eq!(table.num_rows(), 2);
+        assert_eq!(table.num_columns(), 3);
+    }
+
+    #[test]
+    fn test_require_column_lists_available() {
+        let schema = Schema::new(vec![Field::new("age", DataType::Int64, true)]);
+        let err = require_column(&schema, "salary").unwrap_err();
+        assert!(err.to_string().contains("Available columns: age"));
+    }
+}
@@ -0,0 +1,145 @@
+//! Default options from a `sql-stream.toml` config file
+//!
+//! Options that are passed on every run (a delimiter, an output format,
+//! resource limits) can be kept in a TOML file instead:
+//!
+//! ```toml
+//! format = "csv"
+//! delimiter = ";"
+//! threads = 4
+//! memory-limit = "2G"
+//! ```
+//!
+//! The file is looked up in the current directory, then in
+//! `$XDG_CONFIG_HOME` (`~/.config` when unset). Its values only fill in
+//! options the command line leaves unset; the merge itself happens in
+//! [`CliArgs::apply_config`](crate::CliArgs::apply_config).
+
+use crate::error::{Result, SqlStreamError};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// Name of the config file looked up in each candidate directory
+pub const CONFIG_FILE_NAME: &str = "sql-stream.toml";
+
+/// Default options read from a config file
+///
+/// Every field is optional; values are kept as written and validated when
+/// they are applied, so errors can name the option.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Settings {
+    /// Print format: `table`, `csv`, `json` or `ndjson`
+    pub format: Option<String>,
+    /// CSV field delimiter, a single character or `\t`
+    pub delimiter: Option<String>,
+    /// Number of partitions (threads) queries run with
+    pub threads: Option<usize>,
+    /// Query memory budget such as `512M` or `2G`
+    pub memory_limit: Option<String>,
+}
+
+impl Settings {
+    /// Parse settings from the TOML text of `file`
+    ///
+    /// # Errors
+    ///
+    /// Returns [`SqlStreamError::Config`] if the text is not valid TOML, has
+    /// an unknown key or a value of the wrong type
+    pub fn parse(text: &str, file: &Path) -> Result<Self> {
+        toml::from_str(text).map_err(|e| SqlStreamError::Config {
+            file: file.to_path_buf(),
+            detail: e.message().to_string(),
+        })
+    }
+
+    /// Read settings from a config file
+    ///
+    /// # Errors
+    ///
+    /// Returns an IO error if the file cannot be read, or the errors of
+    /// [`parse`](Self::parse)
+    pub fn load(file: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(file)?;
+        Self::parse(&text, file)
+    }
+}
+
+/// The first existing config file among the default locations
+pub fn find_config() -> Option<PathBuf> {
+    let cwd = std::env::current_dir().ok();
+    let xdg = std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from);
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    config_candidates(cwd.as_deref(), xdg.as_deref(), home.as_deref())
+        .into_iter()
+        .find(|path| path.is_file())
+}
+
+/// Config file locations in lookup order
+///
+/// The current directory comes first, then `$XDG_CONFIG_HOME`, falling back
+/// to `~/.config` as the XDG spec says when the variable is unset or empty.
+fn config_candidates(cwd: Option<&Path>, xdg: Option<&Path>, home: Option<&Path>) -> Vec<PathBuf> {
+    let config_home = match xdg.filter(|dir| !dir.as_os_str().is_empty()) {
+        Some(dir) => Some(dir.to_path_buf()),
+        None => home.map(|home| home.join(".config")),
+    };
+    cwd.map(Path::to_path_buf)
+        .into_iter()
+        .chain(config_home)
+        .map(|dir| dir.join(CONFIG_FILE_NAME))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let file = Path::new(CONFIG_FILE_NAME);
+        let settings = Settings::parse(
+            "format = \"csv\"\ndelimiter = \";\"\nthreads = 4\nmemory-limit = \"2G\"\n",
+            file,
+        )
+        .unwrap();
+        assert_eq!(
+            settings,
+            Settings {
+                format: Some("csv".to_string()),
+                delimiter: Some(";".to_string()),
+                threads: Some(4),
+                memory_limit: Some("2G".to_string()),
+            }
+        );
+
+        assert_eq!(Settings::parse("", file).unwrap(), Settings::default());
+        assert!(matches!(
+            Settings::parse("colour = true", file),
+            Err(SqlStreamError::Config { .. })
+        ));
+        assert!(Settings::parse("threads = \"four\"", file).is_err());
+    }
+
+    #[test]
+    fn test_config_candidates() {
+        let candidates = config_candidates(
+            Some(Path::new("/work")),
+            Some(Path::new("/xdg")),
+            Some(Path::new("/home/user")),
+        );
+        assert_eq!(
+            candidates,
+            [
+                PathBuf::from("/work/sql-stream.toml"),
+                PathBuf::from("/xdg/sql-stream.toml")
+            ]
+        );
+
+        let candidates = config_candidates(None, Some(Path::new("")), Some(Path::new("/home/u")));
+        assert_eq!(
+            candidates,
+            [PathBuf::from("/home/u/.config/sql-stream.toml")]
+        );
+    }
+}
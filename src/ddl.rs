@@ -0,0 +1,175 @@
+//! CREATE TABLE generation from inferred schemas
+//!
+//! Maps an Arrow schema onto the column types of a target database so the
+//! inferred structure of a file can be used to bootstrap a bulk load.
+
+use clap::ValueEnum;
+use datafusion::arrow::datatypes::{DataType, Schema};
+
+/// SQL dialects supported for DDL generation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SqlDialect {
+    /// PostgreSQL
+    Postgres,
+    /// MySQL / MariaDB
+    Mysql,
+    /// SQLite
+    Sqlite,
+}
+
+impl SqlDialect {
+    /// Quote an identifier using the dialect's quoting style
+    pub(crate) fn quote(&self, name: &str) -> String {
+        match self {
+            Self::Mysql => format!("`{}`", name.replace('`', "``")),
+            Self::Postgres | Self::Sqlite => format!("\"{}\"", name.replace('"', "\"\"")),
+        }
+    }
+
+    /// Map an Arrow data type to the dialect's column type
+    pub fn column_type(&self, data_type: &DataType) -> String {
+        match self {
+            Self::Postgres => postgres_type(data_type),
+            Self::Mysql => mysql_type(data_type),
+            Self::Sqlite => sqlite_type(data_type).to_string(),
+        }
+    }
+}
+
+/// Build a `CREATE TABLE` statement for `schema` in the given dialect
+///
+/// Non-nullable fields are declared `NOT NULL`.
+pub fn create_table(schema: &Schema, table_name: &str, dialect: SqlDialect) -> String {
+    let columns = schema
+        .fields()
+        .iter()
+        .map(|field| {
+            let not_null = if field.is_nullable() { "" } else { " NOT NULL" };
+            format!(
+                "    {} {}{}",
+                dialect.quote(field.name()),
+                dialect.column_type(field.data_type()),
+                not_null
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",\n");
+
+    format!(
+        "CREATE TABLE {} (\n{}\n);",
+        dialect.quote(table_name),
+        columns
+    )
+}
+
+fn postgres_type(data_type: &DataType) -> String {
+    match data_type {
+        DataType::Boolean => "BOOLEAN".to_string(),
+        DataType::Int8 | DataType::Int16 | DataType::UInt8 => "SMALLINT".to_string(),
+        DataType::Int32 | DataType::UInt16 => "INTEGER".to_string(),
+        DataType::Int64 | DataType::UInt32 => "BIGINT".to_string(),
+        DataType::UInt64 => "NUMERIC(20)".to_string(),
+        DataType::Float16 | DataType::Float32 => "REAL".to_string(),
+        DataType::Float64 => "DOUBLE PRECISION".to_string(),
+        DataType::Decimal128(p, s) | DataType::Decimal256(p, s) => format!("NUMERIC({p},{s})"),
+        DataType::Binary | DataType::LargeBinary | DataType::FixedSizeBinary(_) => {
+            "BYTEA".to_string()
+        }
+        DataType::Date32 | DataType::Date64 => "DATE".to_string(),
+        DataType::Timestamp(_, None) => "TIMESTAMP".to_string(),
+        DataType::Timestamp(_, Some(_)) => "TIMESTAMPTZ".to_string(),
+        DataType::Time32(_) | DataType::Time64(_) => "TIME".to_string(),
+        DataType::Interval(_) | DataType::Duration(_) => "INTERVAL".to_string(),
+        DataType::List(_)
+        | DataType::LargeList(_)
+        | DataType::FixedSizeList(_, _)
+        | DataType::Struct(_)
+        | DataType::Map(_, _) => "JSONB".to_string(),
+        DataType::Dictionary(_, value) => postgres_type(value),
+        _ => "TEXT".to_string(),
+    }
+}
+
+fn mysql_type(data_type: &DataType) -> String {
+    match data_type {
+        DataType::Boolean => "BOOLEAN".to_string(),
+        DataType::Int8 => "TINYINT".to_string(),
+        DataType::Int16 => "SMALLINT".to_string(),
+        DataType::Int32 => "INT".to_string(),
+        DataType::Int64 => "BIGINT".to_string(),
+        DataType::UInt8 => "TINYINT UNSIGNED".to_string(),
+        DataType::UInt16 => "SMALLINT UNSIGNED".to_string(),
+        DataType::UInt32 => "INT UNSIGNED".to_string(),
+        DataType::UInt64 => "BIGINT UNSIGNED".to_string(),
+        DataType::Float16 | DataType::Float32 => "FLOAT".to_string(),
+        DataType::Float64 => "DOUBLE".to_string(),
+        DataType::Decimal128(p, s) | DataType::Decimal256(p, s) => format!("DECIMAL({p},{s})"),
+        DataType::Binary | DataType::LargeBinary | DataType::FixedSizeBinary(_) => {
+            "BLOB".to_string()
+        }
+        DataType::Date32 | DataType::Date64 => "DATE".to_string(),
+        DataType::Timestamp(_, None) => "DATETIME".to_string(),
+        DataType::Timestamp(_, Some(_)) => "TIMESTAMP".to_string(),
+        DataType::Time32(_) | DataType::Time64(_) => "TIME".to_string(),
+        DataType::List(_)
+        | DataType::LargeList(_)
+        | DataType::FixedSizeList(_, _)
+        | DataType::Struct(_)
+        | DataType::Map(_, _) => "JSON".to_string(),
+        DataType::Dictionary(_, value) => mysql_type(value),
+        _ => "TEXT".to_string(),
+    }
+}
+
+fn sqlite_type(data_type: &DataType) -> &'static str {
+    match data_type {
+        DataType::Boolean
+        | DataType::Int8
+        | DataType::Int16
+        | DataType::Int32
+        | DataType::Int64
+        | DataType::UInt8
+        | DataType::UInt16
+        | DataType::UInt32
+        | DataType::UInt64 => "INTEGER",
+        DataType::Float16 | DataType::Float32 | DataType::Float64 => "REAL",
+        DataType::Decimal128(_, _) | DataType::Decimal256(_, _) => "NUMERIC",
+        DataType::Binary | DataType::LargeBinary | DataType::FixedSizeBinary(_) => "BLOB",
+        DataType::Dictionary(_, value) => sqlite_type(value),
+        _ => "TEXT",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::datatypes::Field;
+
+    fn schema() -> Schema {
+        Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("name", DataType::Utf8, true),
+            Field::new("salary", DataType::Float64, true),
+        ])
+    }
+
+    #[test]
+    fn test_postgres_ddl() {
+        let ddl = create_table(&schema(), "employees", SqlDialect::Postgres);
+        assert_eq!(
+            ddl,
+            "CREATE TABLE \"employees\" (\n    \"id\" BIGINT NOT NULL,\n    \"name\" TEXT,\n    \"salary\" DOUBLE PRECISION\n);"
+        );
+    }
+
+    #[test]
+    fn test_mysql_and_sqlite_types() {
+        let mysql = create_table(&schema(), "employees", SqlDialect::Mysql);
+        assert!(mysql.starts_with("CREATE TABLE `employees`"));
+        assert!(mysql.contains("`salary` DOUBLE"));
+
+        let sqlite = create_table(&schema(), "employees", SqlDialect::Sqlite);
+        assert!(sqlite.contains("\"id\" INTEGER NOT NULL"));
+        assert!(sqlite.contains("\"salary\" REAL"));
+    }
+}
@@ -4,11 +4,507 @@
 //! with support for registering CSV and JSON files as tables and executing SQL queries
 //! with streaming result processing.
 
+use crate::analysis::require_column;
 use crate::error::{Result, SqlStreamError};
-use datafusion::arrow::util::pretty::print_batches;
+use crate::output::{
+    self, CompressedWriter, IntervalFlush, OutputCompression, OutputFormat, PrintFormat,
+    QueryResult, QueryTimings, StreamCounter, StreamStats, TableStyle,
+};
+use crate::preprocess::{self, JsonMode};
+use crate::sql::{self, quote_ident};
+use crate::transform::{self, NumericLocale};
+use clap::ValueEnum;
+use datafusion::arrow::array::AsArray;
+use datafusion::arrow::compute::can_cast_types;
+use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef, UInt64Type};
+use datafusion::arrow::error::ArrowError;
+use datafusion::arrow::ipc::reader::{FileReader, StreamReader};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::common::parsers::CompressionTypeVariant;
+use datafusion::config::{CsvOptions, JsonOptions, TableParquetOptions};
+use datafusion::dataframe::DataFrameWriteOptions;
+use datafusion::datasource::file_format::csv::CsvFormat;
+use datafusion::datasource::file_format::json::JsonFormat;
+use datafusion::datasource::file_format::parquet::ParquetFormat;
+use datafusion::datasource::file_format::FileFormat;
+use datafusion::datasource::listing::{
+    ListingOptions, ListingTable, ListingTableConfig, ListingTableUrl,
+};
+#[cfg(feature = "avro")]
+use datafusion::datasource::physical_plan::AvroExec;
+use datafusion::datasource::physical_plan::{CsvExec, NdJsonExec, ParquetExec};
+use datafusion::datasource::{MemTable, TableProvider};
+use datafusion::error::DataFusionError;
+use datafusion::execution::disk_manager::DiskManagerConfig;
+use datafusion::execution::memory_pool::{
+    FairSpillPool, MemoryConsumer, MemoryPool, MemoryReservation,
+};
+use datafusion::execution::runtime_env::RuntimeEnvBuilder;
+use datafusion::execution::SendableRecordBatchStream;
+use datafusion::logical_expr::ScalarUDF;
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use datafusion::physical_plan::{self, ExecutionPlan};
 use datafusion::prelude::*;
-use std::path::Path;
-use tracing::{debug, info, instrument};
+use futures::future::try_join_all;
+use futures::{StreamExt, TryStreamExt};
+use object_store::http::HttpBuilder;
+use object_store::{ClientOptions, ObjectStore, RetryConfig};
+use regex::Regex;
+use std::collections::{HashSet, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, MutexGuard, PoisonError};
+use std::task::Poll;
+use std::time::{Duration, Instant};
+use tempfile::NamedTempFile;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, instrument, warn};
+use url::Url;
+
+/// Options controlling how data files are read when they are registered
+#[derive(Debug, Clone, Default)]
+pub struct ReadOptions {
+    /// Regex identifying the CSV header row; every line before it is skipped
+    pub header_pattern: Option<String>,
+    /// CSV field delimiter; defaults to a comma (a tab for `.tsv` files)
+    pub delimiter: Option<u8>,
+    /// CSV quote character; defaults to a double quote
+    pub quote: Option<u8>,
+    /// The CSV file has no header row; columns are named `column_1`, `column_2`, ...
+    pub no_header: bool,
+    /// Rows scanned to infer CSV/JSON column types; `Some(0)` scans the whole
+    /// file and `None` keeps DataFusion's default
+    pub infer_rows: Option<usize>,
+    /// Detect the CSV delimiter from the file contents when none is given
+    pub sniff_delimiter: bool,
+    /// Regex matching CSV fields to read as NULL, such as `NA|N/A`; it must
+    /// match the whole field, and empty fields are always NULL
+    pub null_regex: Option<String>,
+    /// Schema of CSV and JSON files, replacing inference (see [`crate::schema`]);
+    /// CSV files must have exactly its number of columns, and its names replace
+    /// the header's
+    pub schema: Option<SchemaRef>,
+    /// CSV columns read as text whatever type inference picks, so values like
+    /// ZIP codes keep their leading zeros; ignored when a schema is declared
+    pub text_columns: Vec<String>,
+    /// Whether `.json` files hold NDJSON or a single array of objects;
+    /// detected from the first character by default
+    pub json_mode: JsonMode,
+    /// Worksheet of `.xlsx` files to read; the first one when unset
+    pub sheet: Option<String>,
+    /// Times opening a local file is retried after a transient error, such as
+    /// the file being locked by the process writing it, and times a failed
+    /// request for a remote file is retried; 3 when unset
+    pub open_retries: Option<u32>,
+    /// How long connecting to the host of a remote file may take; 5 seconds when unset
+    pub connect_timeout: Option<Duration>,
+    /// How long each request for a remote file may take, from connecting to
+    /// reading the whole response; 30 seconds when unset
+    pub request_timeout: Option<Duration>,
+    /// Replace a table already registered under the same name, such as to
+    /// reload a file that changed, instead of failing
+    pub replace: bool,
+}
+
+impl ReadOptions {
+    /// The schema inference row limit to pass to DataFusion, if any
+    fn infer_rows(&self) -> Option<usize> {
+        self.infer_rows
+            .map(|rows| if rows == 0 { usize::MAX } else { rows })
+    }
+
+    /// The NULL regex to pass to DataFusion, anchored to whole fields
+    fn null_regex(&self) -> Option<String> {
+        self.null_regex
+            .as_ref()
+            .map(|regex| format!("^(?:{})?$", regex))
+    }
+
+    /// How many times to retry opening a file
+    fn open_retries(&self) -> u32 {
+        self.open_retries.unwrap_or(DEFAULT_OPEN_RETRIES)
+    }
+
+    /// Connect timeout of the remote file client
+    fn connect_timeout(&self) -> Duration {
+        self.connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT)
+    }
+
+    /// Request timeout of the remote file client
+    fn request_timeout(&self) -> Duration {
+        self.request_timeout.unwrap_or(DEFAULT_REQUEST_TIMEOUT)
+    }
+}
+
+/// Retries of a file that fails to open when [`ReadOptions::open_retries`] is unset
+const DEFAULT_OPEN_RETRIES: u32 = 3;
+
+/// Connect timeout for remote files when [`ReadOptions::connect_timeout`] is unset
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Request timeout for remote files when [`ReadOptions::request_timeout`] is unset
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Resource limits and SQL session options for a [`QueryEngine`]
+#[derive(Debug, Clone, Default)]
+pub struct EngineConfig {
+    /// Maximum bytes of memory queries may use before spilling; unlimited when unset
+    pub memory_limit: Option<usize>,
+    /// Directory for spill files; the OS temporary directory when unset
+    pub spill_dir: Option<PathBuf>,
+    /// Number of partitions queries run with; DataFusion's default (the CPU count) when unset or 0
+    pub target_partitions: Option<usize>,
+    /// Expose `information_schema.tables` and `information_schema.columns`
+    pub information_schema: bool,
+    /// Keep the case of unquoted identifiers instead of folding them to lower case
+    pub preserve_case: bool,
+    /// Most query results kept by [`QueryEngine::execute_cached`]; results are
+    /// not cached when unset or 0
+    pub cache_entries: Option<usize>,
+}
+
+/// Fluent builder for a [`QueryEngine`], filling in an [`EngineConfig`]
+///
+/// Settings left alone keep their defaults, as with [`QueryEngine::new`].
+///
+/// # Example
+///
+/// ```
+/// use sql_stream::{EngineBuilder, SqlStreamError};
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), SqlStreamError> {
+/// let engine = EngineBuilder::new()
+///     .memory_limit(512 * 1024 * 1024)
+///     .target_partitions(2)
+///     .with_information_schema()
+///     .build()?;
+///
+/// let batches = engine
+///     .collect_query("SELECT COUNT(*) FROM information_schema.tables")
+///     .await?;
+/// assert_eq!(batches[0].num_rows(), 1);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct EngineBuilder {
+    config: EngineConfig,
+}
+
+impl EngineBuilder {
+    /// Start from the default configuration
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cap the memory queries may use, in bytes (see [`EngineConfig::memory_limit`])
+    pub fn memory_limit(mut self, bytes: usize) -> Self {
+        self.config.memory_limit = Some(bytes);
+        self
+    }
+
+    /// Directory to spill to under a memory limit (see [`EngineConfig::spill_dir`])
+    pub fn spill_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config.spill_dir = Some(dir.into());
+        self
+    }
+
+    /// Number of partitions, and so threads, each query runs with; 0 keeps the default
+    pub fn target_partitions(mut self, partitions: usize) -> Self {
+        self.config.target_partitions = Some(partitions);
+        self
+    }
+
+    /// Expose the `information_schema` tables
+    pub fn with_information_schema(mut self) -> Self {
+        self.config.information_schema = true;
+        self
+    }
+
+    /// Match unquoted identifiers case-sensitively
+    pub fn preserve_case(mut self) -> Self {
+        self.config.preserve_case = true;
+        self
+    }
+
+    /// Keep up to `entries` query results for [`QueryEngine::execute_cached`]
+    pub fn cache_entries(mut self, entries: usize) -> Self {
+        self.config.cache_entries = Some(entries);
+        self
+    }
+
+    /// The configuration built so far
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    /// Create the engine
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the runtime environment cannot be created
+    pub fn build(self) -> Result<QueryEngine> {
+        QueryEngine::with_config(&self.config)
+    }
+}
+
+impl From<EngineConfig> for EngineBuilder {
+    fn from(config: EngineConfig) -> Self {
+        Self { config }
+    }
+}
+
+/// Hits and misses of the query result cache (see [`QueryEngine::execute_cached`])
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Queries answered from the cache
+    pub hits: usize,
+    /// Queries run and added to the cache
+    pub misses: usize,
+    /// Results currently held
+    pub entries: usize,
+}
+
+/// Peak memory use and spilling of the queries run so far (see [`QueryEngine::memory_stats`])
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// The memory limit queries ran under; `None` when memory is unlimited,
+    /// and so untracked
+    pub limit: Option<usize>,
+    /// Most bytes reserved from the memory pool at once
+    pub peak: usize,
+    /// Bytes written to spill files by sorts, aggregations and joins
+    pub spilled_bytes: usize,
+    /// Times an operator spilled its state to disk
+    pub spills: usize,
+}
+
+impl MemoryStats {
+    /// A one-line summary such as
+    /// `peak memory 12.0 MiB of 64.0 MiB (19%); no spilling occurred`
+    pub fn summary(&self) -> String {
+        let mib = |bytes: usize| format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0));
+        let usage = match self.limit {
+            Some(limit) if limit > 0 => format!(
+                "peak memory {} of {} ({:.0}%)",
+                mib(self.peak),
+                mib(limit),
+                self.peak as f64 * 100.0 / limit as f64
+            ),
+            _ => format!("peak memory {}", mib(self.peak)),
+        };
+        let spilling = if self.spills == 0 {
+            "no spilling occurred".to_string()
+        } else {
+            format!(
+                "spilled {} in {} spill{}",
+                mib(self.spilled_bytes),
+                self.spills,
+                if self.spills == 1 { "" } else { "s" }
+            )
+        };
+        format!("{}; {}", usage, spilling)
+    }
+}
+
+/// A memory pool that remembers the most memory reserved from it at once
+#[derive(Debug)]
+struct PeakTrackingPool {
+    inner: Arc<dyn MemoryPool>,
+    peak: AtomicUsize,
+}
+
+impl PeakTrackingPool {
+    fn new(inner: Arc<dyn MemoryPool>) -> Self {
+        Self {
+            inner,
+            peak: AtomicUsize::new(0),
+        }
+    }
+
+    fn record_peak(&self) {
+        self.peak
+            .fetch_max(self.inner.reserved(), Ordering::Relaxed);
+    }
+}
+
+impl MemoryPool for PeakTrackingPool {
+    fn register(&self, consumer: &MemoryConsumer) {
+        self.inner.register(consumer);
+    }
+
+    fn unregister(&self, consumer: &MemoryConsumer) {
+        self.inner.unregister(consumer);
+    }
+
+    fn grow(&self, reservation: &MemoryReservation, additional: usize) {
+        self.inner.grow(reservation, additional);
+        self.record_peak();
+    }
+
+    fn shrink(&self, reservation: &MemoryReservation, shrink: usize) {
+        self.inner.shrink(reservation, shrink);
+    }
+
+    fn try_grow(
+        &self,
+        reservation: &MemoryReservation,
+        additional: usize,
+    ) -> datafusion::error::Result<()> {
+        self.inner.try_grow(reservation, additional)?;
+        self.record_peak();
+        Ok(())
+    }
+
+    fn reserved(&self) -> usize {
+        self.inner.reserved()
+    }
+}
+
+/// Spills reported by the plans of result streams that have ended
+#[derive(Debug, Default)]
+struct SpillTotals {
+    bytes: AtomicUsize,
+    count: AtomicUsize,
+}
+
+impl SpillTotals {
+    /// Add the spills of every operator in `plan`
+    fn record(&self, plan: &dyn ExecutionPlan) {
+        if let Some(metrics) = plan.metrics() {
+            self.bytes
+                .fetch_add(metrics.spilled_bytes().unwrap_or(0), Ordering::Relaxed);
+            self.count
+                .fetch_add(metrics.spill_count().unwrap_or(0), Ordering::Relaxed);
+        }
+        for child in plan.children() {
+            self.record(child.as_ref());
+        }
+    }
+}
+
+/// How [`QueryEngine::execute_cached_status`] answered a statement
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CacheStatus {
+    /// The result kept under `key` was reused; it was computed `age` ago
+    Hit { key: String, age: Duration },
+    /// The query ran and its result was kept under `key`
+    Miss { key: String },
+    /// The statement ran without the cache: none is configured, or it is not a query
+    Bypassed,
+}
+
+/// Collected query results keyed by normalized SQL, least recently used first
+#[derive(Debug, Default)]
+struct ResultCache {
+    capacity: usize,
+    entries: VecDeque<(String, SchemaRef, Vec<RecordBatch>, Instant)>,
+    hits: usize,
+    misses: usize,
+}
+
+impl ResultCache {
+    /// The cached result of `key` and its age, marking it as the most recently used
+    fn get(&mut self, key: &str) -> Option<(SchemaRef, Vec<RecordBatch>, Duration)> {
+        let index = self.entries.iter().position(|(cached, ..)| cached == key)?;
+        let entry = self.entries.remove(index)?;
+        let result = (Arc::clone(&entry.1), entry.2.clone(), entry.3.elapsed());
+        self.entries.push_back(entry);
+        self.hits += 1;
+        Some(result)
+    }
+
+    /// Add a result, evicting the least recently used one when full
+    fn insert(&mut self, key: String, schema: SchemaRef, batches: Vec<RecordBatch>) {
+        self.misses += 1;
+        self.entries.retain(|(cached, ..)| *cached != key);
+        if self.entries.len() >= self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries
+            .push_back((key, schema, batches, Instant::now()));
+    }
+}
+
+/// Options controlling how results are written to files
+#[derive(Debug, Clone, Default)]
+pub struct WriteOptions {
+    /// Output format; detected from the file extension when unset
+    pub format: Option<OutputFormat>,
+    /// Minimum time between flushes of the file (see [`output::IntervalFlush`])
+    pub flush_interval: Duration,
+    /// Replace the file if it already exists
+    pub overwrite: bool,
+    /// Text written for NULL values in CSV output; empty when unset
+    pub null_value: Option<String>,
+    /// Cell width and color of printed text tables
+    pub table_style: TableStyle,
+    /// Decimal places floating-point values are rounded to in table and CSV
+    /// output; JSON and Parquet always keep the exact value
+    pub precision: Option<usize>,
+    /// Most rows printed by [`QueryEngine::print_results_with`]; the rest are
+    /// dropped and [`StreamStats::truncated`] is set. Files are never capped.
+    pub max_rows: Option<usize>,
+    /// Indent JSON and NDJSON output, one object per row (see
+    /// [`output::write_pretty_json`]); other formats ignore it
+    pub pretty: bool,
+    /// Compression of the written file; when unset, a `.gz` or `.zst`
+    /// extension decides and Parquet keeps its usual codec
+    pub compression: Option<OutputCompression>,
+    /// Stop reading results at this instant and keep the rows produced so
+    /// far, setting [`StreamStats::timed_out`]; applies to printed results and
+    /// [`QueryEngine::write_results_with`]
+    pub deadline: Option<Instant>,
+}
+
+impl WriteOptions {
+    /// The compression to write `path` with: explicit, or named by its extension
+    fn compression_for(&self, path: &Path) -> Option<OutputCompression> {
+        self.compression
+            .or_else(|| OutputCompression::from_path(path))
+    }
+
+    /// Whether results in `format` are written with [`output::write_pretty_json`]
+    fn pretty_json(&self, format: OutputFormat) -> bool {
+        self.pretty && matches!(format, OutputFormat::Json | OutputFormat::Ndjson)
+    }
+
+    /// Round floats in a stream bound for a text format (see [`output::round_floats`])
+    fn round_text(&self, stream: SendableRecordBatchStream) -> SendableRecordBatchStream {
+        match self.precision {
+            Some(precision) => output::round_floats(stream, precision),
+            None => stream,
+        }
+    }
+}
+
+/// Formats that can be read from an input without a file extension, such as stdin
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum InputFormat {
+    /// Comma-separated (or `delimiter`-separated) values with a header row
+    Csv,
+    /// Tab-separated values with a header row
+    Tsv,
+    /// Newline-delimited JSON
+    Json,
+    /// Apache Parquet
+    Parquet,
+}
+
+impl InputFormat {
+    /// File extension that selects this format in [`QueryEngine::register_file`]
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Tsv => "tsv",
+            Self::Json => "json",
+            Self::Parquet => "parquet",
+        }
+    }
+}
 
 /// High-performance SQL query engine powered by Apache DataFusion
 ///
@@ -17,73 +513,487 @@ use tracing::{debug, info, instrument};
 /// zero-copy streaming.
 pub struct QueryEngine {
     ctx: SessionContext,
+    /// Preprocessed copies of input files, kept alive for the engine's lifetime
+    ///
+    /// Behind a mutex so files being registered concurrently can add to it.
+    temp_files: Mutex<Vec<NamedTempFile>>,
+    /// Totals of the result rows and batches streamed so far
+    counter: Arc<StreamCounter>,
+    /// Results reused by [`execute_cached`](Self::execute_cached)
+    cache: Mutex<ResultCache>,
+    /// The memory pool, when a memory limit is set, and its limit
+    memory_pool: Option<(Arc<PeakTrackingPool>, usize)>,
+    /// Spills of the result streams that have ended
+    spills: Arc<SpillTotals>,
 }
 
 impl QueryEngine {
     /// Create a new query engine with default configuration
     ///
+    /// Use [`builder`](Self::builder) to change resource limits or session options.
+    ///
     /// # Errors
     ///
     /// Returns an error if the session context cannot be initialized
     #[instrument]
     pub fn new() -> Result<Self> {
+        Self::with_config(&EngineConfig::default())
+    }
+
+    /// Start configuring a query engine (see [`EngineBuilder`])
+    pub fn builder() -> EngineBuilder {
+        EngineBuilder::new()
+    }
+
+    /// Create a new query engine with custom resource limits and session options
+    ///
+    /// [`target_partitions`](EngineConfig::target_partitions) sets how many
+    /// partitions, and so threads, each query is split across.
+    ///
+    /// [`information_schema`](EngineConfig::information_schema) lets queries
+    /// list the registered tables and their columns, and
+    /// [`preserve_case`](EngineConfig::preserve_case) makes unquoted column
+    /// names such as `Name` match mixed-case headers. Table names are still
+    /// registered in lower case either way.
+    ///
+    /// With a [`memory_limit`](EngineConfig::memory_limit), memory-hungry
+    /// operators (sorts, aggregations, joins) share the budget through a
+    /// `FairSpillPool` and spill intermediate state to disk once their share
+    /// runs out. Spill files go to [`spill_dir`](EngineConfig::spill_dir),
+    /// or the OS temporary directory. The pool's peak usage and the spills are
+    /// reported by [`memory_stats`](Self::memory_stats).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the runtime environment cannot be created
+    #[instrument]
+    pub fn with_config(config: &EngineConfig) -> Result<Self> {
         info!("Initializing query engine");
-        let ctx = SessionContext::new();
-        Ok(Self { ctx })
+
+        let mut runtime = RuntimeEnvBuilder::new().with_disk_manager(match &config.spill_dir {
+            Some(dir) => DiskManagerConfig::NewSpecified(vec![dir.clone()]),
+            None => DiskManagerConfig::NewOs,
+        });
+        let memory_pool = config.memory_limit.map(|limit| {
+            info!("Limiting query memory to {} bytes", limit);
+            let pool = Arc::new(PeakTrackingPool::new(Arc::new(FairSpillPool::new(limit))));
+            (pool, limit)
+        });
+        if let Some((pool, _)) = &memory_pool {
+            runtime = runtime.with_memory_pool(Arc::clone(pool) as Arc<dyn MemoryPool>);
+        }
+
+        let mut session = SessionConfig::new();
+        if let Some(partitions) = config.target_partitions.filter(|&n| n > 0) {
+            info!("Running queries with {} partitions", partitions);
+            session = session.with_target_partitions(partitions);
+        }
+        if config.information_schema {
+            session = session.with_information_schema(true);
+        }
+        if config.preserve_case {
+            debug!("Preserving the case of unquoted identifiers");
+            session.options_mut().sql_parser.enable_ident_normalization = false;
+        }
+
+        let ctx = SessionContext::new_with_config_rt(session, runtime.build_arc()?);
+        Ok(Self {
+            ctx,
+            temp_files: Mutex::default(),
+            counter: Arc::default(),
+            cache: Mutex::new(ResultCache {
+                capacity: config.cache_entries.unwrap_or(0),
+                ..Default::default()
+            }),
+            memory_pool,
+            spills: Arc::default(),
+        })
     }
 
-    /// Register a CSV or JSON file as a table in the query engine
+    /// Register a CSV, JSON, Parquet or Arrow IPC file as a table in the query engine
     ///
     /// The file format is automatically detected from the file extension.
-    /// Supported formats: `.csv`, `.json`
+    /// Supported formats: `.csv` (or tab-separated `.tsv`), `.json`, `.parquet` (or `.pqt`),
+    /// Arrow IPC as `.arrow`, `.arrows` or `.feather`, ORC as `.orc` with
+    /// the `orc` feature, Avro container files as `.avro` with the `avro`
+    /// feature, and Excel workbooks as `.xlsx` with the `excel` feature. CSV and JSON files may be gzip (`.csv.gz`) or
+    /// zstd (`.json.zst`) compressed. An `http://` or `https://` URL, or an
+    /// `s3://bucket/key` path with the `s3` feature, reads a remote CSV, JSON
+    /// or Parquet file in place. Arrow IPC files carry their own schema and are
+    /// loaded into memory as-is, without type inference.
     ///
     /// # Arguments
     ///
     /// * `file_path` - Path to the data file
-    /// * `table_name` - Name to use for the table in SQL queries
+    /// * `table_name` - Name to use for the table in SQL queries. Unquoted SQL
+    ///   identifiers are case-insensitive, so a name like `MyData` is
+    ///   registered as `mydata` (see [`sql::normalize_table_name`]); a warning
+    ///   is logged when the name differs from how SQL must refer to it, or
+    ///   must be quoted because it is not a plain identifier or is a reserved
+    ///   word such as `order`. Blank names are rejected.
     ///
     /// # Errors
     ///
     /// Returns an error if:
-    /// - The file does not exist
+    /// - The file does not exist, or a pattern matches no files
+    /// - The file cannot be opened: access is denied, or it stays locked by
+    ///   another process ([`SqlStreamError::FileAccess`])
+    /// - A remote file cannot be fetched ([`SqlStreamError::RemoteFetch`] for
+    ///   HTTP(S), [`SqlStreamError::ObjectStore`] for S3)
     /// - The file format is unsupported
-    /// - Schema inference fails
-    /// - Table registration fails
+    /// - The files of a multi-file table differ in format or schema
+    /// - Schema inference fails; malformed data is reported as
+    ///   [`SqlStreamError::ParseError`] naming the file
+    /// - The table name is blank or already registered (see
+    ///   [`ReadOptions::replace`]), or table registration fails
     #[instrument(skip(self))]
     pub async fn register_file(&mut self, file_path: &str, table_name: &str) -> Result<()> {
+        self.register_file_with(file_path, table_name, &ReadOptions::default())
+            .await
+    }
+
+    /// Register a CSV or JSON file as a table using custom read options
+    ///
+    /// Behaves like [`register_file`](Self::register_file), applying `options`
+    /// to control how the file is read.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`register_file`](Self::register_file), plus
+    /// [`SqlStreamError::HeaderNotFound`] if a header pattern matches no line
+    #[instrument(skip(self))]
+    pub async fn register_file_with(
+        &mut self,
+        file_path: &str,
+        table_name: &str,
+        options: &ReadOptions,
+    ) -> Result<()> {
+        self.register_source(file_path, table_name, options).await
+    }
+
+    /// Register several files at once, inferring their schemas concurrently
+    ///
+    /// Each `(file_path, table_name)` pair is registered exactly as
+    /// [`register_file_with`](Self::register_file_with) would, but the files
+    /// are read and inferred in parallel rather than one after another, which
+    /// matters when there are many of them or they are remote. Table names are
+    /// checked for clashes before anything is registered, so the resulting
+    /// tables do not depend on which file finishes first.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`SqlStreamError::TableRegistration`] if two pairs share a table
+    /// name (compared as SQL does, case-insensitively), or the first error any
+    /// file fails with. Files registered before the failure stay registered.
+    #[instrument(skip(self, tables))]
+    pub async fn register_all(
+        &mut self,
+        tables: &[(&str, &str)],
+        options: &ReadOptions,
+    ) -> Result<()> {
+        for (i, (_, table_name)) in tables.iter().enumerate() {
+            let normalized = sql::normalize_table_name(table_name);
+            if tables[..i]
+                .iter()
+                .any(|(_, other)| sql::normalize_table_name(other) == normalized)
+            {
+                return Err(SqlStreamError::TableRegistration(
+                    table_name.to_string(),
+                    "table name is used more than once".to_string(),
+                ));
+            }
+        }
+
+        debug!("Registering {} files concurrently", tables.len());
+        let this = &*self;
+        try_join_all(
+            tables.iter().map(|(file_path, table_name)| {
+                this.register_source(file_path, table_name, options)
+            }),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Register each file of a directory or glob, or each worksheet of an
+    /// Excel workbook, as its own table named after it
+    ///
+    /// Files are named after their stem (`orders.csv.gz` becomes `orders`) and
+    /// worksheets after the sheet, lower-cased and with characters needing
+    /// quotes replaced as [`sql::suggest_table_name`] does. A name already
+    /// taken, by an earlier file or a registered table, gets a `_2`, `_3`, ...
+    /// suffix and a warning. A single file is registered as one table named
+    /// after its stem. Files are registered concurrently, as by
+    /// [`register_all`](Self::register_all).
+    ///
+    /// Returns the table names, in the order of the sorted files or sheets.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`SqlStreamError::FileNotFound`] if nothing matches, or the
+    /// first error any file or sheet fails to register with
+    #[instrument(skip(self))]
+    pub async fn register_each(
+        &mut self,
+        source: &str,
+        options: &ReadOptions,
+    ) -> Result<Vec<String>> {
+        let files = if preprocess::is_glob(source) || Path::new(source).is_dir() {
+            preprocess::expand_paths(source)?
+        } else {
+            vec![PathBuf::from(source)]
+        };
+
+        let mut taken = self.list_tables();
+        let is_workbook = |file: &Path| {
+            preprocess::format_extension(file).is_some_and(|ext| ext.eq_ignore_ascii_case("xlsx"))
+        };
+        if let [file] = files.as_slice() {
+            if is_workbook(file) && options.sheet.is_none() {
+                let mut names = Vec::new();
+                for sheet in xlsx_sheet_names(file)? {
+                    let name = unique_table_name(&sheet, &mut taken);
+                    let sheet_options = ReadOptions {
+                        sheet: Some(sheet),
+                        ..options.clone()
+                    };
+                    self.register_source(&file.to_string_lossy(), &name, &sheet_options)
+                        .await?;
+                    names.push(name);
+                }
+                return Ok(names);
+            }
+        }
+
+        let tables: Vec<(String, String)> = files
+            .iter()
+            .map(|file| {
+                let stem = file_stem_name(file);
+                (
+                    file.to_string_lossy().to_string(),
+                    unique_table_name(&stem, &mut taken),
+                )
+            })
+            .collect();
+        let pairs: Vec<(&str, &str)> = tables
+            .iter()
+            .map(|(file, name)| (file.as_str(), name.as_str()))
+            .collect();
+        self.register_all(&pairs, options).await?;
+        Ok(tables.into_iter().map(|(_, name)| name).collect())
+    }
+
+    /// Register one file, URL or pattern as a table
+    ///
+    /// Only needs `&self`, as the session context is internally synchronized
+    /// and temporary files go behind a mutex, so several can run at once.
+    /// With [`ReadOptions::replace`], a table of the same name is swapped out,
+    /// and restored if the new one fails to register.
+    async fn register_source(
+        &self,
+        file_path: &str,
+        table_name: &str,
+        options: &ReadOptions,
+    ) -> Result<()> {
+        check_table_name(table_name)?;
+        let previous = if options.replace {
+            self.ctx.deregister_table(table_name)?
+        } else if self.ctx.table_exist(table_name)? {
+            return Err(SqlStreamError::TableRegistration(
+                table_name.to_string(),
+                "a table with this name is already registered".to_string(),
+            ));
+        } else {
+            None
+        };
+
+        let result = self
+            .register_new_source(file_path, table_name, options)
+            .await;
+        match (&result, previous) {
+            (Err(_), Some(previous)) => {
+                self.ctx.register_table(table_name, previous)?;
+            }
+            (Ok(()), Some(_)) => {
+                info!("Replaced table: {}", table_name);
+                // Results cached from the old table are stale
+                self.lock_cache().entries.clear();
+            }
+            _ => {}
+        }
+        result
+    }
+
+    /// Register one file, URL or pattern under a table name not yet in use
+    async fn register_new_source(
+        &self,
+        file_path: &str,
+        table_name: &str,
+        options: &ReadOptions,
+    ) -> Result<()> {
         let path = Path::new(file_path);
 
+        if let Some(url) = preprocess::remote_url(file_path) {
+            return self.register_remote(url, table_name, options).await;
+        }
+        if path.is_dir() {
+            if let Some(layout) = preprocess::hive_layout(path)? {
+                return self
+                    .register_partitioned(file_path, layout, table_name, options)
+                    .await;
+            }
+        }
+        if preprocess::is_glob(file_path) || path.is_dir() {
+            return self.register_files(file_path, table_name, options).await;
+        }
+
         // Check if file exists
         if !path.exists() {
             return Err(SqlStreamError::FileNotFound(path.to_path_buf()));
         }
 
-        info!("Registering file: {} as table: {}", file_path, table_name);
+        // A pipe is consumed by reading it, so it is copied for DataFusion's scans
+        let spooled;
+        let path = if preprocess::is_pipe(path)? {
+            spooled = self.spool_pipe(path).await?;
+            Path::new(&spooled)
+        } else {
+            path
+        };
+        // A file still being written elsewhere may take a moment to open
+        preprocess::open_with_retry(path, options.open_retries(), || File::open(path)).await?;
 
         // Detect file format from extension
-        let extension = path
-            .extension()
-            .and_then(|ext| ext.to_str())
-            .ok_or_else(|| SqlStreamError::UnsupportedFormat(path.to_string_lossy().to_string()))?;
+        let extension = file_format(path)?;
+        if preprocess::is_blank(path)? {
+            return Err(SqlStreamError::EmptyFile(path.to_path_buf()));
+        }
+
+        info!("Registering file: {} as table: {}", file_path, table_name);
+        warn_table_name(table_name);
+        check_compression(path, extension)?;
+        check_declared_schema(extension, options)?;
 
         match extension.to_lowercase().as_str() {
-            "csv" => {
+            "csv" | "tsv" => {
                 debug!("Detected CSV format");
-                self.ctx
-                    .register_csv(table_name, file_path, CsvReadOptions::new())
-                    .await
-                    .map_err(|e| {
-                        SqlStreamError::TableRegistration(table_name.to_string(), e.to_string())
-                    })?;
+                let file_path = self.csv_source(path, options)?;
+                let delimiter = csv_delimiter(&file_path, extension, options)?;
+                if let Some(schema) = &options.schema {
+                    check_declared_columns(
+                        path,
+                        Path::new(&file_path),
+                        delimiter,
+                        schema,
+                        options,
+                    )?;
+                }
+
+                // Without data rows there is nothing to infer types from
+                let header_schema = match &options.schema {
+                    None if !options.no_header => {
+                        header_only_schema(Path::new(&file_path), delimiter)?
+                    }
+                    _ => None,
+                };
+
+                let file_extension = dotted_extension(&file_path);
+                let mut csv_options = CsvReadOptions::new()
+                    .delimiter(delimiter)
+                    .quote(options.quote.unwrap_or(b'"'))
+                    .has_header(!options.no_header)
+                    .file_extension(&file_extension)
+                    .file_compression_type(preprocess::compression(Path::new(&file_path)));
+                if let Some(rows) = options.infer_rows() {
+                    csv_options = csv_options.schema_infer_max_records(rows);
+                }
+                csv_options = csv_options.null_regex(options.null_regex());
+                let text_schema = match (&options.schema, &header_schema) {
+                    (None, None) if !options.text_columns.is_empty() => {
+                        let inferred = self
+                            .ctx
+                            .read_csv(file_path.as_str(), csv_options.clone())
+                            .await
+                            .map_err(|e| registration_error(table_name, path, e))?
+                            .schema()
+                            .inner()
+                            .clone();
+                        Some(read_as_text(inferred, &options.text_columns))
+                    }
+                    _ => None,
+                };
+                let schema = options
+                    .schema
+                    .as_ref()
+                    .or(header_schema.as_ref())
+                    .or(text_schema.as_ref());
+                if let Some(regex) = options.null_regex() {
+                    let view = self
+                        .csv_null_view(&file_path, csv_options, schema, &regex)
+                        .await
+                        .map_err(|e| registration_error(table_name, path, e))?;
+                    self.register_table(table_name, view)?;
+                } else {
+                    if let Some(schema) = schema {
+                        csv_options = csv_options.schema(schema);
+                    }
+                    self.ctx
+                        .register_csv(table_name, &file_path, csv_options)
+                        .await
+                        .map_err(|e| registration_error(table_name, path, e))?;
+                }
             }
             "json" => {
                 debug!("Detected JSON format");
+                // Cleaning also decompresses, so the copy is always plain NDJSON
+                let file_path = self.keep_temp(preprocess::clean_ndjson(path, options.json_mode)?);
+                let mut json_options = NdJsonReadOptions::default();
+                if let Some(rows) = options.infer_rows() {
+                    json_options.schema_infer_max_records = rows;
+                }
+                if let Some(schema) = &options.schema {
+                    json_options = json_options.schema(schema);
+                }
+                self.ctx
+                    .register_json(table_name, &file_path, json_options)
+                    .await
+                    .map_err(|e| registration_error(table_name, path, e))?;
+            }
+            "parquet" | "pqt" => {
+                debug!("Detected Parquet format");
+                let file_path = path.to_string_lossy();
+                let file_extension = dotted_extension(&file_path);
+                let parquet_options = ParquetReadOptions {
+                    file_extension: &file_extension,
+                    ..Default::default()
+                };
                 self.ctx
-                    .register_json(table_name, file_path, NdJsonReadOptions::default())
+                    .register_parquet(table_name, &file_path, parquet_options)
                     .await
-                    .map_err(|e| {
-                        SqlStreamError::TableRegistration(table_name.to_string(), e.to_string())
-                    })?;
+                    .map_err(|e| registration_error(table_name, path, e))?;
+            }
+            "avro" => {
+                debug!("Detected Avro format");
+                self.register_avro(table_name, path).await?;
+            }
+            "arrow" | "arrows" | "feather" => {
+                debug!("Detected Arrow IPC format");
+                let (schema, batches) = read_arrow_ipc(path)?;
+                let table = MemTable::try_new(schema, vec![batches])?;
+                self.register_table(table_name, Arc::new(table))?;
+            }
+            "orc" => {
+                debug!("Detected ORC format");
+                let (schema, batches) =
+                    read_orc(path).map_err(|e| registration_error(table_name, path, e))?;
+                let table = MemTable::try_new(schema, vec![batches])?;
+                self.register_table(table_name, Arc::new(table))?;
+            }
+            "xlsx" => {
+                debug!("Detected Excel format");
+                let (schema, batches) = read_xlsx(path, options.sheet.as_deref())?;
+                let table = MemTable::try_new(schema, vec![batches])?;
+                self.register_table(table_name, Arc::new(table))?;
             }
             _ => {
                 return Err(SqlStreamError::UnsupportedFormat(extension.to_string()));
@@ -94,80 +1004,2833 @@ impl QueryEngine {
         Ok(())
     }
 
-    /// Execute a SQL query and return the results as a DataFrame
-    ///
-    /// # Arguments
-    ///
-    /// * `sql` - SQL query string to execute
-    ///
-    /// # Errors
+    /// Register an Avro container file through DataFusion's Avro reader
     ///
-    /// Returns an error if query parsing or execution fails
-    #[instrument(skip(self))]
-    pub async fn execute_query(&self, sql: &str) -> Result<DataFrame> {
-        info!("Executing SQL query");
-        debug!("Query: {}", sql);
-
-        let df = self
-            .ctx
-            .sql(sql)
+    /// The schema written in the file's header is used: nested records become
+    /// struct columns, and unions of `null` with one other type become
+    /// nullable columns of that type.
+    #[cfg(feature = "avro")]
+    async fn register_avro(&self, table_name: &str, path: &Path) -> Result<()> {
+        let file_path = path.to_string_lossy();
+        let file_extension = dotted_extension(&file_path);
+        let avro_options = AvroReadOptions {
+            file_extension: &file_extension,
+            ..Default::default()
+        };
+        self.ctx
+            .register_avro(table_name, &file_path, avro_options)
             .await
-            .map_err(|e| SqlStreamError::QueryExecution(e.to_string()))?;
+            .map_err(|e| registration_error(table_name, path, e))
+    }
 
-        Ok(df)
+    /// Stand-in for [`register_avro`](Self::register_avro) when the `avro` feature is disabled
+    #[cfg(not(feature = "avro"))]
+    async fn register_avro(&self, _table_name: &str, _path: &Path) -> Result<()> {
+        avro_format().map(|_| ())
     }
 
-    /// Execute a SQL query and print the results to stdout
-    ///
-    /// Uses Arrow's pretty printer for formatted table output with
-    /// streaming to handle large result sets efficiently.
-    ///
-    /// # Arguments
-    ///
-    /// * `dataframe` - The DataFrame to print
+    /// Register a CSV, JSON or Parquet file served over HTTP(S), or stored in S3, as a table
     ///
-    /// # Errors
-    ///
-    /// Returns an error if result collection or printing fails
-    #[instrument(skip(self, dataframe))]
-    pub async fn print_results(&self, dataframe: DataFrame) -> Result<()> {
-        info!("Collecting and printing results");
+    /// An object store is registered for the URL's host (or bucket) and
+    /// DataFusion reads the file through it, so nothing is downloaded up front. Remote JSON must
+    /// be plain NDJSON, and header patterns and delimiter sniffing, which
+    /// rewrite or pre-read local files, are not available.
+    async fn register_remote(
+        &self,
+        url: Url,
+        table_name: &str,
+        options: &ReadOptions,
+    ) -> Result<()> {
+        info!("Registering remote file: {} as table: {}", url, table_name);
+        warn_table_name(table_name);
+
+        if options.header_pattern.is_some() || options.sniff_delimiter {
+            return Err(SqlStreamError::InvalidOption(
+                "header patterns and delimiter sniffing need a local file".to_string(),
+            ));
+        }
 
-        // Collect results as RecordBatches
-        let batches = dataframe.collect().await?;
+        let store = object_store_for(&url, options)?;
+        self.ctx.register_object_store(&url, store);
 
-        // Print using Arrow's pretty printer
-        print_batches(&batches).map_err(|e| {
-            SqlStreamError::QueryExecution(format!("Failed to print results: {}", e))
-        })?;
+        let path = Path::new(url.path());
+        let extension = preprocess::format_extension(path)
+            .ok_or_else(|| SqlStreamError::UnsupportedFormat(url.to_string()))?;
+        check_compression(path, extension)?;
+        check_declared_schema(extension, options)?;
+        let file_extension = dotted_extension(url.path());
+        let compression = preprocess::compression(path);
+
+        let registered = match extension.to_lowercase().as_str() {
+            "csv" | "tsv" => {
+                let mut csv_options = CsvReadOptions::new()
+                    .delimiter(csv_delimiter(url.path(), extension, options)?)
+                    .quote(options.quote.unwrap_or(b'"'))
+                    .has_header(!options.no_header)
+                    .file_extension(&file_extension)
+                    .file_compression_type(compression);
+                if let Some(rows) = options.infer_rows() {
+                    csv_options = csv_options.schema_infer_max_records(rows);
+                }
+                csv_options = csv_options.null_regex(options.null_regex());
+                if let Some(regex) = options.null_regex() {
+                    self.csv_null_view(url.as_str(), csv_options, options.schema.as_ref(), &regex)
+                        .await
+                        .and_then(|view| self.ctx.register_table(table_name, view))
+                        .map(|_| ())
+                } else {
+                    if let Some(schema) = &options.schema {
+                        csv_options = csv_options.schema(schema);
+                    }
+                    self.ctx
+                        .register_csv(table_name, url.as_str(), csv_options)
+                        .await
+                }
+            }
+            "json" => {
+                if options.json_mode == JsonMode::Array {
+                    return Err(SqlStreamError::InvalidOption(
+                        "JSON arrays need a local file; remote JSON must be NDJSON".to_string(),
+                    ));
+                }
+                let mut json_options = NdJsonReadOptions::default()
+                    .file_extension(&file_extension)
+                    .file_compression_type(compression);
+                if let Some(rows) = options.infer_rows() {
+                    json_options.schema_infer_max_records = rows;
+                }
+                if let Some(schema) = &options.schema {
+                    json_options = json_options.schema(schema);
+                }
+                self.ctx
+                    .register_json(table_name, url.as_str(), json_options)
+                    .await
+            }
+            "parquet" | "pqt" => {
+                let parquet_options = ParquetReadOptions {
+                    file_extension: &file_extension,
+                    ..Default::default()
+                };
+                self.ctx
+                    .register_parquet(table_name, url.as_str(), parquet_options)
+                    .await
+            }
+            _ => return Err(SqlStreamError::UnsupportedFormat(url.to_string())),
+        };
 
-        let total_rows: usize = batches.iter().map(|b| b.num_rows()).sum();
-        info!("Query returned {} rows", total_rows);
+        registered.map_err(|e| match e.find_root() {
+            DataFusionError::ObjectStore(err) => remote_error(&url, err, options),
+            _ => registration_error(table_name, path, e),
+        })?;
 
+        info!("Successfully registered table: {}", table_name);
         Ok(())
     }
-}
 
-impl Default for QueryEngine {
-    fn default() -> Self {
-        Self::new().expect("Failed to create default QueryEngine")
+    /// Read a CSV source as a view that turns fields matching `regex` into NULL
+    ///
+    /// `schema` gives the column types; when unset they are inferred, with
+    /// matching fields counted as NULL. See [`null_sentinels`].
+    async fn csv_null_view(
+        &self,
+        source: &str,
+        csv_options: CsvReadOptions<'_>,
+        schema: Option<&SchemaRef>,
+        regex: &str,
+    ) -> datafusion::error::Result<Arc<dyn TableProvider>> {
+        let schema = match schema {
+            Some(schema) => Arc::clone(schema),
+            None => self
+                .ctx
+                .read_csv(source, csv_options.clone())
+                .await?
+                .schema()
+                .inner()
+                .clone(),
+        };
+        let text = all_text(&schema);
+        let dataframe = self.ctx.read_csv(source, csv_options.schema(&text)).await?;
+        Ok(null_sentinels(dataframe, &schema, regex)?.into_view())
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
 
-    #[tokio::test]
-    async fn test_engine_creation() {
-        let engine = QueryEngine::new();
-        assert!(engine.is_ok());
+    /// Register a listing table, behind a view when some columns are read as text
+    ///
+    /// With a NULL regex, CSV columns are read with [`all_text`] and cast back
+    /// to `schema`; `padded` partition columns are cast to Int64.
+    fn register_listing(
+        &self,
+        table_name: &str,
+        table: ListingTable,
+        schema: &Schema,
+        regex: Option<&str>,
+        padded: &[String],
+    ) -> Result<()> {
+        if regex.is_none() && padded.is_empty() {
+            return self.register_table(table_name, Arc::new(table));
+        }
+        let mut dataframe = self.ctx.read_table(Arc::new(table))?;
+        if let Some(regex) = regex {
+            dataframe = null_sentinels(dataframe, schema, regex)?;
+        }
+        if !padded.is_empty() {
+            let columns: Vec<Expr> = dataframe
+                .schema()
+                .fields()
+                .iter()
+                .map(|field| {
+                    let column = ident(field.name());
+                    if padded.contains(field.name()) {
+                        cast(column, DataType::Int64).alias(field.name())
+                    } else {
+                        column
+                    }
+                })
+                .collect();
+            dataframe = dataframe.select(columns)?;
+        }
+        self.register_table(table_name, dataframe.into_view())
     }
 
-    #[tokio::test]
-    async fn test_file_not_found() {
-        let mut engine = QueryEngine::new().unwrap();
-        let result = engine.register_file("nonexistent.csv", "test").await;
-        assert!(matches!(result, Err(SqlStreamError::FileNotFound(_))));
+    /// Register every file matching a glob pattern, or inside a directory, as one table
+    ///
+    /// All files must share a format and, once inferred, a schema (the same
+    /// column names and types, in order). CSV and JSON files are preprocessed
+    /// individually, exactly as single files are; Arrow IPC and ORC files are
+    /// loaded into memory with one partition per file.
+    async fn register_files(
+        &self,
+        pattern: &str,
+        table_name: &str,
+        options: &ReadOptions,
+    ) -> Result<()> {
+        let files = preprocess::expand_paths(pattern)?;
+        let extension = common_extension(&files)?;
+        for file in &files {
+            if preprocess::is_blank(file)? {
+                return Err(SqlStreamError::EmptyFile(file.clone()));
+            }
+        }
+        check_declared_schema(&extension, options)?;
+        warn_table_name(table_name);
+        info!(
+            "Registering {} files from {} as table: {}",
+            files.len(),
+            pattern,
+            table_name
+        );
+
+        let (format, paths): (Arc<dyn FileFormat>, Vec<String>) = match extension.as_str() {
+            "csv" | "tsv" => {
+                let paths = files
+                    .iter()
+                    .map(|file| self.csv_source(file, options))
+                    .collect::<Result<Vec<_>>>()?;
+                let delimiter = csv_delimiter(&paths[0], &extension, options)?;
+                if let Some(schema) = &options.schema {
+                    for (file, path) in files.iter().zip(&paths) {
+                        check_declared_columns(file, Path::new(path), delimiter, schema, options)?;
+                    }
+                }
+                let format = csv_format(delimiter, Path::new(&paths[0]), options);
+                (Arc::new(format), paths)
+            }
+            "json" => {
+                let mut paths = Vec::with_capacity(files.len());
+                for file in &files {
+                    paths.push(self.keep_temp(preprocess::clean_ndjson(file, options.json_mode)?));
+                }
+                let mut format = JsonFormat::default();
+                if let Some(rows) = options.infer_rows() {
+                    format = format.with_schema_infer_max_rec(rows);
+                }
+                (Arc::new(format), paths)
+            }
+            "parquet" | "pqt" => {
+                let paths = files
+                    .iter()
+                    .map(|file| file.to_string_lossy().to_string())
+                    .collect();
+                (Arc::new(ParquetFormat::default()), paths)
+            }
+            "avro" => {
+                let paths = files
+                    .iter()
+                    .map(|file| file.to_string_lossy().to_string())
+                    .collect();
+                (avro_format()?, paths)
+            }
+            "arrow" | "arrows" | "feather" | "orc" | "xlsx" => {
+                let mut schema: Option<SchemaRef> = None;
+                let mut partitions = Vec::with_capacity(files.len());
+                for file in &files {
+                    let (file_schema, batches) = match extension.as_str() {
+                        "orc" => {
+                            read_orc(file).map_err(|e| registration_error(table_name, file, e))?
+                        }
+                        "xlsx" => read_xlsx(file, options.sheet.as_deref())?,
+                        _ => read_arrow_ipc(file)?,
+                    };
+                    match &schema {
+                        Some(first) => check_same_columns(first, &file_schema, &files[0], file)?,
+                        None => schema = Some(file_schema),
+                    }
+                    partitions.push(batches);
+                }
+                let schema = schema.ok_or_else(|| SqlStreamError::FileNotFound(pattern.into()))?;
+                let table = MemTable::try_new(schema, partitions)?;
+                return self.register_table(table_name, Arc::new(table));
+            }
+            _ => return Err(SqlStreamError::UnsupportedFormat(extension)),
+        };
+
+        // The paths are listed explicitly, so no extension filter is needed
+        let listing_options = ListingOptions::new(format).with_file_extension("");
+        let state = self.ctx.state();
+        let mut urls = Vec::with_capacity(paths.len());
+        let mut schema: Option<SchemaRef> = None;
+        for (file, path) in files.iter().zip(&paths) {
+            let url = ListingTableUrl::parse(path)?;
+            let file_schema = match &options.schema {
+                Some(declared) => Arc::clone(declared),
+                None => listing_options
+                    .infer_schema(&state, &url)
+                    .await
+                    .map_err(|e| registration_error(table_name, file, e))?,
+            };
+            let file_schema = match extension.as_str() {
+                "csv" | "tsv" if options.schema.is_none() => {
+                    read_as_text(file_schema, &options.text_columns)
+                }
+                _ => file_schema,
+            };
+            match &schema {
+                Some(first) => check_same_columns(first, &file_schema, &files[0], file)?,
+                None => schema = Some(file_schema),
+            }
+            urls.push(url);
+        }
+
+        let schema = schema.ok_or_else(|| SqlStreamError::FileNotFound(pattern.into()))?;
+        let null_regex = options.null_regex().filter(|_| is_csv(&extension));
+        let config = ListingTableConfig::new_with_multi_paths(urls)
+            .with_listing_options(listing_options)
+            .with_schema(match null_regex {
+                Some(_) => all_text(&schema),
+                None => Arc::clone(&schema),
+            });
+        let table = ListingTable::try_new(config).map_err(|e| {
+            SqlStreamError::TableRegistration(table_name.to_string(), e.to_string())
+        })?;
+        self.register_listing(table_name, table, &schema, null_regex.as_deref(), &[])?;
+
+        info!("Successfully registered table: {}", table_name);
+        Ok(())
+    }
+
+    /// Register a directory of Hive-style `key=value` partitions as one table
+    ///
+    /// Each partition key becomes a column after the files' own columns, typed
+    /// Int64 when every value is a whole number and text otherwise. The
+    /// directory itself is the table's location, so filters on partition
+    /// columns skip whole directories without reading them. Its CSV, JSON or
+    /// Parquet files are read as they are: CSV files can't have preambles and
+    /// JSON files must be newline-delimited.
+    async fn register_partitioned(
+        &self,
+        dir: &str,
+        layout: preprocess::HiveLayout,
+        table_name: &str,
+        options: &ReadOptions,
+    ) -> Result<()> {
+        let files = &layout.files;
+        if files.is_empty() {
+            return Err(SqlStreamError::FileNotFound(dir.into()));
+        }
+        let extension = common_extension(files)?;
+        for file in files {
+            if preprocess::is_blank(file)? {
+                return Err(SqlStreamError::EmptyFile(file.clone()));
+            }
+        }
+        check_declared_schema(&extension, options)?;
+        warn_table_name(table_name);
+        info!(
+            "Registering {} files in {} partitions of {} as table: {}",
+            files.len(),
+            layout.columns.len(),
+            dir,
+            table_name
+        );
+
+        let format: Arc<dyn FileFormat> = match extension.as_str() {
+            "csv" | "tsv" => {
+                if options.header_pattern.is_some() {
+                    return Err(SqlStreamError::InvalidOption(
+                        "--header-pattern can't be used with a partitioned directory".to_string(),
+                    ));
+                }
+                let delimiter = csv_delimiter(&files[0].to_string_lossy(), &extension, options)?;
+                if let Some(schema) = &options.schema {
+                    for file in files {
+                        check_declared_columns(file, file, delimiter, schema, options)?;
+                    }
+                }
+                Arc::new(csv_format(delimiter, &files[0], options))
+            }
+            "json" => {
+                let mut format = JsonFormat::default()
+                    .with_file_compression_type(preprocess::compression(&files[0]));
+                if let Some(rows) = options.infer_rows() {
+                    format = format.with_schema_infer_max_rec(rows);
+                }
+                Arc::new(format)
+            }
+            "parquet" | "pqt" => Arc::new(ParquetFormat::default()),
+            _ => {
+                return Err(SqlStreamError::UnsupportedFormat(format!(
+                    "{} (partitioned directories must hold CSV, JSON or Parquet files)",
+                    dir
+                )))
+            }
+        };
+
+        // Equality filters on every partition column make DataFusion list the
+        // `key=value` directory directly, which misses zero-padded values such
+        // as `month=01`, so those columns are listed as text and cast after
+        let padded: Vec<String> = layout
+            .columns
+            .iter()
+            .filter(|(_, values)| {
+                partition_type(values) == DataType::Int64
+                    && values.iter().any(|value| {
+                        value.parse::<i64>().map(|n| n.to_string()) != Ok(value.clone())
+                    })
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        let partition_cols = layout
+            .columns
+            .iter()
+            .map(|(key, values)| {
+                if padded.contains(key) {
+                    (key.clone(), DataType::Utf8)
+                } else {
+                    (key.clone(), partition_type(values))
+                }
+            })
+            .collect();
+        let listing_options = ListingOptions::new(format)
+            .with_file_extension(listing_extension(&files[0], &extension))
+            .with_table_partition_cols(partition_cols);
+        let url = ListingTableUrl::parse(dir)?;
+        let schema = match &options.schema {
+            Some(declared) => Arc::clone(declared),
+            None => listing_options
+                .infer_schema(&self.ctx.state(), &url)
+                .await
+                .map_err(|e| registration_error(table_name, Path::new(dir), e))?,
+        };
+        let schema = match extension.as_str() {
+            "csv" | "tsv" if options.schema.is_none() => {
+                read_as_text(schema, &options.text_columns)
+            }
+            _ => schema,
+        };
+
+        let null_regex = options.null_regex().filter(|_| is_csv(&extension));
+        let config = ListingTableConfig::new(url)
+            .with_listing_options(listing_options)
+            .with_schema(match null_regex {
+                Some(_) => all_text(&schema),
+                None => Arc::clone(&schema),
+            });
+        let table = ListingTable::try_new(config).map_err(|e| {
+            SqlStreamError::TableRegistration(table_name.to_string(), e.to_string())
+        })?;
+        self.register_listing(table_name, table, &schema, null_regex.as_deref(), &padded)?;
+
+        info!("Successfully registered table: {}", table_name);
+        Ok(())
+    }
+
+    /// The path to read a CSV file from, after applying `--header-pattern`
+    ///
+    /// Files with preamble lines are copied to a temporary file starting at the
+    /// header, and files starting with a UTF-8 BOM are copied without it; the
+    /// copy lives as long as the engine.
+    fn csv_source(&self, path: &Path, options: &ReadOptions) -> Result<String> {
+        match &options.header_pattern {
+            Some(pattern) => {
+                let pattern = Regex::new(pattern)
+                    .map_err(|e| SqlStreamError::InvalidOption(e.to_string()))?;
+                Ok(self.keep_temp(preprocess::skip_to_header(path, &pattern)?))
+            }
+            None if preprocess::has_bom(path)? => Ok(self.keep_temp(preprocess::strip_bom(path)?)),
+            None => Ok(path.to_string_lossy().to_string()),
+        }
+    }
+
+    /// Copy a named pipe to a temporary file with the same extensions, returning its path
+    ///
+    /// Reading waits for a writer to open the pipe and finishes once it closes.
+    async fn spool_pipe(&self, path: &Path) -> Result<String> {
+        let format = preprocess::format_extension(path)
+            .ok_or_else(|| SqlStreamError::UnsupportedFormat(path.to_string_lossy().to_string()))?;
+        // Compressed data stays compressed, and is decompressed like any other file
+        let extension = match path.extension().and_then(|ext| ext.to_str()) {
+            Some(compressed) if preprocess::compression(path).is_compressed() => {
+                format!("{}.{}", format, compressed)
+            }
+            _ => format.to_string(),
+        };
+        debug!(
+            "Copying pipe {} to a temporary .{} file",
+            path.display(),
+            extension
+        );
+        let pipe = path.to_path_buf();
+        let temp = spool_blocking(move || File::open(pipe), extension).await?;
+        Ok(self.keep_temp(temp))
+    }
+
+    /// Keep a temporary file alive for the engine's lifetime, returning its path
+    fn keep_temp(&self, temp: NamedTempFile) -> String {
+        let path = temp.path().to_string_lossy().to_string();
+        self.temp_files
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(temp);
+        path
+    }
+
+    /// Register data read from `reader`, such as stdin, as a table
+    ///
+    /// The input is spooled to a temporary file first, since it has no path or
+    /// extension and DataFusion's readers need to scan it more than once (for
+    /// schema inference, then for queries). The copy lives as long as the engine.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading fails or the data cannot be registered as `format`
+    #[instrument(skip(self, reader))]
+    pub async fn register_reader<R: Read + Send + 'static>(
+        &mut self,
+        reader: R,
+        format: InputFormat,
+        table_name: &str,
+        options: &ReadOptions,
+    ) -> Result<()> {
+        let temp = spool_blocking(move || Ok(reader), format.extension().to_string()).await?;
+        let file_path = self.keep_temp(temp);
+        self.register_file_with(&file_path, table_name, options)
+            .await
+    }
+
+    /// Register record batches already in memory as a table
+    ///
+    /// The batches are queried in place, without being copied to a file. Every
+    /// batch must have the same schema as the first.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use sql_stream::{QueryEngine, RecordBatch, SqlStreamError};
+    /// use datafusion::arrow::array::{Int64Array, StringArray};
+    /// use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), SqlStreamError> {
+    /// let schema = Arc::new(Schema::new(vec![
+    ///     Field::new("id", DataType::Int64, false),
+    ///     Field::new("name", DataType::Utf8, false),
+    /// ]));
+    /// let batch = RecordBatch::try_new(
+    ///     schema,
+    ///     vec![
+    ///         Arc::new(Int64Array::from(vec![1, 2])),
+    ///         Arc::new(StringArray::from(vec!["a", "b"])),
+    ///     ],
+    /// )?;
+    ///
+    /// let mut engine = QueryEngine::new()?;
+    /// engine.register_batches("people", vec![batch])?;
+    /// let batches = engine.collect_query("SELECT name FROM people WHERE id = 2").await?;
+    /// assert_eq!(batches[0].num_rows(), 1);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns [`SqlStreamError::TableRegistration`] if `batches` is empty,
+    /// their schemas differ, or registration fails
+    #[instrument(skip(self, batches))]
+    pub fn register_batches(&mut self, table_name: &str, batches: Vec<RecordBatch>) -> Result<()> {
+        let registration_error =
+            |reason: String| SqlStreamError::TableRegistration(table_name.to_string(), reason);
+
+        let schema = batches
+            .first()
+            .map(|batch| batch.schema())
+            .ok_or_else(|| registration_error("no batches to take a schema from".to_string()))?;
+        if let Some((index, batch)) = batches
+            .iter()
+            .enumerate()
+            .find(|(_, batch)| batch.schema() != schema)
+        {
+            return Err(registration_error(format!(
+                "batch {} has schema {} but batch 0 has {}",
+                index,
+                batch.schema(),
+                schema
+            )));
+        }
+
+        let table = MemTable::try_new(schema, vec![batches])?;
+        self.register_provider(table_name, Arc::new(table))
+    }
+
+    /// Register a user-defined scalar function for use in SQL queries
+    ///
+    /// The function is called by the name it was created with. A registered
+    /// function replaces any existing one of the same name, including built-ins.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use datafusion::arrow::array::StringArray;
+    /// use datafusion::arrow::datatypes::DataType;
+    /// use datafusion::common::cast::as_string_array;
+    /// use sql_stream::{create_udf, ColumnarValue, QueryEngine, SqlStreamError, Volatility};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), SqlStreamError> {
+    /// // redact(text) masks every character of a string
+    /// let redact = create_udf(
+    ///     "redact",
+    ///     vec![DataType::Utf8],
+    ///     DataType::Utf8,
+    ///     Volatility::Immutable,
+    ///     Arc::new(
+    ///         |args: &[ColumnarValue]| -> datafusion::error::Result<ColumnarValue> {
+    ///             let args = ColumnarValue::values_to_arrays(args)?;
+    ///             let redacted: StringArray = as_string_array(&args[0])?
+    ///                 .iter()
+    ///                 .map(|text| text.map(|text| "*".repeat(text.chars().count())))
+    ///                 .collect();
+    ///             Ok(ColumnarValue::Array(Arc::new(redacted)))
+    ///         },
+    ///     ),
+    /// );
+    ///
+    /// let mut engine = QueryEngine::new()?;
+    /// engine.register_udf(redact);
+    /// let batches = engine.collect_query("SELECT redact('secret') AS masked").await?;
+    /// assert_eq!(batches[0].num_rows(), 1);
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[instrument(skip(self, udf))]
+    pub fn register_udf(&mut self, udf: ScalarUDF) {
+        debug!("Registered scalar function: {}", udf.name());
+        self.ctx.register_udf(udf);
+    }
+
+    /// Register a custom table provider under `table_name`
+    ///
+    /// This is the extension point for data sources sql-stream does not know
+    /// about, such as bespoke file formats or computed tables. DataFusion
+    /// requires providers to be `Send + Sync`: `scan` may be called from any
+    /// runtime worker thread, and the returned plans may run concurrently.
+    /// The engine holds its `Arc` until the table is replaced or the engine is
+    /// dropped, so the provider must own (or share ownership of) its data.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`SqlStreamError::TableRegistration`] if registration fails
+    #[instrument(skip(self, provider))]
+    pub fn register_provider(
+        &mut self,
+        table_name: &str,
+        provider: Arc<dyn TableProvider>,
+    ) -> Result<()> {
+        check_table_name(table_name)?;
+        self.register_table(table_name, provider)?;
+        debug!("Registered custom provider as table: {}", table_name);
+        Ok(())
+    }
+
+    /// Add `provider` to the session context as `table_name`
+    fn register_table(&self, table_name: &str, provider: Arc<dyn TableProvider>) -> Result<()> {
+        self.ctx.register_table(table_name, provider).map_err(|e| {
+            SqlStreamError::TableRegistration(table_name.to_string(), e.to_string())
+        })?;
+        Ok(())
+    }
+
+    /// Execute a SQL query and return the results as a DataFrame
+    ///
+    /// # Arguments
+    ///
+    /// * `sql` - SQL query string to execute
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if query parsing or execution fails, or
+    /// [`SqlStreamError::TableNotFound`] if the query names an unregistered table
+    #[instrument(skip(self))]
+    pub async fn execute_query(&self, sql: &str) -> Result<DataFrame> {
+        info!("Executing SQL query");
+        debug!("Query: {}", sql);
+
+        let df = self.ctx.sql(sql).await.map_err(|e| self.query_error(e))?;
+
+        Ok(df)
+    }
+
+    /// Plan a SQL statement without running it
+    ///
+    /// The statement is parsed and planned against the registered tables, so
+    /// unknown tables and columns and type errors are reported, but nothing is
+    /// executed until the returned DataFrame is: unlike
+    /// [`execute_query`](Self::execute_query), DDL such as `CREATE VIEW` does
+    /// not take effect. Use it to check a query or read its output schema.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the statement does not parse or plan
+    #[instrument(skip(self))]
+    pub async fn plan_query(&self, sql: &str) -> Result<DataFrame> {
+        debug!("Planning query: {}", sql);
+
+        let state = self.ctx.state();
+        let plan = state
+            .create_logical_plan(sql)
+            .await
+            .map_err(|e| self.query_error(e))?;
+
+        Ok(DataFrame::new(state, plan))
+    }
+
+    /// Run every statement of a script except the last, and return the last
+    ///
+    /// Scripts are split with [`sql::split_statements`]. The leading statements
+    /// run to completion in order, so views, tables and session settings they
+    /// create are visible to the final statement, which is returned unexecuted
+    /// for the caller to run (or to explain, wrap, and so on).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the script has no statements or a leading statement fails
+    #[instrument(skip(self, script))]
+    pub async fn prepare_script(&self, script: &str) -> Result<String> {
+        let mut statements = sql::split_statements(script);
+        let last = statements.pop().ok_or_else(|| {
+            SqlStreamError::QueryExecution("No SQL statement to execute".to_string())
+        })?;
+
+        for statement in &statements {
+            debug!("Running leading statement: {}", statement);
+            self.execute_query(statement)
+                .await?
+                .collect()
+                .await
+                .map_err(SqlStreamError::execution)?;
+        }
+        Ok(last)
+    }
+
+    /// Execute a script of `;`-separated statements, returning the last one's results
+    ///
+    /// See [`prepare_script`](Self::prepare_script) for how the statements run.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use sql_stream::{PrintFormat, QueryEngine, SqlStreamError};
+    ///
+    /// # async fn example() -> Result<(), SqlStreamError> {
+    /// let mut engine = QueryEngine::new()?;
+    /// engine.register_file("employees.csv", "employees").await?;
+    ///
+    /// let results = engine
+    ///     .execute_script(
+    ///         "CREATE VIEW seniors AS SELECT * FROM employees WHERE age > 50; \
+    ///          SELECT COUNT(*) FROM seniors",
+    ///     )
+    ///     .await?;
+    /// engine.print_results(results, PrintFormat::Table).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the script has no statements or any statement fails
+    #[instrument(skip(self, script))]
+    pub async fn execute_script(&self, script: &str) -> Result<DataFrame> {
+        let last = self.prepare_script(script).await?;
+        self.execute_query(&last).await
+    }
+
+    /// Execute a SQL query and collect all result batches
+    ///
+    /// Convenient for library consumers that want to inspect results in code.
+    /// The whole result is held in memory; use [`stream_query`](Self::stream_query)
+    /// for large results.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use sql_stream::{QueryEngine, RecordBatch, SqlStreamError};
+    ///
+    /// # async fn example() -> Result<(), SqlStreamError> {
+    /// let mut engine = QueryEngine::new()?;
+    /// engine.register_file("employees.csv", "employees").await?;
+    ///
+    /// let batches: Vec<RecordBatch> = engine
+    ///     .collect_query("SELECT name, age FROM employees WHERE age > 30")
+    ///     .await?;
+    /// let rows: usize = batches.iter().map(|b| b.num_rows()).sum();
+    /// println!("{} rows, columns: {:?}", rows, batches[0].schema().fields());
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if query parsing or execution fails
+    #[instrument(skip(self))]
+    pub async fn collect_query(&self, sql: &str) -> Result<Vec<RecordBatch>> {
+        let df = self.execute_query(sql).await?;
+        let stream = self.execute_tracked(df).await?;
+        stream
+            .try_collect()
+            .await
+            .map_err(SqlStreamError::execution)
+    }
+
+    /// Execute a SQL query and collect its result unless `token` is cancelled first
+    ///
+    /// Like [`collect_query`](Self::collect_query), but once `token` is
+    /// cancelled the running query is dropped, which stops DataFusion's
+    /// execution, and [`SqlStreamError::Cancelled`] is returned. A token
+    /// cancelled before the call returns the error without running the query.
+    /// One token can cancel any number of queries, for example all those
+    /// running when a shutdown signal arrives.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use sql_stream::{CancellationToken, QueryEngine, SqlStreamError};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), SqlStreamError> {
+    /// let engine = QueryEngine::new()?;
+    /// let token = CancellationToken::new();
+    ///
+    /// let batches = engine
+    ///     .execute_query_cancellable("SELECT 1 AS n", &token)
+    ///     .await?;
+    /// assert_eq!(batches[0].num_rows(), 1);
+    ///
+    /// token.cancel();
+    /// let result = engine.execute_query_cancellable("SELECT 1 AS n", &token).await;
+    /// assert!(matches!(result, Err(SqlStreamError::Cancelled)));
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns [`SqlStreamError::Cancelled`] if `token` is cancelled before
+    /// the query finishes, or an error if planning or execution fails
+    #[instrument(skip(self, token))]
+    pub async fn execute_query_cancellable(
+        &self,
+        sql: &str,
+        token: &CancellationToken,
+    ) -> Result<Vec<RecordBatch>> {
+        tokio::select! {
+            biased;
+            () = token.cancelled() => {
+                info!("Query cancelled");
+                Err(SqlStreamError::Cancelled)
+            }
+            result = self.collect_query(sql) => result,
+        }
+    }
+
+    /// Execute a SQL query and collect its result with its schema and timing
+    ///
+    /// Like [`collect_query`](Self::collect_query), but the result also carries
+    /// the output schema (even for empty results), the row count and the time
+    /// taken, so embedders need not work them out from the batches.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use sql_stream::{QueryEngine, SqlStreamError};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), SqlStreamError> {
+    /// let engine = QueryEngine::new()?;
+    /// let result = engine
+    ///     .run("SELECT n, n * 2 AS doubled FROM (VALUES (1), (2)) AS t(n)")
+    ///     .await?;
+    ///
+    /// assert_eq!(result.row_count, 2);
+    /// assert_eq!(result.schema.field(1).name(), "doubled");
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if query parsing or execution fails
+    #[instrument(skip(self))]
+    pub async fn run(&self, sql: &str) -> Result<QueryResult> {
+        let started = Instant::now();
+        let df = self.execute_query(sql).await?;
+        let schema = df.schema().inner().clone();
+        let batches = df.collect().await.map_err(SqlStreamError::execution)?;
+        let row_count = batches.iter().map(|batch| batch.num_rows()).sum();
+
+        Ok(QueryResult {
+            schema,
+            batches,
+            row_count,
+            elapsed: started.elapsed(),
+        })
+    }
+
+    /// Execute a SQL query, reusing the result of an identical earlier query
+    ///
+    /// With [`EngineConfig::cache_entries`] set, the result of each query is
+    /// collected into memory and kept under its SQL text, with whitespace
+    /// outside quotes collapsed (see [`sql::normalize_query`]). Running the
+    /// same query again returns a DataFrame over the kept batches without
+    /// executing it. Input files are assumed not to change during the session,
+    /// so nothing is invalidated; the least recently used result is dropped
+    /// when the cache is full. Statements other than queries, such as
+    /// `CREATE VIEW` or `SET`, always run.
+    ///
+    /// Without a cache this is [`execute_query`](Self::execute_query).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if query parsing or execution fails
+    pub async fn execute_cached(&self, sql: &str) -> Result<DataFrame> {
+        let (df, _) = self.execute_cached_status(sql).await?;
+        Ok(df)
+    }
+
+    /// Like [`execute_cached`](Self::execute_cached), also saying whether the
+    /// cache answered the statement
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if query parsing or execution fails
+    #[instrument(skip(self))]
+    pub async fn execute_cached_status(&self, sql: &str) -> Result<(DataFrame, CacheStatus)> {
+        let key = sql::normalize_query(sql);
+        if self.lock_cache().capacity == 0 || !sql::is_query(&key) {
+            let df = self.execute_query(sql).await?;
+            return Ok((df, CacheStatus::Bypassed));
+        }
+
+        if let Some((schema, batches, age)) = self.lock_cache().get(&key) {
+            debug!("Reusing the cached result of: {}", key);
+            let df = self.cached_frame(schema, batches)?;
+            return Ok((df, CacheStatus::Hit { key, age }));
+        }
+
+        let df = self.execute_query(sql).await?;
+        let planned = df.schema().inner().clone();
+        let batches = df.collect().await.map_err(SqlStreamError::execution)?;
+        // Batches may differ from the plan in nullability, and must match the table
+        let schema = batches.first().map_or(planned, RecordBatch::schema);
+        self.lock_cache()
+            .insert(key.clone(), Arc::clone(&schema), batches.clone());
+        let df = self.cached_frame(schema, batches)?;
+        Ok((df, CacheStatus::Miss { key }))
+    }
+
+    /// Hits, misses and size of the result cache
+    pub fn cache_stats(&self) -> CacheStats {
+        let cache = self.lock_cache();
+        CacheStats {
+            hits: cache.hits,
+            misses: cache.misses,
+            entries: cache.entries.len(),
+        }
+    }
+
+    fn lock_cache(&self) -> MutexGuard<'_, ResultCache> {
+        self.cache.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// A DataFrame over collected batches
+    pub(crate) fn cached_frame(
+        &self,
+        schema: SchemaRef,
+        batches: Vec<RecordBatch>,
+    ) -> Result<DataFrame> {
+        let table = MemTable::try_new(schema, vec![batches])?;
+        Ok(self.ctx.read_table(Arc::new(table))?)
+    }
+
+    /// Execute a SQL query and stream its result batches
+    ///
+    /// Batches are produced as the caller polls the stream, so only the
+    /// batches in flight are held in memory and a slow consumer slows the scan
+    /// down. Rows pulled through the stream count towards
+    /// [`stream_stats`](Self::stream_stats).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use futures::StreamExt;
+    /// use sql_stream::{QueryEngine, SqlStreamError};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), SqlStreamError> {
+    /// let engine = QueryEngine::new()?;
+    /// let mut stream = engine
+    ///     .stream_query("SELECT * FROM (VALUES (1), (2), (3)) AS t(n) WHERE n > 1")
+    ///     .await?;
+    ///
+    /// let mut rows = 0;
+    /// while let Some(batch) = stream.next().await {
+    ///     rows += batch?.num_rows();
+    /// }
+    /// assert_eq!(rows, 2);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if query parsing or planning fails; errors during
+    /// execution are yielded by the stream
+    #[instrument(skip(self))]
+    pub async fn stream_query(&self, sql: &str) -> Result<SendableRecordBatchStream> {
+        let df = self.execute_query(sql).await?;
+        self.execute_stream(df).await
+    }
+
+    /// Run a query repeatedly and time each run
+    ///
+    /// The query runs `warmup` times untimed, to warm caches, and then
+    /// `repeat` times timed. Each run plans the query and drains its result
+    /// without printing it; registration is not repeated. Rows read here do
+    /// not count towards [`stream_stats`](Self::stream_stats).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if planning or executing any run fails
+    #[instrument(skip(self))]
+    pub async fn benchmark_query(
+        &self,
+        sql: &str,
+        warmup: usize,
+        repeat: usize,
+    ) -> Result<QueryTimings> {
+        let mut timings = QueryTimings::default();
+        for run in 0..warmup + repeat {
+            let started = Instant::now();
+            let mut stream = self.execute_query(sql).await?.execute_stream().await?;
+            while let Some(batch) = stream.next().await {
+                batch.map_err(SqlStreamError::execution)?;
+            }
+
+            if run >= warmup {
+                timings.runs.push(started.elapsed());
+            }
+        }
+
+        debug!("Benchmark: {}", timings.summary());
+        Ok(timings)
+    }
+
+    /// Totals of the rows and batches streamed by the output methods so far
+    ///
+    /// Every result printed, written or published through this engine is
+    /// counted, which makes a cheap summary of what a run produced.
+    pub fn stream_stats(&self) -> StreamStats {
+        self.counter.stats()
+    }
+
+    /// Peak memory pool usage and spilling of the results streamed so far
+    ///
+    /// The peak is only tracked under a
+    /// [`memory_limit`](EngineConfig::memory_limit); without one it stays 0.
+    /// It covers results printed, written or published through this engine,
+    /// as well as those of [`collect_query`](Self::collect_query), and a
+    /// result's spills are added once its stream has been read to the end.
+    pub fn memory_stats(&self) -> MemoryStats {
+        let (peak, limit) = match &self.memory_pool {
+            Some((pool, limit)) => (pool.peak.load(Ordering::Relaxed), Some(*limit)),
+            None => (0, None),
+        };
+        MemoryStats {
+            limit,
+            peak,
+            spilled_bytes: self.spills.bytes.load(Ordering::Relaxed),
+            spills: self.spills.count.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Start executing a DataFrame, counting the batches it produces
+    async fn execute_stream(&self, dataframe: DataFrame) -> Result<SendableRecordBatchStream> {
+        let stream = self.execute_tracked(dataframe).await?;
+        Ok(self.counter.count(stream))
+    }
+
+    /// Start executing a DataFrame, recording what its plan spilled once the stream ends
+    async fn execute_tracked(&self, dataframe: DataFrame) -> Result<SendableRecordBatchStream> {
+        let task_ctx = Arc::new(dataframe.task_ctx());
+        let plan = dataframe.create_physical_plan().await?;
+        let stream = physical_plan::execute_stream(Arc::clone(&plan), task_ctx)?;
+
+        let spills = Arc::clone(&self.spills);
+        let mut plan = Some(plan);
+        let schema = stream.schema();
+        let tracked = stream.chain(futures::stream::poll_fn(move |_| {
+            if let Some(plan) = plan.take() {
+                spills.record(plan.as_ref());
+            }
+            Poll::Ready(None)
+        }));
+        Ok(Box::pin(RecordBatchStreamAdapter::new(schema, tracked)))
+    }
+
+    /// Start executing a DataFrame like [`execute_stream`](Self::execute_stream),
+    /// stopping after `max_rows` rows, or at `deadline`, when given
+    ///
+    /// The cap is pushed into the plan as a `LIMIT` of one extra row, so the
+    /// scan stops early even if the query has its own larger limit; the extra
+    /// row is dropped from the stream and only marks the stats as truncated.
+    /// At the deadline the stream ends with the rows produced so far and marks
+    /// the stats as timed out.
+    async fn execute_capped(
+        &self,
+        dataframe: DataFrame,
+        max_rows: Option<usize>,
+        deadline: Option<Instant>,
+    ) -> Result<SendableRecordBatchStream> {
+        let mut stream = match max_rows {
+            Some(max_rows) => {
+                let limited = dataframe.limit(0, Some(max_rows.saturating_add(1)))?;
+                let stream = self.execute_tracked(limited).await?;
+                self.counter.cap(stream, max_rows)
+            }
+            None => self.execute_tracked(dataframe).await?,
+        };
+        if let Some(deadline) = deadline {
+            stream = self.counter.deadline(stream, deadline);
+        }
+        Ok(self.counter.count(stream))
+    }
+
+    /// Get a DataFrame over the full contents of a registered table
+    ///
+    /// Equivalent to `SELECT * FROM table_name` without going through the SQL planner.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no table with the given name is registered
+    #[instrument(skip(self))]
+    pub async fn table(&self, table_name: &str) -> Result<DataFrame> {
+        let df = self
+            .ctx
+            .table(table_name)
+            .await
+            .map_err(|e| self.query_error(e))?;
+
+        Ok(df)
+    }
+
+    /// Convert an error raised while planning a query
+    ///
+    /// A reference to an unregistered table becomes
+    /// [`SqlStreamError::TableNotFound`] listing the registered tables, since
+    /// DataFusion's message names neither, and SQL DataFusion cannot plan yet
+    /// becomes [`SqlStreamError::UnsupportedFeature`]; other errors become
+    /// [`SqlStreamError::QueryExecution`].
+    fn query_error(&self, err: DataFusionError) -> SqlStreamError {
+        let message = match err.find_root() {
+            DataFusionError::Plan(message) => message,
+            DataFusionError::NotImplemented(message) => {
+                return SqlStreamError::unsupported_feature(message)
+            }
+            _ => return SqlStreamError::QueryExecution(err.to_string()),
+        };
+        let Some(captures) = MISSING_TABLE.captures(message) else {
+            return SqlStreamError::QueryExecution(err.to_string());
+        };
+
+        // Names are reported fully qualified; the default catalog and schema are implied
+        let requested = captures
+            .get(1)
+            .or(captures.get(2))
+            .map_or("", |m| m.as_str());
+        let state = self.ctx.state();
+        let defaults = &state.config_options().catalog;
+        let prefix = format!("{}.{}.", defaults.default_catalog, defaults.default_schema);
+        SqlStreamError::TableNotFound {
+            requested: requested
+                .strip_prefix(&prefix)
+                .unwrap_or(requested)
+                .to_string(),
+            available: self.list_tables(),
+        }
+    }
+
+    /// Get the Arrow schema of a registered table
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no table with the given name is registered
+    pub async fn table_schema(&self, table_name: &str) -> Result<SchemaRef> {
+        let df = self.table(table_name).await?;
+        Ok(df.schema().inner().clone())
+    }
+
+    /// Remove a registered table
+    ///
+    /// Cached query results are dropped too, as they may have read the table.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`SqlStreamError::TableNotFound`] if no table with the given
+    /// name is registered
+    pub fn deregister_table(&mut self, table_name: &str) -> Result<()> {
+        if self.ctx.deregister_table(table_name)?.is_none() {
+            return Err(SqlStreamError::TableNotFound {
+                requested: table_name.to_string(),
+                available: self.list_tables(),
+            });
+        }
+        self.lock_cache().entries.clear();
+        info!("Deregistered table: {}", table_name);
+        Ok(())
+    }
+
+    /// Names of all tables in the default catalog and schema, sorted
+    pub fn list_tables(&self) -> Vec<String> {
+        let state = self.ctx.state();
+        let defaults = &state.config_options().catalog;
+        let mut names = self
+            .ctx
+            .catalog(&defaults.default_catalog)
+            .and_then(|catalog| catalog.schema(&defaults.default_schema))
+            .map(|schema| schema.table_names())
+            .unwrap_or_default();
+        names.sort();
+        names
+    }
+
+    /// Dictionary-encode string columns of a registered table
+    ///
+    /// Replaces the table with a projection that casts each named column to
+    /// `Dictionary(Int32, Utf8)`. This reduces memory and speeds up grouping and
+    /// joins on low-cardinality columns such as status codes or country names;
+    /// on high-cardinality columns the dictionary only adds overhead.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a column does not exist or is not a string column
+    #[instrument(skip(self))]
+    pub async fn dictionary_encode(&mut self, table_name: &str, columns: &[String]) -> Result<()> {
+        let df = self.table(table_name).await?;
+        let schema = df.schema().as_arrow().clone();
+
+        for column in columns {
+            let field = require_column(&schema, column)?;
+            if !matches!(
+                field.data_type(),
+                DataType::Utf8 | DataType::LargeUtf8 | DataType::Utf8View
+            ) {
+                return Err(SqlStreamError::InvalidOption(format!(
+                    "Cannot dictionary-encode column '{}' of type {}; only string columns are supported",
+                    column,
+                    field.data_type()
+                )));
+            }
+        }
+
+        let dictionary = DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8));
+        let exprs = schema
+            .fields()
+            .iter()
+            .map(|field| {
+                if columns.contains(field.name()) {
+                    cast(ident(field.name()), dictionary.clone()).alias(field.name())
+                } else {
+                    ident(field.name())
+                }
+            })
+            .collect();
+
+        self.replace_table(table_name, df.select(exprs)?)?;
+        debug!("Dictionary-encoded columns {:?} of {}", columns, table_name);
+        Ok(())
+    }
+
+    /// Cast columns of a registered table to other types
+    ///
+    /// Replaces the table with a projection applying `CAST(column AS type)` to
+    /// each named column, so later queries see the new types. Casting to text
+    /// after inference cannot restore leading zeros of a column read as a
+    /// number; list such columns in [`ReadOptions::text_columns`] as well.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a column does not exist or Arrow cannot cast its
+    /// type to the requested one
+    #[instrument(skip(self))]
+    pub async fn cast_columns(
+        &mut self,
+        table_name: &str,
+        casts: &[(String, DataType)],
+    ) -> Result<()> {
+        let df = self.table(table_name).await?;
+        let schema = df.schema().as_arrow().clone();
+
+        for (column, data_type) in casts {
+            let field = require_column(&schema, column)?;
+            if !can_cast_types(field.data_type(), data_type) {
+                return Err(SqlStreamError::InvalidOption(format!(
+                    "Cannot cast column '{}' from {} to {}",
+                    column,
+                    field.data_type(),
+                    data_type
+                )));
+            }
+        }
+
+        let exprs = schema
+            .fields()
+            .iter()
+            .map(|field| {
+                match casts
+                    .iter()
+                    .rev()
+                    .find(|(column, _)| column == field.name())
+                {
+                    Some((_, data_type)) => {
+                        cast(ident(field.name()), data_type.clone()).alias(field.name())
+                    }
+                    None => ident(field.name()),
+                }
+            })
+            .collect();
+
+        self.replace_table(table_name, df.select(exprs)?)?;
+        debug!("Cast columns {:?} of {}", casts, table_name);
+        Ok(())
+    }
+
+    /// Rename columns of a registered table
+    ///
+    /// Replaces the table with a projection aliasing each `(old, new)` column,
+    /// keeping every column in place, so queries can use clean names for
+    /// headers such as `Customer Name`. See [`transform::parse_rename`] for the
+    /// names accepted on the command line.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a column does not exist or is renamed twice, or the
+    /// table would end up with two columns of the same name
+    #[instrument(skip(self))]
+    pub async fn rename_columns(
+        &mut self,
+        table_name: &str,
+        renames: &[(String, String)],
+    ) -> Result<()> {
+        let df = self.table(table_name).await?;
+        let schema = df.schema().as_arrow().clone();
+
+        for (index, (old, _)) in renames.iter().enumerate() {
+            require_column(&schema, old)?;
+            if renames[..index].iter().any(|(earlier, _)| earlier == old) {
+                return Err(SqlStreamError::InvalidOption(format!(
+                    "Column '{}' is renamed more than once",
+                    old
+                )));
+            }
+        }
+
+        let new_name = |name: &str| {
+            renames
+                .iter()
+                .find(|(old, _)| old == name)
+                .map_or(name.to_string(), |(_, new)| new.clone())
+        };
+        let mut names = HashSet::new();
+        for field in schema.fields() {
+            let name = new_name(field.name());
+            if !names.insert(name.clone()) {
+                return Err(SqlStreamError::InvalidOption(format!(
+                    "Renaming columns of {} would leave two columns named '{}'",
+                    table_name, name
+                )));
+            }
+        }
+
+        let exprs = schema
+            .fields()
+            .iter()
+            .map(|field| ident(field.name()).alias(new_name(field.name())))
+            .collect();
+
+        self.replace_table(table_name, df.select(exprs)?)?;
+        debug!("Renamed columns {:?} of {}", renames, table_name);
+        Ok(())
+    }
+
+    /// Parse formatted numeric text columns of a registered table as numbers
+    ///
+    /// Replaces the table with a projection that strips currency symbols and
+    /// grouping separators from each named column and casts it to Float64, so
+    /// values like `$1,234.56` (US) or `1.234,56 €` (EU) can be aggregated.
+    /// Values that cannot be parsed become NULL.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a column does not exist or the projection fails
+    #[instrument(skip(self))]
+    pub async fn parse_numeric(
+        &mut self,
+        table_name: &str,
+        columns: &[String],
+        locale: NumericLocale,
+    ) -> Result<()> {
+        let schema = self.table_schema(table_name).await?;
+        for column in columns {
+            require_column(&schema, column)?;
+        }
+
+        let select = schema
+            .fields()
+            .iter()
+            .map(|field| {
+                let name = quote_ident(field.name());
+                if columns.contains(field.name()) {
+                    format!(
+                        "{} AS {}",
+                        transform::numeric_expr(field.name(), locale),
+                        name
+                    )
+                } else {
+                    name
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let df = self
+            .execute_query(&format!(
+                "SELECT {} FROM {}",
+                select,
+                quote_ident(&sql::normalize_table_name(table_name))
+            ))
+            .await?;
+        self.replace_table(table_name, df)?;
+        debug!("Parsed numeric columns {:?} of {}", columns, table_name);
+        Ok(())
+    }
+
+    /// Parse text date and timestamp columns of a registered table
+    ///
+    /// CSV inference only recognises ISO 8601 dates, so columns like
+    /// `03/14/2024` are read as text. Every text column whose values parse with
+    /// `date_format` becomes a `Date32` column, and otherwise every one whose
+    /// values parse with `timestamp_format` becomes a `Timestamp` column; both
+    /// use chrono's strftime syntax. One format of each kind applies to the
+    /// whole table.
+    ///
+    /// Columns are matched on a sample of their non-NULL values, so a later
+    /// value in another format makes queries over that column fail.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the table does not exist or the projection fails
+    #[instrument(skip(self))]
+    pub async fn parse_temporal(
+        &mut self,
+        table_name: &str,
+        date_format: Option<&str>,
+        timestamp_format: Option<&str>,
+    ) -> Result<()> {
+        let schema = self.table_schema(table_name).await?;
+
+        let mut parsed = Vec::new();
+        let mut select = Vec::with_capacity(schema.fields().len());
+        for field in schema.fields() {
+            let name = quote_ident(field.name());
+            let candidates = [
+                date_format.map(|format| transform::date_expr(field.name(), format)),
+                timestamp_format.map(|format| transform::timestamp_expr(field.name(), format)),
+            ];
+
+            let mut expr = None;
+            if matches!(
+                field.data_type(),
+                DataType::Utf8 | DataType::LargeUtf8 | DataType::Utf8View
+            ) {
+                for candidate in candidates.into_iter().flatten() {
+                    if self
+                        .parses_sample(table_name, field.name(), &candidate)
+                        .await?
+                    {
+                        expr = Some(candidate);
+                        break;
+                    }
+                }
+            }
+
+            match expr {
+                Some(expr) => {
+                    parsed.push(field.name().clone());
+                    select.push(format!("{} AS {}", expr, name));
+                }
+                None => select.push(name),
+            }
+        }
+
+        if parsed.is_empty() {
+            warn!(
+                "No text column of {} matches the date or timestamp format",
+                table_name
+            );
+            return Ok(());
+        }
+
+        let df = self
+            .execute_query(&format!(
+                "SELECT {} FROM {}",
+                select.join(", "),
+                quote_ident(&sql::normalize_table_name(table_name))
+            ))
+            .await?;
+        self.replace_table(table_name, df)?;
+        debug!(
+            "Parsed date/timestamp columns {:?} of {}",
+            parsed, table_name
+        );
+        Ok(())
+    }
+
+    /// Whether `expr` evaluates over a sample of `column`'s non-NULL values
+    ///
+    /// Columns without any non-NULL value never match, and neither do columns
+    /// with a value `expr` cannot parse or cast.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the sample query fails for any other reason, such
+    /// as an unreadable file or an exhausted memory pool
+    async fn parses_sample(&self, table_name: &str, column: &str, expr: &str) -> Result<bool> {
+        let sql = format!(
+            "SELECT {} FROM {} WHERE {} IS NOT NULL LIMIT {}",
+            expr,
+            quote_ident(&sql::normalize_table_name(table_name)),
+            quote_ident(column),
+            TEMPORAL_SAMPLE_ROWS
+        );
+        match self.execute_query(&sql).await?.collect().await {
+            Ok(batches) => Ok(batches.iter().any(|batch| batch.num_rows() > 0)),
+            Err(err) if is_parse_error(&err) => Ok(false),
+            Err(err) => Err(SqlStreamError::execution(err)),
+        }
+    }
+
+    /// Re-register `table_name` as a view over `dataframe`
+    ///
+    /// Used by post-registration transformations that reshape a table while
+    /// keeping its name, so queries see the transformed columns.
+    fn replace_table(&mut self, table_name: &str, dataframe: DataFrame) -> Result<()> {
+        let view = dataframe.into_view();
+        self.ctx.deregister_table(table_name)?;
+        self.register_provider(table_name, view)
+    }
+
+    /// List the physical files a query will scan
+    ///
+    /// Plans the query and walks the physical plan for file scan nodes,
+    /// returning the files each scan touches after any pruning has been applied.
+    /// Only CSV, JSON, Parquet and Avro tables are scanned from their files;
+    /// Arrow IPC, ORC and Excel inputs are loaded into memory at registration,
+    /// so they are not listed.
+    ///
+    /// # Arguments
+    ///
+    /// * `sql` - SQL query string to plan
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if query parsing or physical planning fails
+    #[instrument(skip(self))]
+    pub async fn scanned_files(&self, sql: &str) -> Result<Vec<String>> {
+        let df = self.execute_query(sql).await?;
+        let plan = df.create_physical_plan().await?;
+
+        let mut files = Vec::new();
+        collect_scanned_files(plan.as_ref(), &mut files);
+        files.sort();
+        files.dedup();
+
+        debug!("Query scans {} file(s)", files.len());
+        Ok(files)
+    }
+
+    /// Count a DataFrame's result rows without materializing them
+    ///
+    /// The DataFrame's plan becomes the input of a `COUNT(*)` aggregate, the
+    /// equivalent of `SELECT COUNT(*) FROM (<query>)`, so filters and
+    /// groupings apply as written but no result columns are built or
+    /// formatted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if execution fails
+    #[instrument(skip(self, dataframe))]
+    pub async fn count_results(&self, dataframe: DataFrame) -> Result<usize> {
+        dataframe.count().await.map_err(SqlStreamError::execution)
+    }
+
+    /// Print a DataFrame's results to stdout in the given format
+    ///
+    /// # Arguments
+    ///
+    /// * `dataframe` - The DataFrame to print
+    /// * `format` - Text table, CSV, JSON array or newline-delimited JSON
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if execution or printing fails
+    #[instrument(skip(self, dataframe))]
+    pub async fn print_results(&self, dataframe: DataFrame, format: PrintFormat) -> Result<()> {
+        self.print_results_with(dataframe, format, &WriteOptions::default())
+            .await
+    }
+
+    /// Print a DataFrame's results to stdout using custom write options
+    ///
+    /// [`WriteOptions::null_value`] applies to CSV output,
+    /// [`WriteOptions::table_style`] to the table format,
+    /// [`WriteOptions::precision`] to both and [`WriteOptions::max_rows`] to
+    /// every format; the other options only concern files.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if execution or printing fails
+    #[instrument(skip(self, dataframe))]
+    pub async fn print_results_with(
+        &self,
+        dataframe: DataFrame,
+        format: PrintFormat,
+        options: &WriteOptions,
+    ) -> Result<()> {
+        info!("Printing results as {:?}", format);
+
+        let rows = self
+            .write_formatted_with(dataframe, format, BufWriter::new(io::stdout()), options)
+            .await?;
+        info!("Query returned {} rows", rows);
+
+        Ok(())
+    }
+
+    /// Write a DataFrame's results to `writer` in the given print format
+    ///
+    /// Results are streamed: batches are written as they are produced rather
+    /// than collected first. CSV and JSON formats use the same writers as
+    /// [`write_results`](Self::write_results); the table format is rendered in
+    /// windows (see [`output::write_table_stream`]). Returns the number of rows
+    /// written.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if execution fails or the output cannot be written
+    pub async fn write_formatted<W: Write + Send>(
+        &self,
+        dataframe: DataFrame,
+        format: PrintFormat,
+        writer: W,
+    ) -> Result<usize> {
+        self.write_formatted_with(dataframe, format, writer, &WriteOptions::default())
+            .await
+    }
+
+    /// Write a DataFrame's results to `writer` using custom write options
+    ///
+    /// Behaves like [`write_formatted`](Self::write_formatted), with the
+    /// options applied as in [`print_results_with`](Self::print_results_with).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if execution fails or the output cannot be written
+    pub async fn write_formatted_with<W: Write + Send>(
+        &self,
+        dataframe: DataFrame,
+        format: PrintFormat,
+        mut writer: W,
+        options: &WriteOptions,
+    ) -> Result<usize> {
+        let rows = match format.output_format() {
+            Some(output_format) => {
+                let mut stream = self
+                    .execute_capped(dataframe, options.max_rows, options.deadline)
+                    .await?;
+                if output_format == OutputFormat::Csv {
+                    stream = options.round_text(stream);
+                }
+                let csv_null = options.null_value.as_deref();
+                let rows = if options.pretty_json(output_format) {
+                    output::write_pretty_json(stream, output_format, &mut writer).await?
+                } else {
+                    output::write_stream_with(stream, output_format, &mut writer, csv_null).await?
+                };
+                // The JSON array writer leaves the closing bracket unterminated
+                if output_format == OutputFormat::Json {
+                    writeln!(writer)?;
+                }
+                rows
+            }
+            None => {
+                let stream = self
+                    .execute_capped(dataframe, options.max_rows, options.deadline)
+                    .await?;
+                let stream = options.round_text(stream);
+                output::write_table_stream_with(stream, &mut writer, &options.table_style).await?
+            }
+        };
+
+        writer.flush()?;
+        Ok(rows)
+    }
+
+    /// Stream a DataFrame's results into a file
+    ///
+    /// The output format is detected from the file extension (see
+    /// [`OutputFormat::from_path`]). Batches are written as they are produced
+    /// and the file is flushed after every batch. Missing parent directories
+    /// are created and an existing file is overwritten.
+    ///
+    /// # Arguments
+    ///
+    /// * `dataframe` - The DataFrame to write
+    /// * `path` - Destination file path
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the format is unsupported, the file cannot be created,
+    /// or execution fails while streaming
+    pub async fn write_results(&self, dataframe: DataFrame, path: &Path) -> Result<usize> {
+        let options = WriteOptions {
+            overwrite: true,
+            ..Default::default()
+        };
+        self.write_results_with(dataframe, path, &options).await
+    }
+
+    /// Stream a DataFrame's results into a file using custom write options
+    ///
+    /// Behaves like [`write_results`](Self::write_results), except that the
+    /// format may be given explicitly and an existing file is only replaced
+    /// when [`WriteOptions::overwrite`] is set.
+    ///
+    /// If writing fails, or the returned future is dropped before completing
+    /// (for example by `tokio::time::timeout`), the partly written file is
+    /// removed.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`write_results`](Self::write_results), plus
+    /// [`SqlStreamError::Output`] if the file exists and overwriting is off
+    #[instrument(skip(self, dataframe))]
+    pub async fn write_results_with(
+        &self,
+        dataframe: DataFrame,
+        path: &Path,
+        options: &WriteOptions,
+    ) -> Result<usize> {
+        let format = match options.format {
+            Some(format) => format,
+            None => OutputFormat::from_path(path)?,
+        };
+        let compression = options
+            .compression_for(path)
+            .unwrap_or(OutputCompression::None);
+        compression.check(format)?;
+        info!("Writing results to {} as {:?}", path.display(), format);
+
+        let mut stream = self
+            .execute_capped(dataframe, None, options.deadline)
+            .await?;
+        if format == OutputFormat::Csv {
+            stream = options.round_text(stream);
+        }
+        let file = create_output_file(path, options.overwrite)?;
+        let partial = PartialOutput::new(path);
+        let writer = IntervalFlush::new(BufWriter::new(file), options.flush_interval);
+        let rows = if options.pretty_json(format) {
+            let mut writer = CompressedWriter::new(writer, compression)?;
+            let rows = output::write_pretty_json(stream, format, &mut writer).await?;
+            writer.finish()?.flush()?;
+            rows
+        } else {
+            let null = options.null_value.as_deref();
+            output::write_stream_compressed(stream, format, writer, null, compression).await?
+        };
+        partial.complete();
+
+        info!("Wrote {} rows to {}", rows, path.display());
+        Ok(rows)
+    }
+
+    /// Convert a DataFrame's results into a file with DataFusion's own writers
+    ///
+    /// Unlike [`write_results_with`](Self::write_results_with), which streams
+    /// batches through sql-stream's writers, this hands the plan to
+    /// DataFusion's file sinks, which encode in parallel. Parquet is written
+    /// with zstd compression ([`PARQUET_COMPRESSION`]) unless
+    /// [`WriteOptions::compression`] picks another codec. Supported extensions
+    /// are `.parquet`, `.csv` and `.ndjson`/`.jsonl`, optionally followed by
+    /// `.gz` or `.zst` for the text formats; a `.json` target is rejected,
+    /// since DataFusion only writes newline-delimited JSON.
+    ///
+    /// [`WriteOptions::overwrite`], [`WriteOptions::compression`] and
+    /// [`WriteOptions::null_value`] (for CSV) apply; a partly written file is
+    /// removed on failure.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`SqlStreamError::UnsupportedFormat`] for other extensions,
+    /// [`SqlStreamError::InvalidOption`] for snappy with a text format,
+    /// [`SqlStreamError::Output`] if the file exists and overwriting is off,
+    /// or an error if execution or writing fails
+    #[instrument(skip(self, dataframe))]
+    pub async fn convert_results(
+        &self,
+        dataframe: DataFrame,
+        path: &Path,
+        options: &WriteOptions,
+    ) -> Result<usize> {
+        let format = OutputFormat::from_path(path)?;
+        if format == OutputFormat::Json {
+            return Err(SqlStreamError::UnsupportedFormat(
+                ".json (conversion writes newline-delimited JSON; use .ndjson or .jsonl)"
+                    .to_string(),
+            ));
+        }
+        let compression = options.compression_for(path);
+        if let Some(compression) = compression {
+            compression.check(format)?;
+        }
+        if path.exists() && !options.overwrite {
+            return Err(SqlStreamError::Output(format!(
+                "{} already exists",
+                path.display()
+            )));
+        }
+        info!("Converting results to {} as {:?}", path.display(), format);
+
+        let target = path.to_string_lossy();
+        let write_options = DataFrameWriteOptions::new().with_single_file_output(true);
+        let partial = PartialOutput::new(path);
+        let written = match format {
+            OutputFormat::Parquet => {
+                let mut parquet = TableParquetOptions::default();
+                let codec = compression.map_or(PARQUET_COMPRESSION, parquet_compression);
+                parquet.global.compression = Some(codec.to_string());
+                dataframe
+                    .write_parquet(&target, write_options, Some(parquet))
+                    .await
+            }
+            OutputFormat::Csv => {
+                let mut csv = CsvOptions::default()
+                    .with_has_header(true)
+                    .with_file_compression_type(text_compression(compression));
+                csv.null_value = options.null_value.clone();
+                dataframe.write_csv(&target, write_options, Some(csv)).await
+            }
+            OutputFormat::Ndjson | OutputFormat::Json => {
+                let json = JsonOptions {
+                    compression: text_compression(compression),
+                    ..Default::default()
+                };
+                dataframe
+                    .write_json(&target, write_options, Some(json))
+                    .await
+            }
+        }
+        .map_err(SqlStreamError::execution)?;
+        partial.complete();
+
+        // The sinks report the rows written as a single `count` value
+        let rows = written
+            .first()
+            .and_then(|batch| batch.column(0).as_primitive_opt::<UInt64Type>())
+            .map_or(0, |counts| counts.value(0) as usize);
+        info!("Wrote {} rows to {}", rows, path.display());
+        Ok(rows)
+    }
+
+    /// Write a DataFrame's results as a JSON object grouping rows by `column`
+    ///
+    /// Writes to `path` when given, otherwise prints to stdout. See
+    /// [`output::write_grouped_json`] for the layout.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the column does not exist, the file cannot be
+    /// created, or execution fails
+    #[instrument(skip(self, dataframe))]
+    pub async fn write_grouped_json(
+        &self,
+        dataframe: DataFrame,
+        column: &str,
+        path: Option<&Path>,
+        options: &WriteOptions,
+    ) -> Result<usize> {
+        let stream = self.execute_stream(dataframe).await?;
+        let rows = match path {
+            Some(path) => {
+                let writer = BufWriter::new(create_output_file(path, options.overwrite)?);
+                let partial = PartialOutput::new(path);
+                let rows = output::write_grouped_json(stream, column, writer).await?;
+                partial.complete();
+                rows
+            }
+            None => {
+                let mut buffer = Vec::new();
+                let rows = output::write_grouped_json(stream, column, &mut buffer).await?;
+                println!("{}", String::from_utf8_lossy(&buffer));
+                rows
+            }
+        };
+
+        info!("Wrote {} rows grouped by {}", rows, column);
+        Ok(rows)
+    }
+
+    /// Stream a DataFrame's results to a Kafka topic, one JSON message per row
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if execution fails or any message cannot be delivered
+    #[cfg(feature = "kafka")]
+    #[instrument(skip(self, dataframe))]
+    pub async fn publish_results(
+        &self,
+        dataframe: DataFrame,
+        target: &crate::kafka::KafkaTarget,
+    ) -> Result<usize> {
+        let stream = self.execute_stream(dataframe).await?;
+        crate::kafka::publish_stream(stream, target).await
+    }
+
+    /// Stream a DataFrame's results into a new table of a SQLite database
+    ///
+    /// The database file is created if needed; see [`crate::sqlite`] for how
+    /// Arrow types are stored.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if execution fails, the table already exists or a row
+    /// cannot be inserted
+    #[cfg(feature = "sqlite")]
+    #[instrument(skip(self, dataframe))]
+    pub async fn write_sqlite(
+        &self,
+        dataframe: DataFrame,
+        path: &Path,
+        table_name: &str,
+    ) -> Result<usize> {
+        let stream = self.execute_stream(dataframe).await?;
+        crate::sqlite::write_stream(stream, path, table_name).await
+    }
+}
+
+/// Reject table names no SQL can refer to, even quoted
+fn check_table_name(table_name: &str) -> Result<()> {
+    if table_name.trim().is_empty() {
+        return Err(SqlStreamError::TableRegistration(
+            table_name.to_string(),
+            format!(
+                "table names cannot be blank; try '{}'",
+                sql::suggest_table_name(table_name)
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Warn when a table will be known in SQL by something other than `table_name`,
+/// or only when quoted
+fn warn_table_name(table_name: &str) {
+    let normalized = sql::normalize_table_name(table_name);
+    if !sql::is_plain_ident(table_name) || sql::is_reserved_word(table_name) {
+        warn!(
+            "Table name '{}' must be double-quoted in SQL: {} (or register it as {})",
+            table_name,
+            quote_ident(&normalized),
+            sql::suggest_table_name(table_name)
+        );
+    } else if normalized != table_name {
+        warn!(
+            "Table name '{}' is folded to lower case; refer to it as {} or \"{}\"",
+            table_name, normalized, normalized
+        );
+    }
+}
+
+/// A file's name without its format and compression extensions
+fn file_stem_name(path: &Path) -> String {
+    let mut name = path.file_name().map(Path::new).unwrap_or(path);
+    if preprocess::compression(name).is_compressed() {
+        name = name.file_stem().map(Path::new).unwrap_or(name);
+    }
+    name.file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string()
+}
+
+/// A table name derived from `name` that is not in `taken`, which it is added to
+///
+/// Names are lower-cased and stripped of characters needing quotes; a name
+/// already taken gets the first free `_2`, `_3`, ... suffix.
+fn unique_table_name(name: &str, taken: &mut Vec<String>) -> String {
+    let base = sql::suggest_table_name(name).to_lowercase();
+    let mut unique = base.clone();
+    for suffix in 2.. {
+        if !taken.contains(&unique) {
+            break;
+        }
+        unique = format!("{}_{}", base, suffix);
+    }
+    if unique != base {
+        warn!(
+            "Table name '{}' for {} is taken; registering it as '{}'",
+            base, name, unique
+        );
+    }
+    taken.push(unique.clone());
+    unique
+}
+
+/// A schema of text columns named by the header of a CSV file without data rows
+///
+/// Returns `None` when the file has data rows, whose types are inferred as usual.
+fn header_only_schema(path: &Path, delimiter: u8) -> Result<Option<SchemaRef>> {
+    if !preprocess::csv_header_only(path)? {
+        return Ok(None);
+    }
+    debug!("{} has a header but no data rows", path.display());
+
+    let fields: Vec<Field> = preprocess::csv_first_record(path, delimiter)?
+        .unwrap_or_default()
+        .into_iter()
+        .map(|name| Field::new(name, DataType::Utf8, true))
+        .collect();
+    Ok(Some(Arc::new(Schema::new(fields))))
+}
+
+/// `schema` with the named columns retyped as text (see [`ReadOptions::text_columns`])
+///
+/// Names that are not columns of the schema are left for later steps to report.
+fn read_as_text(schema: SchemaRef, columns: &[String]) -> SchemaRef {
+    if !schema.fields().iter().any(|f| columns.contains(f.name())) {
+        return schema;
+    }
+    let fields: Vec<Field> = schema
+        .fields()
+        .iter()
+        .map(|field| {
+            if columns.contains(field.name()) {
+                field.as_ref().clone().with_data_type(DataType::Utf8)
+            } else {
+                field.as_ref().clone()
+            }
+        })
+        .collect();
+    Arc::new(Schema::new_with_metadata(fields, schema.metadata().clone()))
+}
+
+/// Non-NULL values checked against `--date-format` / `--timestamp-format`
+const TEMPORAL_SAMPLE_ROWS: usize = 1000;
+
+/// Extensions of the formats local files can be registered from
+const FILE_FORMATS: [&str; 11] = [
+    "csv", "tsv", "json", "parquet", "pqt", "avro", "arrow", "arrows", "feather", "orc", "xlsx",
+];
+
+/// DataFusion's planning errors for a table that isn't registered
+static MISSING_TABLE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?:table '(.+)' not found|No table named '(.+)')$").expect("valid regex")
+});
+
+/// Build the object store serving `url`
+///
+/// HTTP(S) stores are rooted at the URL's origin. S3 stores (with the `s3`
+/// feature) use the URL's host as the bucket and take credentials and region
+/// from the standard `AWS_*` environment variables, falling back to web
+/// identity or instance metadata credentials. Both use the timeouts of
+/// `options`, and retry failed requests up to its `open_retries` times.
+fn object_store_for(url: &Url, options: &ReadOptions) -> Result<Arc<dyn ObjectStore>> {
+    let client_options = ClientOptions::new()
+        .with_connect_timeout(options.connect_timeout())
+        .with_timeout(options.request_timeout());
+    let retry = RetryConfig {
+        max_retries: options.open_retries() as usize,
+        ..Default::default()
+    };
+    match url.scheme() {
+        "http" | "https" => {
+            // object_store refuses plain HTTP by default, but an http:// URL asks for it
+            let store = HttpBuilder::new()
+                .with_url(url.origin().ascii_serialization())
+                .with_client_options(client_options.with_allow_http(true))
+                .with_retry(retry)
+                .build()
+                .map_err(|e| object_store_error(url, e.to_string()))?;
+            Ok(Arc::new(store))
+        }
+        #[cfg(feature = "s3")]
+        "s3" => {
+            let bucket = url.host_str().ok_or_else(|| {
+                SqlStreamError::InvalidOption(format!("{} has no bucket name", url))
+            })?;
+            let store = object_store::aws::AmazonS3Builder::from_env()
+                .with_bucket_name(bucket)
+                .with_client_options(client_options)
+                .with_retry(retry)
+                .build()
+                .map_err(|e| object_store_error(url, e.to_string()))?;
+            Ok(Arc::new(store))
+        }
+        #[cfg(not(feature = "s3"))]
+        "s3" => Err(SqlStreamError::UnsupportedFormat(format!(
+            "{} (S3 support requires sql-stream to be built with the `s3` feature)",
+            url
+        ))),
+        scheme => Err(SqlStreamError::UnsupportedFormat(format!(
+            "{}:// URLs",
+            scheme
+        ))),
+    }
+}
+
+/// The error for a failed request for a remote file
+///
+/// Timeouts, which the HTTP client reports deep in the error's source chain,
+/// get a message naming the limits that were hit.
+fn remote_error(url: &Url, err: &object_store::Error, options: &ReadOptions) -> SqlStreamError {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(err);
+    while let Some(cause) = source {
+        if cause
+            .downcast_ref::<reqwest::Error>()
+            .is_some_and(reqwest::Error::is_timeout)
+        {
+            return object_store_error(
+                url,
+                format!(
+                    "timed out after {} retries (connect timeout {}s, request timeout {}s)",
+                    options.open_retries(),
+                    options.connect_timeout().as_secs_f64(),
+                    options.request_timeout().as_secs_f64()
+                ),
+            );
+        }
+        source = cause.source();
+    }
+    object_store_error(url, err.to_string())
+}
+
+/// The error for a failed object store request, by the kind of store
+fn object_store_error(url: &Url, detail: String) -> SqlStreamError {
+    if matches!(url.scheme(), "http" | "https") {
+        SqlStreamError::RemoteFetch {
+            url: url.to_string(),
+            detail,
+        }
+    } else {
+        SqlStreamError::ObjectStore {
+            url: url.to_string(),
+            detail,
+        }
+    }
+}
+
+/// Whether a format extension names CSV data
+fn is_csv(extension: &str) -> bool {
+    matches!(extension, "csv" | "tsv")
+}
+
+/// `schema` with every column read as text
+fn all_text(schema: &SchemaRef) -> SchemaRef {
+    let columns: Vec<String> = schema.fields().iter().map(|f| f.name().clone()).collect();
+    read_as_text(Arc::clone(schema), &columns)
+}
+
+/// Turn CSV fields matching `regex` into NULL and cast them to `schema`
+///
+/// DataFusion only applies [`CsvReadOptions::null_regex`] while inferring
+/// types, and its reader fails on a sentinel such as `NA` in a numeric column,
+/// so `dataframe` reads the columns of `schema` as text (see [`all_text`]).
+/// Other columns, such as partition columns, pass through unchanged.
+fn null_sentinels(
+    dataframe: DataFrame,
+    schema: &Schema,
+    regex: &str,
+) -> datafusion::error::Result<DataFrame> {
+    let columns = dataframe
+        .schema()
+        .fields()
+        .iter()
+        .map(|field| {
+            let column = ident(field.name());
+            match schema.field_with_name(field.name()) {
+                Ok(typed) => Ok(when(
+                    not(regexp_like(column.clone(), lit(regex), None)),
+                    cast(column, typed.data_type().clone()),
+                )
+                .end()?
+                .alias(field.name())),
+                Err(_) => Ok(column),
+            }
+        })
+        .collect::<datafusion::error::Result<Vec<_>>>()?;
+    dataframe.select(columns)
+}
+
+/// Whether `err` comes from a value that does not parse or cast
+///
+/// DataFusion reports unparseable `to_date`/`to_timestamp` input as an
+/// execution error, and Arrow casts as cast or parse errors.
+fn is_parse_error(err: &DataFusionError) -> bool {
+    matches!(
+        err.find_root(),
+        DataFusionError::Execution(_)
+            | DataFusionError::ArrowError(ArrowError::CastError(_) | ArrowError::ParseError(_), _)
+    )
+}
+
+/// Map a failure registering `path`, attributing data parse errors to the file
+///
+/// Readers report problems in the (possibly preprocessed) copy they read, so
+/// parse errors are re-labelled with the file the user passed in.
+fn registration_error(table_name: &str, path: &Path, err: DataFusionError) -> SqlStreamError {
+    let detail = match err.find_root() {
+        DataFusionError::ArrowError(
+            ArrowError::CsvError(detail)
+            | ArrowError::JsonError(detail)
+            | ArrowError::ParseError(detail),
+            _,
+        ) => Some(detail.clone()),
+        DataFusionError::ParquetError(err) => Some(err.to_string()),
+        _ => None,
+    };
+
+    match detail {
+        Some(detail) => SqlStreamError::ParseError {
+            file: path.to_path_buf(),
+            detail,
+        },
+        None => SqlStreamError::TableRegistration(table_name.to_string(), err.to_string()),
+    }
+}
+
+/// Parquet compression used by [`QueryEngine::convert_results`]
+///
+/// zstd at a low level compresses typical tabular data well while staying
+/// close to Snappy's speed.
+pub const PARQUET_COMPRESSION: &str = "zstd(3)";
+
+/// The `compression` setting of DataFusion's Parquet writer for a codec
+fn parquet_compression(compression: OutputCompression) -> &'static str {
+    match compression {
+        OutputCompression::None => "uncompressed",
+        OutputCompression::Gzip => "gzip(6)",
+        OutputCompression::Zstd => PARQUET_COMPRESSION,
+        OutputCompression::Snappy => "snappy",
+    }
+}
+
+/// DataFusion's compression of a converted CSV or JSON file
+///
+/// Snappy never gets here, as [`OutputCompression::check`] rejects it for text.
+fn text_compression(compression: Option<OutputCompression>) -> CompressionTypeVariant {
+    match compression {
+        Some(OutputCompression::Gzip) => CompressionTypeVariant::GZIP,
+        Some(OutputCompression::Zstd) => CompressionTypeVariant::ZSTD,
+        _ => CompressionTypeVariant::UNCOMPRESSED,
+    }
+}
+
+/// Removes a partly written output file unless the write is marked complete
+///
+/// The guard lives in the writing future, so it also fires when that future
+/// is cancelled rather than run to completion.
+struct PartialOutput<'a> {
+    path: &'a Path,
+    complete: bool,
+}
+
+impl<'a> PartialOutput<'a> {
+    fn new(path: &'a Path) -> Self {
+        Self {
+            path,
+            complete: false,
+        }
+    }
+
+    fn complete(mut self) {
+        self.complete = true;
+    }
+}
+
+impl Drop for PartialOutput<'_> {
+    fn drop(&mut self) {
+        if !self.complete {
+            debug!("Removing incomplete output {}", self.path.display());
+            let _ = std::fs::remove_file(self.path);
+        }
+    }
+}
+
+/// Create an output file, creating missing parent directories first
+///
+/// Unless `overwrite` is set, an existing file is left untouched and an error
+/// is returned.
+fn create_output_file(path: &Path, overwrite: bool) -> Result<File> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let mut open = OpenOptions::new();
+    open.write(true);
+    if overwrite {
+        open.create(true).truncate(true);
+    } else {
+        open.create_new(true);
+    }
+
+    open.open(path).map_err(|e| match e.kind() {
+        io::ErrorKind::AlreadyExists => {
+            SqlStreamError::Output(format!("{} already exists", path.display()))
+        }
+        _ => e.into(),
+    })
+}
+
+/// The extension of `path` with a leading dot, as DataFusion's readers expect
+///
+/// Readers only pick up files ending in their configured extension, so the
+/// file's own extension is passed through; this keeps `.tsv`, `.pqt` and
+/// upper-case extensions readable.
+fn dotted_extension(path: &str) -> String {
+    Path::new(path)
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default()
+}
+
+/// The delimiter to read a CSV file with: explicit, sniffed, or the extension's default
+fn csv_delimiter(file_path: &str, extension: &str, options: &ReadOptions) -> Result<u8> {
+    Ok(match options.delimiter {
+        Some(delimiter) => delimiter,
+        None if options.sniff_delimiter => {
+            let sniffed = preprocess::sniff_delimiter(Path::new(file_path))?;
+            let delimiter = sniffed.unwrap_or(b',');
+            info!("Detected delimiter {:?}", delimiter as char);
+            delimiter
+        }
+        None if extension.eq_ignore_ascii_case("tsv") => b'\t',
+        None => b',',
+    })
+}
+
+/// The format DataFusion reads a multi-file CSV table with
+///
+/// `file` decides the compression of every file.
+fn csv_format(delimiter: u8, file: &Path, options: &ReadOptions) -> CsvFormat {
+    let mut format = CsvFormat::default()
+        .with_delimiter(delimiter)
+        .with_quote(options.quote.unwrap_or(b'"'))
+        .with_has_header(!options.no_header)
+        .with_file_compression_type(preprocess::compression(file));
+    if let Some(rows) = options.infer_rows() {
+        format = format.with_schema_infer_max_rec(rows);
+    }
+    format.with_null_regex(options.null_regex())
+}
+
+/// Copy the reader `open` returns to a temporary `.extension` file on a blocking thread
+///
+/// Opening a named pipe waits for a writer, and reading a pipe or stdin waits
+/// for data; on the runtime's threads that would keep Ctrl+C and timeouts
+/// from ever being noticed.
+async fn spool_blocking<R: Read>(
+    open: impl FnOnce() -> std::io::Result<R> + Send + 'static,
+    extension: String,
+) -> Result<NamedTempFile> {
+    tokio::task::spawn_blocking(move || preprocess::spool(open()?, &extension))
+        .await
+        .map_err(std::io::Error::other)?
+}
+
+/// The end of a file name from its format extension on, such as `.csv.gz`
+///
+/// A partitioned table lists its directory, and only reads files with this suffix.
+fn listing_extension(file: &Path, extension: &str) -> String {
+    let name = file
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let dotted = format!(".{}", extension);
+    match name.to_ascii_lowercase().rfind(&dotted) {
+        Some(start) => name[start..].to_string(),
+        None => dotted,
+    }
+}
+
+/// The type of a partition column: Int64 if every value is a whole number, text otherwise
+fn partition_type(values: &[String]) -> DataType {
+    if values.iter().all(|value| value.parse::<i64>().is_ok()) {
+        DataType::Int64
+    } else {
+        DataType::Utf8
+    }
+}
+
+/// The lower-cased format extension shared by every file in `files`
+///
+/// Files must also agree on compression, since one reader decodes them all.
+fn common_extension(files: &[PathBuf]) -> Result<String> {
+    let extension_of = |file: &PathBuf| {
+        let extension = file_format(file)?;
+        check_compression(file, extension)?;
+        let suffix = file
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or_default();
+        Ok::<_, SqlStreamError>((extension.to_lowercase(), suffix.to_lowercase()))
+    };
+
+    let first = extension_of(&files[0])?;
+    for file in &files[1..] {
+        if extension_of(file)? != first {
+            return Err(SqlStreamError::InvalidOption(format!(
+                "{} and {} have different formats; a multi-file table needs a single format",
+                files[0].display(),
+                file.display()
+            )));
+        }
+    }
+    Ok(first.0)
+}
+
+/// The format extension of a local file, if it is one sql-stream reads
+fn file_format(path: &Path) -> Result<&str> {
+    let extension = preprocess::format_extension(path)
+        .ok_or_else(|| SqlStreamError::UnsupportedFormat(path.to_string_lossy().to_string()))?;
+    if !FILE_FORMATS.contains(&extension.to_lowercase().as_str()) {
+        return Err(SqlStreamError::UnsupportedFormat(extension.to_string()));
+    }
+    Ok(extension)
+}
+
+/// Reject compressed files of formats read without decompression
+///
+/// Only the text formats are decompressed; Parquet and Arrow IPC compress
+/// their contents internally instead.
+fn check_compression(path: &Path, extension: &str) -> Result<()> {
+    let text = matches!(extension.to_lowercase().as_str(), "csv" | "tsv" | "json");
+    if preprocess::compression(path).is_compressed() && !text {
+        return Err(SqlStreamError::UnsupportedFormat(format!(
+            "{} (only CSV and JSON files may be gzip or zstd compressed)",
+            path.display()
+        )));
+    }
+    Ok(())
+}
+
+/// Reject a declared schema for formats that carry their own
+fn check_declared_schema(extension: &str, options: &ReadOptions) -> Result<()> {
+    let text = matches!(extension.to_lowercase().as_str(), "csv" | "tsv" | "json");
+    if options.schema.is_some() && !text {
+        return Err(SqlStreamError::InvalidOption(format!(
+            "a schema can only be declared for CSV and JSON files, not .{}",
+            extension
+        )));
+    }
+    Ok(())
+}
+
+/// Check that a CSV file has as many columns as its declared schema
+///
+/// `file` is the file as given, for messages, and `source` the one read (a
+/// copy when a header pattern applies). Header names that differ from the
+/// declared ones are only logged, since the declared names win.
+fn check_declared_columns(
+    file: &Path,
+    source: &Path,
+    delimiter: u8,
+    schema: &SchemaRef,
+    options: &ReadOptions,
+) -> Result<()> {
+    let Some(record) = preprocess::csv_first_record(source, delimiter)? else {
+        return Ok(());
+    };
+
+    if record.len() != schema.fields().len() {
+        return Err(SqlStreamError::InvalidOption(format!(
+            "the schema declares {} columns but {} has {}",
+            schema.fields().len(),
+            file.display(),
+            record.len()
+        )));
+    }
+    if !options.no_header {
+        for (header, field) in record.iter().zip(schema.fields()) {
+            if header.trim() != field.name() {
+                warn!(
+                    "Column '{}' of {} is named '{}' by the declared schema",
+                    header.trim(),
+                    file.display(),
+                    field.name()
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check that two files of a multi-file table have the same columns and types
+fn check_same_columns(
+    first: &SchemaRef,
+    other: &SchemaRef,
+    first_file: &Path,
+    file: &Path,
+) -> Result<()> {
+    let columns = |schema: &SchemaRef| {
+        schema
+            .fields()
+            .iter()
+            .map(|field| format!("{}: {}", field.name(), field.data_type()))
+            .collect::<Vec<_>>()
+    };
+
+    let (expected, actual) = (columns(first), columns(other));
+    if expected != actual {
+        return Err(SqlStreamError::SchemaMismatch {
+            first: first_file.to_path_buf(),
+            file: file.to_path_buf(),
+            expected,
+            actual,
+        });
+    }
+    Ok(())
+}
+
+/// Magic bytes opening an Arrow IPC file (as opposed to a bare IPC stream)
+const ARROW_FILE_MAGIC: &[u8] = b"ARROW1";
+
+/// Read every batch of an Arrow IPC file or stream
+///
+/// The IPC file format (also used by Feather v2) is recognised by its magic
+/// bytes; anything else is read as an IPC stream. The schema comes from the
+/// file itself, so no inference takes place.
+fn read_arrow_ipc(path: &Path) -> Result<(SchemaRef, Vec<RecordBatch>)> {
+    let mut magic = [0u8; ARROW_FILE_MAGIC.len()];
+    let is_file_format = {
+        let mut file = File::open(path)?;
+        file.read_exact(&mut magic).is_ok() && magic == ARROW_FILE_MAGIC
+    };
+
+    let file = File::open(path)?;
+    if is_file_format {
+        let reader = FileReader::try_new(file, None)?;
+        let schema = reader.schema();
+        let batches = reader.collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok((schema, batches))
+    } else {
+        let reader = StreamReader::try_new(BufReader::new(file), None)?;
+        let schema = reader.schema();
+        let batches = reader.collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok((schema, batches))
+    }
+}
+
+/// Read every batch of an ORC file
+///
+/// Like Arrow IPC files, ORC files carry their own schema and are loaded into
+/// memory whole, with one record batch per stripe chunk.
+#[cfg(feature = "orc")]
+fn read_orc(path: &Path) -> datafusion::error::Result<(SchemaRef, Vec<RecordBatch>)> {
+    use datafusion::arrow::record_batch::RecordBatchReader;
+
+    let reader = orc_rust::ArrowReaderBuilder::try_new(File::open(path)?)
+        .map_err(|e| DataFusionError::External(Box::new(e)))?
+        .build();
+    let schema = reader.schema();
+    let batches = reader.collect::<std::result::Result<Vec<_>, _>>()?;
+    Ok((schema, batches))
+}
+
+/// Stand-in for [`read_orc`] when the `orc` feature is disabled
+#[cfg(not(feature = "orc"))]
+fn read_orc(_path: &Path) -> datafusion::error::Result<(SchemaRef, Vec<RecordBatch>)> {
+    Err(DataFusionError::Configuration(
+        "ORC input requires sql-stream to be built with the `orc` feature".to_string(),
+    ))
+}
+
+/// The listing format of a multi-file Avro table
+#[cfg(feature = "avro")]
+fn avro_format() -> Result<Arc<dyn FileFormat>> {
+    Ok(Arc::new(
+        datafusion::datasource::file_format::avro::AvroFormat,
+    ))
+}
+
+/// Stand-in for [`avro_format`] when the `avro` feature is disabled
+#[cfg(not(feature = "avro"))]
+fn avro_format() -> Result<Arc<dyn FileFormat>> {
+    Err(SqlStreamError::InvalidOption(
+        "Avro input requires sql-stream to be built with the `avro` feature".to_string(),
+    ))
+}
+
+/// Read a worksheet of an Excel workbook (see [`crate::excel`])
+#[cfg(feature = "excel")]
+fn read_xlsx(path: &Path, sheet: Option<&str>) -> Result<(SchemaRef, Vec<RecordBatch>)> {
+    crate::excel::read_sheet(path, sheet)
+}
+
+/// Names of the worksheets of an Excel workbook, in workbook order
+#[cfg(feature = "excel")]
+fn xlsx_sheet_names(path: &Path) -> Result<Vec<String>> {
+    crate::excel::sheet_names(path)
+}
+
+/// Stand-in for [`xlsx_sheet_names`] when the `excel` feature is disabled
+#[cfg(not(feature = "excel"))]
+fn xlsx_sheet_names(path: &Path) -> Result<Vec<String>> {
+    read_xlsx(path, None).map(|_| Vec::new())
+}
+
+/// Stand-in for [`read_xlsx`] when the `excel` feature is disabled
+#[cfg(not(feature = "excel"))]
+fn read_xlsx(_path: &Path, _sheet: Option<&str>) -> Result<(SchemaRef, Vec<RecordBatch>)> {
+    Err(SqlStreamError::InvalidOption(
+        "Excel input requires sql-stream to be built with the `excel` feature".to_string(),
+    ))
+}
+
+/// Recursively collect the file paths read by scan nodes in a physical plan
+fn collect_scanned_files(plan: &dyn ExecutionPlan, files: &mut Vec<String>) {
+    let any = plan.as_any();
+    let config = if let Some(exec) = any.downcast_ref::<CsvExec>() {
+        Some(exec.base_config())
+    } else if let Some(exec) = any.downcast_ref::<NdJsonExec>() {
+        Some(exec.base_config())
+    } else {
+        any.downcast_ref::<ParquetExec>()
+            .map(|exec| exec.base_config())
+    };
+    #[cfg(feature = "avro")]
+    let config = config.or_else(|| {
+        any.downcast_ref::<AvroExec>()
+            .map(|exec| exec.base_config())
+    });
+
+    if let Some(config) = config {
+        for group in &config.file_groups {
+            files.extend(
+                group
+                    .iter()
+                    .map(|file| file.object_meta.location.to_string()),
+            );
+        }
+    }
+
+    for child in plan.children() {
+        collect_scanned_files(child.as_ref(), files);
+    }
+}
+
+impl Default for QueryEngine {
+    fn default() -> Self {
+        Self::new().expect("Failed to create default QueryEngine")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_engine_creation() {
+        let engine = QueryEngine::new();
+        assert!(engine.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_target_partitions() {
+        let default_partitions = QueryEngine::new()
+            .unwrap()
+            .ctx
+            .state()
+            .config()
+            .target_partitions();
+
+        for (threads, expected) in [
+            (Some(3), 3),
+            (Some(0), default_partitions),
+            (None, default_partitions),
+        ] {
+            let config = EngineConfig {
+                target_partitions: threads,
+                ..Default::default()
+            };
+            let engine = QueryEngine::with_config(&config).unwrap();
+            assert_eq!(engine.ctx.state().config().target_partitions(), expected);
+        }
+    }
+
+    #[test]
+    fn test_deregister_table() {
+        let schema = Arc::new(Schema::new(vec![Field::new("id", DataType::Int64, false)]));
+        let mut engine = QueryEngine::new().unwrap();
+        for name in ["orders", "customers"] {
+            let table = MemTable::try_new(Arc::clone(&schema), vec![vec![]]).unwrap();
+            engine.register_provider(name, Arc::new(table)).unwrap();
+        }
+        assert_eq!(engine.list_tables(), ["customers", "orders"]);
+
+        engine.deregister_table("orders").unwrap();
+        assert_eq!(engine.list_tables(), ["customers"]);
+
+        match engine.deregister_table("orders") {
+            Err(SqlStreamError::TableNotFound { available, .. }) => {
+                assert_eq!(available, ["customers"])
+            }
+            other => panic!("expected TableNotFound, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_builder() {
+        let builder = QueryEngine::builder()
+            .memory_limit(1024)
+            .spill_dir("/tmp/spill")
+            .target_partitions(3)
+            .with_information_schema()
+            .preserve_case()
+            .cache_entries(4);
+        let config = builder.config();
+        assert_eq!(config.memory_limit, Some(1024));
+        assert_eq!(config.spill_dir.as_deref(), Some(Path::new("/tmp/spill")));
+        assert!(config.information_schema && config.preserve_case);
+        assert_eq!(config.cache_entries, Some(4));
+
+        let engine = EngineBuilder::new().target_partitions(3).build().unwrap();
+        let options = engine.ctx.state().config().options().clone();
+        assert_eq!(options.execution.target_partitions, 3);
+        assert!(!options.catalog.information_schema);
+    }
+
+    #[tokio::test]
+    async fn test_register_batches() {
+        use datafusion::arrow::array::{Int64Array, StringArray};
+        use datafusion::arrow::datatypes::{Field, Schema};
+
+        let ids = Arc::new(Schema::new(vec![Field::new("id", DataType::Int64, false)]));
+        let batch = |values: Vec<i64>| {
+            RecordBatch::try_new(ids.clone(), vec![Arc::new(Int64Array::from(values))]).unwrap()
+        };
+
+        let mut engine = QueryEngine::new().unwrap();
+        engine
+            .register_batches("ids", vec![batch(vec![1, 2]), batch(vec![3])])
+            .unwrap();
+        let rows: usize = engine
+            .collect_query("SELECT * FROM ids WHERE id > 1")
+            .await
+            .unwrap()
+            .iter()
+            .map(|b| b.num_rows())
+            .sum();
+        assert_eq!(rows, 2);
+
+        let names = Arc::new(Schema::new(vec![Field::new("name", DataType::Utf8, false)]));
+        let other =
+            RecordBatch::try_new(names, vec![Arc::new(StringArray::from(vec!["a"]))]).unwrap();
+        for batches in [vec![], vec![batch(vec![1]), other]] {
+            let result = engine.register_batches("mixed", batches);
+            assert!(matches!(result, Err(SqlStreamError::TableRegistration(..))));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let engine = QueryEngine::new().unwrap();
+        let result = engine
+            .run("SELECT n, CAST(n AS VARCHAR) AS label FROM (VALUES (1), (2), (3)) AS t(n) WHERE n > 1")
+            .await
+            .unwrap();
+
+        assert_eq!(result.row_count, 2);
+        assert_eq!(
+            result.batches.iter().map(|b| b.num_rows()).sum::<usize>(),
+            2
+        );
+        let names: Vec<_> = result.schema.fields().iter().map(|f| f.name()).collect();
+        assert_eq!(names, ["n", "label"]);
+        assert_eq!(result.schema.field(0).data_type(), &DataType::Int64);
+        assert_eq!(result.schema.field(1).data_type(), &DataType::Utf8);
+
+        // An empty result still has its schema
+        let result = engine
+            .run("SELECT n FROM (VALUES (1)) AS t(n) WHERE n > 5")
+            .await
+            .unwrap();
+        assert_eq!(result.row_count, 0);
+        assert_eq!(result.schema.fields().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_file_not_found() {
+        let mut engine = QueryEngine::new().unwrap();
+        let result = engine.register_file("nonexistent.csv", "test").await;
+        assert!(matches!(result, Err(SqlStreamError::FileNotFound(_))));
+    }
+
+    #[test]
+    fn test_is_parse_error() {
+        assert!(is_parse_error(&DataFusionError::Execution(
+            "Error parsing timestamp from '14/03/2024'".to_string()
+        )));
+        assert!(is_parse_error(&DataFusionError::ArrowError(
+            ArrowError::CastError("Cannot cast string 'abc'".to_string()),
+            None
+        )));
+
+        // Failures unrelated to the values are not parse errors
+        assert!(!is_parse_error(&DataFusionError::ResourcesExhausted(
+            "memory pool".to_string()
+        )));
+        assert!(!is_parse_error(&DataFusionError::IoError(
+            std::io::Error::other("disk failure")
+        )));
     }
 }
@@ -3,7 +3,10 @@
 //! This module defines all error types used throughout the library using `thiserror`
 //! for ergonomic error handling and proper error propagation.
 
+use datafusion::error::DataFusionError;
+use serde_json::{json, Value};
 use std::path::PathBuf;
+use std::time::Duration;
 use thiserror::Error;
 
 /// Main error type for SQL Stream operations
@@ -13,13 +16,40 @@ pub enum SqlStreamError {
     #[error("File not found: {0}")]
     FileNotFound(PathBuf),
 
+    /// An input file with no content to infer a schema from
+    #[error("{} is empty; there is no header or data to infer a schema from", .0.display())]
+    EmptyFile(PathBuf),
+
+    /// An input path that is neither a regular file, a directory nor a named pipe
+    #[error(
+        "{} is not a regular file; only files, directories and named pipes (FIFOs) can be read",
+        .0.display()
+    )]
+    UnsupportedSpecialFile(PathBuf),
+
+    /// An input file exists but could not be opened: access is denied, or it
+    /// stayed locked by another process through every retry
+    #[error("Cannot open {}: {detail}", .file.display())]
+    FileAccess { file: PathBuf, detail: String },
+
+    /// A config file that could not be parsed
+    #[error("Invalid config file {}: {detail}", .file.display())]
+    Config { file: PathBuf, detail: String },
+
     /// Invalid file format or extension
-    #[error("Unsupported file format: {0}. Supported formats: .csv, .json")]
+    #[error(
+        "Unsupported file format: {0}. Supported formats: .csv, .tsv, .json, .parquet (.pqt), \
+         .arrow (.arrows, .feather), .orc, .avro, .xlsx"
+    )]
     UnsupportedFormat(String),
 
     /// DataFusion-related errors
     #[error("DataFusion error: {0}")]
-    DataFusion(#[from] datafusion::error::DataFusionError),
+    DataFusion(datafusion::error::DataFusionError),
+
+    /// A query needed more memory than the configured limit allows, even after spilling
+    #[error("Query ran out of memory ({0}). Try a higher memory limit")]
+    MemoryExhausted(String),
 
     /// Arrow-related errors
     #[error("Arrow error: {0}")]
@@ -33,6 +63,19 @@ pub enum SqlStreamError {
     #[error("SQL execution failed: {0}")]
     QueryExecution(String),
 
+    /// SQL that parses but uses something DataFusion cannot plan or run yet,
+    /// such as `SELECT TOP`; holds the feature's name
+    #[error("Unsupported SQL feature: {0}")]
+    UnsupportedFeature(String),
+
+    /// A query ran longer than the configured timeout and was cancelled
+    #[error("Query timed out after {}s and was cancelled", .0.as_secs_f64())]
+    Timeout(Duration),
+
+    /// A query was cancelled through its cancellation token
+    #[error("Query was cancelled")]
+    Cancelled,
+
     /// Table registration errors
     #[error("Failed to register table '{0}': {1}")]
     TableRegistration(String, String),
@@ -40,7 +83,296 @@ pub enum SqlStreamError {
     /// Schema inference errors
     #[error("Failed to infer schema from file: {0}")]
     SchemaInference(String),
+
+    /// Files registered as one table inferred different columns
+    #[error(
+        "{} does not match the schema of {}: expected ({}), got ({})",
+        .file.display(), .first.display(), .expected.join(", "), .actual.join(", ")
+    )]
+    SchemaMismatch {
+        first: PathBuf,
+        file: PathBuf,
+        expected: Vec<String>,
+        actual: Vec<String>,
+    },
+
+    /// A remote input could not be fetched (unreachable host, timeout, non-success status)
+    #[error("Failed to fetch {url}: {detail}")]
+    RemoteFetch { url: String, detail: String },
+
+    /// An object store such as S3 rejected a request or could not be reached
+    #[error("Object store request for {url} failed: {detail}")]
+    ObjectStore { url: String, detail: String },
+
+    /// An input file's contents could not be parsed in its format
+    #[error("Failed to parse {}: {detail}", .file.display())]
+    ParseError { file: PathBuf, detail: String },
+
+    /// A `.json` file holding a JSON array that can't be read as records
+    #[error("{} looks like a JSON array rather than newline-delimited JSON, but {reason}", .file.display())]
+    JsonArray { file: PathBuf, reason: String },
+
+    /// No line matched the configured CSV header pattern
+    #[error("No line in {} matches header pattern '{pattern}'", .file.display())]
+    HeaderNotFound { file: PathBuf, pattern: String },
+
+    /// A referenced column does not exist
+    #[error("Column '{column}' not found. Available columns: {}", .available.join(", "))]
+    ColumnNotFound {
+        column: String,
+        available: Vec<String>,
+    },
+
+    /// A query refers to a table that is not registered
+    #[error("Table '{requested}' not found. Available tables: {}", .available.join(", "))]
+    TableNotFound {
+        requested: String,
+        available: Vec<String>,
+    },
+
+    /// Invalid option values
+    #[error("Invalid option: {0}")]
+    InvalidOption(String),
+
+    /// Result output errors
+    #[error("Failed to write output: {0}")]
+    Output(String),
+
+    /// Errors publishing results to an external sink such as Kafka
+    #[error("Failed to publish results: {0}")]
+    Sink(String),
+
+    /// Errors exporting results into a database such as SQLite
+    #[error("Failed to export results: {0}")]
+    Export(String),
+}
+
+impl SqlStreamError {
+    /// Convert an error raised while running a query
+    ///
+    /// Like the `From` conversion, but other errors become
+    /// [`QueryExecution`](Self::QueryExecution).
+    pub(crate) fn execution(err: DataFusionError) -> Self {
+        match err.find_root() {
+            DataFusionError::ResourcesExhausted(message) => Self::MemoryExhausted(message.clone()),
+            DataFusionError::NotImplemented(message) => Self::unsupported_feature(message),
+            _ => Self::QueryExecution(err.to_string()),
+        }
+    }
+
+    /// An [`UnsupportedFeature`](Self::UnsupportedFeature) error from a
+    /// DataFusion "not implemented" message
+    ///
+    /// Messages such as `Recursive CTEs are not enabled` or
+    /// `Unsupported SQL type Int128` are trimmed to the feature they name.
+    pub(crate) fn unsupported_feature(message: &str) -> Self {
+        let mut feature = message.trim().trim_end_matches('.');
+        for prefix in [
+            "This feature is not implemented: ",
+            "Unsupported ast node in sqltorel: ",
+            "Unsupported ",
+        ] {
+            feature = feature.strip_prefix(prefix).unwrap_or(feature);
+        }
+        for suffix in [
+            " is not supported yet",
+            " are not supported yet",
+            " is not supported",
+            " are not supported",
+            " not supported",
+            " is not enabled",
+            " are not enabled",
+        ] {
+            if let Some(stripped) = feature.strip_suffix(suffix) {
+                feature = stripped;
+                break;
+            }
+        }
+        Self::UnsupportedFeature(feature.to_string())
+    }
+
+    /// Stable name of the error's variant, such as `FileNotFound`
+    ///
+    /// Tools reading `--error-format json` output can match on it; it does not
+    /// change when the message wording does.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::FileNotFound(_) => "FileNotFound",
+            Self::EmptyFile(_) => "EmptyFile",
+            Self::UnsupportedSpecialFile(_) => "UnsupportedSpecialFile",
+            Self::FileAccess { .. } => "FileAccess",
+            Self::Config { .. } => "Config",
+            Self::UnsupportedFormat(_) => "UnsupportedFormat",
+            Self::DataFusion(_) => "DataFusion",
+            Self::MemoryExhausted(_) => "MemoryExhausted",
+            Self::Arrow(_) => "Arrow",
+            Self::Io(_) => "Io",
+            Self::QueryExecution(_) => "QueryExecution",
+            Self::UnsupportedFeature(_) => "UnsupportedFeature",
+            Self::Timeout(_) => "Timeout",
+            Self::Cancelled => "Cancelled",
+            Self::TableRegistration(..) => "TableRegistration",
+            Self::SchemaInference(_) => "SchemaInference",
+            Self::SchemaMismatch { .. } => "SchemaMismatch",
+            Self::RemoteFetch { .. } => "RemoteFetch",
+            Self::ObjectStore { .. } => "ObjectStore",
+            Self::ParseError { .. } => "ParseError",
+            Self::JsonArray { .. } => "JsonArray",
+            Self::HeaderNotFound { .. } => "HeaderNotFound",
+            Self::ColumnNotFound { .. } => "ColumnNotFound",
+            Self::TableNotFound { .. } => "TableNotFound",
+            Self::InvalidOption(_) => "InvalidOption",
+            Self::Output(_) => "Output",
+            Self::Sink(_) => "Sink",
+            Self::Export(_) => "Export",
+        }
+    }
+
+    /// The error's fields as a JSON object
+    ///
+    /// Paths, names and lists keep their own keys (`file`, `column`,
+    /// `available`, ...); variants wrapping a bare message or another error
+    /// put it under `message`.
+    pub fn detail(&self) -> Value {
+        match self {
+            Self::FileNotFound(path)
+            | Self::EmptyFile(path)
+            | Self::UnsupportedSpecialFile(path) => json!({ "file": path.display().to_string() }),
+            Self::FileAccess { file, detail }
+            | Self::Config { file, detail }
+            | Self::ParseError { file, detail } => {
+                json!({ "file": file.display().to_string(), "message": detail })
+            }
+            Self::JsonArray { file, reason } => {
+                json!({ "file": file.display().to_string(), "message": reason })
+            }
+            Self::HeaderNotFound { file, pattern } => {
+                json!({ "file": file.display().to_string(), "pattern": pattern })
+            }
+            Self::SchemaMismatch {
+                first,
+                file,
+                expected,
+                actual,
+            } => json!({
+                "first": first.display().to_string(),
+                "file": file.display().to_string(),
+                "expected": expected,
+                "actual": actual,
+            }),
+            Self::RemoteFetch { url, detail } | Self::ObjectStore { url, detail } => {
+                json!({ "url": url, "message": detail })
+            }
+            Self::ColumnNotFound { column, available } => {
+                json!({ "column": column, "available": available })
+            }
+            Self::TableNotFound {
+                requested,
+                available,
+            } => json!({ "table": requested, "available": available }),
+            Self::TableRegistration(table, message) => {
+                json!({ "table": table, "message": message })
+            }
+            Self::UnsupportedFeature(feature) => json!({ "feature": feature }),
+            Self::Timeout(limit) => json!({ "seconds": limit.as_secs_f64() }),
+            Self::Cancelled => json!({}),
+            Self::DataFusion(err) => json!({ "message": err.to_string() }),
+            Self::Arrow(err) => json!({ "message": err.to_string() }),
+            Self::Io(err) => json!({ "message": err.to_string() }),
+            Self::UnsupportedFormat(message)
+            | Self::MemoryExhausted(message)
+            | Self::QueryExecution(message)
+            | Self::SchemaInference(message)
+            | Self::InvalidOption(message)
+            | Self::Output(message)
+            | Self::Sink(message)
+            | Self::Export(message) => json!({ "message": message }),
+        }
+    }
+
+    /// The error as `{"error": message, "kind": kind, "detail": {...}}`
+    pub fn to_json(&self) -> Value {
+        json!({
+            "error": self.to_string(),
+            "kind": self.kind(),
+            "detail": self.detail(),
+        })
+    }
+}
+
+impl From<DataFusionError> for SqlStreamError {
+    fn from(err: DataFusionError) -> Self {
+        match err.find_root() {
+            DataFusionError::ResourcesExhausted(message) => Self::MemoryExhausted(message.clone()),
+            DataFusionError::NotImplemented(message) => Self::unsupported_feature(message),
+            _ => Self::DataFusion(err),
+        }
+    }
 }
 
 /// Type alias for Results using SqlStreamError
 pub type Result<T> = std::result::Result<T, SqlStreamError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_json() {
+        let err = SqlStreamError::FileNotFound(PathBuf::from("data/missing.csv"));
+        assert_eq!(
+            err.to_json(),
+            json!({
+                "error": "File not found: data/missing.csv",
+                "kind": "FileNotFound",
+                "detail": { "file": "data/missing.csv" },
+            })
+        );
+
+        let err = SqlStreamError::ColumnNotFound {
+            column: "agee".to_string(),
+            available: vec!["id".to_string(), "age".to_string()],
+        };
+        let value = err.to_json();
+        assert_eq!(value["kind"], "ColumnNotFound");
+        assert_eq!(
+            value["detail"],
+            json!({ "column": "agee", "available": ["id", "age"] })
+        );
+        assert_eq!(
+            value["error"],
+            "Column 'agee' not found. Available columns: id, age"
+        );
+
+        let err = SqlStreamError::Timeout(std::time::Duration::from_millis(1500));
+        assert_eq!(err.to_json()["detail"], json!({ "seconds": 1.5 }));
+        assert_eq!(SqlStreamError::Cancelled.to_json()["kind"], "Cancelled");
+        assert_eq!(
+            SqlStreamError::InvalidOption("bad".to_string()).to_json()["detail"],
+            json!({ "message": "bad" })
+        );
+    }
+
+    #[test]
+    fn test_unsupported_feature() {
+        let feature = |message: &str| match SqlStreamError::from(DataFusionError::NotImplemented(
+            message.to_string(),
+        )) {
+            SqlStreamError::UnsupportedFeature(feature) => feature,
+            other => panic!("expected UnsupportedFeature, got {:?}", other),
+        };
+        assert_eq!(feature("Recursive CTEs are not enabled"), "Recursive CTEs");
+        assert_eq!(feature("Unsupported SQL type Int128"), "SQL type Int128");
+        assert_eq!(feature("TOP"), "TOP");
+
+        // Wrapped errors are unwrapped to their root
+        let err = DataFusionError::Context(
+            "planning".to_string(),
+            Box::new(DataFusionError::NotImplemented("LATERAL VIEWS".to_string())),
+        );
+        assert!(matches!(
+            SqlStreamError::execution(err),
+            SqlStreamError::UnsupportedFeature(feature) if feature == "LATERAL VIEWS"
+        ));
+    }
+}
@@ -0,0 +1,269 @@
+//! Excel (`.xlsx`) input
+//!
+//! A worksheet is read into memory whole. Its first row names the columns and
+//! every later row becomes a record; empty rows after the last record are
+//! dropped. Each column gets the narrowest type fitting all of its non-empty
+//! cells: Int64 for whole numbers, Float64, Boolean or a millisecond Timestamp
+//! for dates. Columns whose cells disagree are read as text, and empty cells
+//! are NULL.
+
+use crate::error::{Result, SqlStreamError};
+use calamine::{open_workbook, Data, Range, Reader, Xlsx};
+use datafusion::arrow::array::{
+    ArrayRef, BooleanArray, Float64Array, Int64Array, StringArray, TimestampMillisecondArray,
+};
+use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use datafusion::arrow::record_batch::RecordBatch;
+use std::path::Path;
+use std::sync::Arc;
+use tracing::debug;
+
+/// Rows per record batch of a worksheet
+const BATCH_ROWS: usize = 8192;
+
+/// Read a worksheet of an `.xlsx` file as record batches
+///
+/// `sheet` names the worksheet; the first one is read when it is `None`.
+///
+/// # Errors
+///
+/// Returns [`SqlStreamError::ParseError`] if the workbook cannot be read or
+/// has no worksheets, or [`SqlStreamError::InvalidOption`] if it has no
+/// worksheet named `sheet`
+pub fn read_sheet(path: &Path, sheet: Option<&str>) -> Result<(SchemaRef, Vec<RecordBatch>)> {
+    let parse_error = |detail: String| SqlStreamError::ParseError {
+        file: path.to_path_buf(),
+        detail,
+    };
+
+    let mut workbook: Xlsx<_> = open_workbook(path).map_err(|e| parse_error(e.to_string()))?;
+    let range = match sheet {
+        Some(name) => {
+            let names = workbook.sheet_names();
+            if !names.iter().any(|candidate| candidate == name) {
+                return Err(SqlStreamError::InvalidOption(format!(
+                    "{} has no worksheet named '{}'; its worksheets are: {}",
+                    path.display(),
+                    name,
+                    names.join(", ")
+                )));
+            }
+            workbook.worksheet_range(name)
+        }
+        None => workbook
+            .worksheet_range_at(0)
+            .ok_or_else(|| parse_error("the workbook has no worksheets".to_string()))?,
+    }
+    .map_err(|e| parse_error(e.to_string()))?;
+
+    if range.is_empty() {
+        return Err(parse_error("the worksheet is empty".to_string()));
+    }
+    let (schema, batches) = range_batches(&range)?;
+    debug!(
+        "Read {} rows of {} columns from {}",
+        batches.iter().map(RecordBatch::num_rows).sum::<usize>(),
+        schema.fields().len(),
+        path.display()
+    );
+    Ok((schema, batches))
+}
+
+/// Names of the worksheets of an `.xlsx` file, in workbook order
+///
+/// # Errors
+///
+/// Returns [`SqlStreamError::ParseError`] if the workbook cannot be read
+pub fn sheet_names(path: &Path) -> Result<Vec<String>> {
+    let workbook: Xlsx<_> = open_workbook(path).map_err(|e| SqlStreamError::ParseError {
+        file: path.to_path_buf(),
+        detail: e.to_string(),
+    })?;
+    Ok(workbook.sheet_names())
+}
+
+/// Convert a worksheet range, header row first, into record batches
+fn range_batches(range: &Range<Data>) -> Result<(SchemaRef, Vec<RecordBatch>)> {
+    let mut rows = range.rows();
+    let header = rows.next().unwrap_or_default();
+    let mut records: Vec<&[Data]> = rows.collect();
+    while records
+        .last()
+        .is_some_and(|row| row.iter().all(|cell| matches!(cell, Data::Empty)))
+    {
+        records.pop();
+    }
+
+    let fields: Vec<Field> = header
+        .iter()
+        .enumerate()
+        .map(|(index, cell)| {
+            let name = match cell {
+                Data::Empty => format!("column_{}", index + 1),
+                cell => cell.to_string().trim().to_string(),
+            };
+            let cells = records
+                .iter()
+                .map(|row| row.get(index).unwrap_or(&Data::Empty));
+            Field::new(name, column_type(cells), true)
+        })
+        .collect();
+    let schema = Arc::new(Schema::new(fields));
+
+    let batches = records
+        .chunks(BATCH_ROWS)
+        .map(|chunk| {
+            let columns = schema
+                .fields()
+                .iter()
+                .enumerate()
+                .map(|(index, field)| {
+                    let cells = chunk
+                        .iter()
+                        .map(|row| row.get(index).unwrap_or(&Data::Empty));
+                    column_array(field.data_type(), cells)
+                })
+                .collect();
+            RecordBatch::try_new(Arc::clone(&schema), columns)
+        })
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    Ok((schema, batches))
+}
+
+/// The narrowest type holding every non-empty cell of a column
+fn column_type<'a>(cells: impl Iterator<Item = &'a Data>) -> DataType {
+    let mut data_type: Option<DataType> = None;
+    for cell in cells {
+        let cell_type = match cell {
+            Data::Empty => continue,
+            Data::Int(_) => DataType::Int64,
+            Data::Float(value) if whole_number(*value).is_some() => DataType::Int64,
+            Data::Float(_) => DataType::Float64,
+            Data::Bool(_) => DataType::Boolean,
+            Data::DateTime(value) if value.as_datetime().is_some() => {
+                DataType::Timestamp(TimeUnit::Millisecond, None)
+            }
+            _ => return DataType::Utf8,
+        };
+        data_type = match (data_type, cell_type) {
+            (None, cell_type) => Some(cell_type),
+            (Some(DataType::Int64), DataType::Float64)
+            | (Some(DataType::Float64), DataType::Int64) => Some(DataType::Float64),
+            (Some(current), cell_type) if current == cell_type => Some(current),
+            _ => return DataType::Utf8,
+        };
+    }
+    data_type.unwrap_or(DataType::Utf8)
+}
+
+/// Build the array of a column of cells typed by [`column_type`]
+fn column_array<'a>(data_type: &DataType, cells: impl Iterator<Item = &'a Data>) -> ArrayRef {
+    match data_type {
+        DataType::Int64 => Arc::new(
+            cells
+                .map(|cell| match cell {
+                    Data::Int(value) => Some(*value),
+                    Data::Float(value) => whole_number(*value),
+                    _ => None,
+                })
+                .collect::<Int64Array>(),
+        ),
+        DataType::Float64 => Arc::new(
+            cells
+                .map(|cell| match cell {
+                    Data::Int(value) => Some(*value as f64),
+                    Data::Float(value) => Some(*value),
+                    _ => None,
+                })
+                .collect::<Float64Array>(),
+        ),
+        DataType::Boolean => Arc::new(
+            cells
+                .map(|cell| match cell {
+                    Data::Bool(value) => Some(*value),
+                    _ => None,
+                })
+                .collect::<BooleanArray>(),
+        ),
+        DataType::Timestamp(..) => Arc::new(
+            cells
+                .map(|cell| match cell {
+                    Data::DateTime(value) => value
+                        .as_datetime()
+                        .map(|datetime| datetime.and_utc().timestamp_millis()),
+                    _ => None,
+                })
+                .collect::<TimestampMillisecondArray>(),
+        ),
+        _ => Arc::new(
+            cells
+                .map(|cell| match cell {
+                    Data::Empty => None,
+                    cell => Some(cell.to_string()),
+                })
+                .collect::<StringArray>(),
+        ),
+    }
+}
+
+/// A float that holds a whole number, as an integer
+///
+/// Excel stores every number as a float, so whole numbers are told apart by value.
+fn whole_number(value: f64) -> Option<i64> {
+    (value.fract() == 0.0 && value.abs() < 2f64.powi(53)).then_some(value as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::array::{Array, AsArray};
+    use datafusion::arrow::datatypes::Int64Type;
+
+    fn text(value: &str) -> Data {
+        Data::String(value.to_string())
+    }
+
+    #[test]
+    fn test_column_type() {
+        let column_of = |cells: &[Data]| column_type(cells.iter());
+        assert_eq!(
+            column_of(&[Data::Float(1.0), Data::Empty, Data::Int(3)]),
+            DataType::Int64
+        );
+        assert_eq!(
+            column_of(&[Data::Float(1.0), Data::Float(2.5)]),
+            DataType::Float64
+        );
+        assert_eq!(
+            column_of(&[Data::Bool(true), Data::Empty]),
+            DataType::Boolean
+        );
+        assert_eq!(column_of(&[Data::Float(1.0), text("n/a")]), DataType::Utf8);
+        assert_eq!(column_of(&[Data::Bool(true), Data::Int(1)]), DataType::Utf8);
+        assert_eq!(column_of(&[Data::Empty]), DataType::Utf8);
+    }
+
+    #[test]
+    fn test_range_batches() {
+        let mut range = Range::new((0, 0), (4, 2));
+        for (col, name) in ["id", "name"].into_iter().enumerate() {
+            range.set_value((0, col as u32), text(name));
+        }
+        range.set_value((1, 0), Data::Float(1.0));
+        range.set_value((1, 1), text("Alice"));
+        range.set_value((2, 0), Data::Float(2.0));
+        range.set_value((2, 2), Data::Float(0.5));
+        // Rows 3 and 4 stay empty and are dropped
+
+        let (schema, batches) = range_batches(&range).unwrap();
+        let names: Vec<_> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+        assert_eq!(names, ["id", "name", "column_3"]);
+        assert_eq!(schema.field(2).data_type(), &DataType::Float64);
+
+        assert_eq!(batches.len(), 1);
+        let batch = &batches[0];
+        assert_eq!(batch.num_rows(), 2);
+        assert_eq!(batch.column(0).as_primitive::<Int64Type>().value(1), 2);
+        assert!(batch.column(1).is_null(1));
+    }
+}
@@ -0,0 +1,146 @@
+//! Kafka output sink
+//!
+//! Publishes query results to a Kafka topic, one JSON message per row. Batches
+//! are produced as they stream out of DataFusion and every batch's deliveries are
+//! awaited before the next one is read, so memory stays bounded by a single batch.
+//!
+//! Producer settings beyond the broker address are read from environment variables
+//! prefixed with `SQL_STREAM_KAFKA_`. The remainder of the name is lowercased and
+//! underscores become dots, so `SQL_STREAM_KAFKA_SECURITY_PROTOCOL=SASL_SSL` sets
+//! `security.protocol=SASL_SSL`.
+
+use crate::error::{Result, SqlStreamError};
+use datafusion::arrow::json::LineDelimitedWriter;
+use datafusion::execution::SendableRecordBatchStream;
+use futures::future::join_all;
+use futures::StreamExt;
+use rdkafka::config::ClientConfig;
+use rdkafka::producer::{FutureProducer, FutureRecord, Producer};
+use rdkafka::util::Timeout;
+use std::time::Duration;
+use tracing::{debug, info};
+
+/// URL scheme identifying a Kafka output target
+pub const KAFKA_SCHEME: &str = "kafka://";
+
+/// Prefix for environment variables forwarded to the producer configuration
+const ENV_PREFIX: &str = "SQL_STREAM_KAFKA_";
+
+/// How long to wait for outstanding messages when flushing the producer
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A Kafka broker and topic parsed from a `kafka://broker/topic` URL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KafkaTarget {
+    /// Bootstrap broker list, e.g. `localhost:9092`
+    pub brokers: String,
+    /// Topic to publish rows to
+    pub topic: String,
+}
+
+impl KafkaTarget {
+    /// Parse a `kafka://broker[,broker...]/topic` URL
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the scheme is missing or the broker or topic is empty
+    pub fn parse(url: &str) -> Result<Self> {
+        let rest = url
+            .strip_prefix(KAFKA_SCHEME)
+            .ok_or_else(|| SqlStreamError::InvalidOption(format!("Not a Kafka URL: {}", url)))?;
+
+        match rest.split_once('/') {
+            Some((brokers, topic)) if !brokers.is_empty() && !topic.is_empty() => Ok(Self {
+                brokers: brokers.to_string(),
+                topic: topic.to_string(),
+            }),
+            _ => Err(SqlStreamError::InvalidOption(format!(
+                "Kafka output must look like kafka://broker/topic, got: {}",
+                url
+            ))),
+        }
+    }
+
+    /// Build a producer configuration for this target, applying environment overrides
+    fn client_config(&self) -> ClientConfig {
+        let mut config = ClientConfig::new();
+        config.set("bootstrap.servers", &self.brokers);
+
+        for (key, value) in std::env::vars() {
+            if let Some(name) = key.strip_prefix(ENV_PREFIX) {
+                let name = name.to_lowercase().replace('_', ".");
+                debug!("Setting Kafka producer option {}", name);
+                config.set(name, value);
+            }
+        }
+
+        config
+    }
+}
+
+/// Publish every row of a record batch stream to Kafka as a JSON message
+///
+/// Returns the number of messages delivered.
+///
+/// # Errors
+///
+/// Returns an error if the producer cannot be created, a batch cannot be encoded,
+/// or any message fails to be delivered
+pub async fn publish_stream(
+    mut stream: SendableRecordBatchStream,
+    target: &KafkaTarget,
+) -> Result<usize> {
+    let producer: FutureProducer = target
+        .client_config()
+        .create()
+        .map_err(|e| SqlStreamError::Sink(e.to_string()))?;
+
+    info!("Publishing results to Kafka topic '{}'", target.topic);
+
+    let mut delivered = 0;
+    while let Some(batch) = stream.next().await {
+        let batch = batch?;
+
+        let mut writer = LineDelimitedWriter::new(Vec::new());
+        writer.write(&batch)?;
+        writer.finish()?;
+        let payload = String::from_utf8(writer.into_inner())
+            .map_err(|e| SqlStreamError::Sink(e.to_string()))?;
+
+        let deliveries = payload.lines().map(|line| {
+            producer.send(
+                FutureRecord::<(), str>::to(&target.topic).payload(line),
+                Timeout::Never,
+            )
+        });
+
+        for result in join_all(deliveries).await {
+            result.map_err(|(e, _)| SqlStreamError::Sink(e.to_string()))?;
+            delivered += 1;
+        }
+        debug!("Delivered {} messages so far", delivered);
+    }
+
+    producer
+        .flush(Timeout::After(FLUSH_TIMEOUT))
+        .map_err(|e| SqlStreamError::Sink(e.to_string()))?;
+
+    info!("Delivered {} messages to '{}'", delivered, target.topic);
+    Ok(delivered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_target() {
+        let target = KafkaTarget::parse("kafka://localhost:9092/events").unwrap();
+        assert_eq!(target.brokers, "localhost:9092");
+        assert_eq!(target.topic, "events");
+
+        assert!(KafkaTarget::parse("kafka://localhost:9092").is_err());
+        assert!(KafkaTarget::parse("kafka:///events").is_err());
+        assert!(KafkaTarget::parse("http://localhost/events").is_err());
+    }
+}
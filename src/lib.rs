@@ -1,13 +1,13 @@
-//! SQL Stream - A production-grade CLI tool for querying CSV/JSON files with SQL
+//! SQL Stream - A production-grade CLI tool for querying data files with SQL
 //!
 //! This library provides a high-performance SQL query engine built on Apache DataFusion
-//! and Apache Arrow for executing SQL queries against CSV and JSON files using a zero-copy,
-//! streaming architecture.
+//! and Apache Arrow for executing SQL queries against CSV, TSV, JSON, Parquet, Arrow IPC,
+//! ORC, Avro and Excel files using a zero-copy, streaming architecture.
 //!
 //! # Example
 //!
 //! ```no_run
-//! use sql_stream::{QueryEngine, SqlStreamError};
+//! use sql_stream::{PrintFormat, QueryEngine, SqlStreamError};
 //!
 //! #[tokio::main]
 //! async fn main() -> Result<(), SqlStreamError> {
@@ -15,16 +15,46 @@
 //!     engine.register_file("data.csv", "my_table").await?;
 //!     
 //!     let results = engine.execute_query("SELECT * FROM my_table").await?;
-//!     engine.print_results(results).await?;
+//!     engine.print_results(results, PrintFormat::Table).await?;
 //!     Ok(())
 //! }
 //! ```
+//!
+//! Custom data sources can be plugged in by implementing DataFusion's
+//! `TableProvider` and passing it to [`QueryEngine::register_provider`], and
+//! custom SQL functions by building a [`ScalarUDF`] with [`create_udf`] and
+//! passing it to [`QueryEngine::register_udf`].
 
+pub mod analysis;
 pub mod cli;
+pub mod config;
+pub mod ddl;
 pub mod engine;
 pub mod error;
+#[cfg(feature = "excel")]
+pub mod excel;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod output;
+pub mod preprocess;
+pub mod repl;
+pub mod schema;
+pub mod sql;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod transform;
 
 // Re-export key types for library consumers
 pub use cli::CliArgs;
-pub use engine::QueryEngine;
+pub use datafusion::arrow::record_batch::RecordBatch;
+pub use datafusion::execution::SendableRecordBatchStream;
+pub use datafusion::logical_expr::{create_udf, ColumnarValue, ScalarUDF, Volatility};
+pub use engine::{
+    CacheStats, CacheStatus, EngineBuilder, EngineConfig, InputFormat, MemoryStats, QueryEngine,
+    ReadOptions, WriteOptions,
+};
 pub use error::SqlStreamError;
+pub use output::{
+    OutputCompression, OutputFormat, PrintFormat, QueryResult, QueryTimings, TableStyle,
+};
+pub use tokio_util::sync::CancellationToken;
@@ -4,42 +4,82 @@
 //! It handles initialization, signal handling, and orchestrates the query execution.
 
 use anyhow::{Context, Result};
-use sql_stream::{CliArgs, QueryEngine};
+use clap::{CommandFactory, FromArgMatches};
+use datafusion::prelude::DataFrame;
+use sql_stream::cli::{Command, ConvertArgs, DescribeArgs, ErrorFormat, QueryArgs, SchemaArgs};
+use sql_stream::transform::{self, RollingSpec};
+use sql_stream::{
+    analysis, ddl, output, sql, CancellationToken, CliArgs, QueryEngine, ReadOptions,
+    SqlStreamError, WriteOptions,
+};
+use std::future::Future;
+use std::time::{Duration, Instant};
 use tokio::signal;
-use tracing::{error, info, warn};
+use tracing::{error, info, info_span, warn, Instrument};
+use tracing_subscriber::fmt::format::FmtSpan;
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 
-#[tokio::main]
-async fn main() -> Result<()> {
+fn main() -> Result<()> {
+    let runtime = tokio::runtime::Runtime::new().context("Failed to start the async runtime")?;
+    let result = runtime.block_on(run_cli());
+    // After a cancellation a blocking read of a pipe or stdin may still be
+    // waiting for its writer, so don't wait for it on the way out
+    runtime.shutdown_background();
+    result
+}
+
+/// Parse the arguments, run the selected mode and report how it went
+async fn run_cli() -> Result<()> {
     // Parse CLI arguments
-    let args = CliArgs::parse();
+    let matches = <CliArgs as CommandFactory>::command().get_matches();
+    let mut args = CliArgs::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
 
     // Initialize tracing/logging based on verbosity
     init_tracing(args.verbose);
 
     info!("SQL Stream CLI starting");
 
+    // Options the command line leaves unset come from the config file
+    if let Err(e) = args.apply_config(&matches) {
+        if args.error_format == ErrorFormat::Json {
+            exit_with_json(&SqlStreamError::InvalidOption(e).into());
+        }
+        error!("Configuration error: {}", e);
+        anyhow::bail!("{}", e);
+    }
+
     // Validate CLI arguments
     if let Err(e) = args.validate() {
+        if args.error_format == ErrorFormat::Json {
+            exit_with_json(&SqlStreamError::InvalidOption(e).into());
+        }
         error!("Validation error: {}", e);
         anyhow::bail!("{}", e);
     }
 
     // Setup graceful shutdown handler
-    let shutdown_handle = tokio::spawn(async {
-        match signal::ctrl_c().await {
-            Ok(()) => {
-                warn!("Received shutdown signal (Ctrl+C), terminating gracefully...");
-                std::process::exit(0);
-            }
-            Err(err) => {
-                error!("Failed to listen for shutdown signal: {}", err);
+    let shutdown = CancellationToken::new();
+    let shutdown_handle = tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            match signal::ctrl_c().await {
+                Ok(()) => {
+                    warn!("Received shutdown signal (Ctrl+C), cancelling the query...");
+                    shutdown.cancel();
+                }
+                Err(err) => {
+                    error!("Failed to listen for shutdown signal: {}", err);
+                }
             }
         }
     });
 
-    // Run the query
-    let result = run_query(&args).await;
+    // Run the query; cancelling drops it, which stops execution and removes
+    // any partly written output file, like a timeout does
+    let result = tokio::select! {
+        result = run(&args) => result,
+        () = shutdown.cancelled() => Err(SqlStreamError::Cancelled.into()),
+    };
 
     // Abort shutdown handler if query completes normally
     shutdown_handle.abort();
@@ -49,6 +89,7 @@ async fn main() -> Result<()> {
             info!("Query executed successfully");
             Ok(())
         }
+        Err(e) if args.error_format == ErrorFormat::Json => exit_with_json(&e),
         Err(e) => {
             error!("Query execution failed: {}", e);
             Err(e)
@@ -56,51 +97,568 @@ async fn main() -> Result<()> {
     }
 }
 
-/// Execute the SQL query against the provided file
-async fn run_query(args: &CliArgs) -> Result<()> {
+/// Print a failure to stderr as one JSON object and exit with status 1
+///
+/// `kind` and `detail` come from the [`SqlStreamError`] behind the failure,
+/// or are `Other` and `{}` for failures of the CLI itself. `error` holds the
+/// whole message, including what was being done when it failed.
+fn exit_with_json(err: &anyhow::Error) -> ! {
+    let (kind, detail) = match err.downcast_ref::<SqlStreamError>() {
+        Some(e) => (e.kind(), e.detail()),
+        None => ("Other", serde_json::json!({})),
+    };
+    let report = serde_json::json!({
+        "error": format!("{:#}", err),
+        "kind": kind,
+        "detail": detail,
+    });
+    eprintln!("{}", report);
+    std::process::exit(1);
+}
+
+/// Terminal width assumed for charts when `COLUMNS` is not set
+const DEFAULT_TERMINAL_WIDTH: usize = 80;
+
+/// Register the input file and run the selected mode
+async fn run(args: &CliArgs) -> Result<()> {
     // Create query engine
-    let mut engine = QueryEngine::new().context("Failed to initialize query engine")?;
+    let mut engine = QueryEngine::with_config(&args.engine_config())
+        .context("Failed to initialize query engine")?;
 
-    // Register the file as a table
-    engine
-        .register_file(
-            args.file.to_str().context("Invalid file path")?,
-            &args.table_name,
-        )
+    // Single-table modes act on the first table
+    let table_name = register_inputs(&mut engine, args)
+        .instrument(info_span!("registration"))
+        .await?;
+    let table_name = table_name.as_str();
+
+    if args.schema_only {
+        let skipped = match &args.command {
+            Some(Command::Schema(_)) => false,
+            Some(_) => true,
+            None => args.query.query.is_some() || args.query.query_file.is_some(),
+        };
+        if skipped {
+            warn!("--schema-only is set; printing the schema without running the query");
+        }
+    }
+
+    // The input NULL regex doubles as the literal written for NULLs in CSV output
+    let null_value = args.input.null_value.as_deref();
+    match args.command() {
+        Command::Query(query) => {
+            // With --timeout-partial the results stream stops at the deadline instead
+            let timeout = query.timeout.filter(|_| !query.timeout_partial);
+            with_timeout(timeout, run_query(&engine, table_name, &query, null_value)).await?;
+        }
+        Command::Convert(convert) => run_convert(&engine, table_name, &convert, null_value).await?,
+        Command::Schema(schema) => run_schema(&engine, &schema).await?,
+        Command::Describe(describe) => run_describe(&engine, table_name, &describe).await?,
+        Command::Repl(repl) => {
+            let stdin = tokio::io::BufReader::new(tokio::io::stdin());
+            let options = ReadOptions {
+                replace: repl.replace,
+                ..args.read_options()
+            };
+            sql_stream::repl::run(
+                &mut engine,
+                stdin,
+                &mut std::io::stdout(),
+                repl.format,
+                args.cache_status,
+                &options,
+            )
+            .await?;
+        }
+    }
+
+    if args.memory_report {
+        eprintln!("{}", engine.memory_stats().summary());
+    }
+    Ok(())
+}
+
+/// Register stdin or each input file as a table, then apply the column options
+///
+/// Returns the name of the first table.
+async fn register_inputs(engine: &mut QueryEngine, args: &CliArgs) -> Result<String> {
+    let read_options = args.read_options();
+    if let (true, Some(format)) = (args.stdin, args.input_format) {
+        engine
+            .register_reader(
+                std::io::stdin(),
+                format,
+                args.primary_table(),
+                &read_options,
+            )
+            .await
+            .context("Failed to register stdin")?;
+
+        info!("Registered stdin as table '{}'", args.primary_table());
+    }
+
+    // Post-registration options act on the first table
+    let table_name = if args.auto_name {
+        let mut first = None;
+        for file in &args.files {
+            let path = file.to_str().context("Invalid file path")?;
+            let names = engine
+                .register_each(path, &read_options)
+                .await
+                .with_context(|| format!("Failed to register {}", path))?;
+            info!("Registered '{}' as tables: {}", path, names.join(", "));
+            first = first.or(names.into_iter().next());
+        }
+        first.context("No tables were registered")?
+    } else {
+        // Files are independent, so their schemas are inferred concurrently
+        let tables = args.tables();
+        let descriptors = tables
+            .iter()
+            .map(|(file, table_name)| {
+                let path = file.to_str().context("Invalid file path")?;
+                Ok((path, table_name.as_str()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        engine
+            .register_all(&descriptors, &read_options)
+            .await
+            .context("Failed to register input files")?;
+        for (file, table_name) in &descriptors {
+            info!("Registered file '{}' as table '{}'", file, table_name);
+        }
+        args.primary_table().to_string()
+    };
+
+    if !args.input.cast.is_empty() {
+        engine
+            .cast_columns(&table_name, &args.input.cast)
+            .await
+            .context("Failed to cast columns")?;
+    }
+
+    if !args.input.parse_numeric.is_empty() {
+        engine
+            .parse_numeric(
+                &table_name,
+                &args.input.parse_numeric,
+                args.input.numeric_locale,
+            )
+            .await
+            .context("Failed to parse numeric columns")?;
+    }
+
+    if args.input.date_format.is_some() || args.input.timestamp_format.is_some() {
+        engine
+            .parse_temporal(
+                &table_name,
+                args.input.date_format.as_deref(),
+                args.input.timestamp_format.as_deref(),
+            )
+            .await
+            .context("Failed to parse date and timestamp columns")?;
+    }
+
+    if !args.input.dictionary_encode.is_empty() {
+        engine
+            .dictionary_encode(&table_name, &args.input.dictionary_encode)
+            .await
+            .context("Failed to dictionary-encode columns")?;
+    }
+
+    // Last, so the options above refer to columns by their names in the file
+    if !args.input.rename.is_empty() {
+        engine
+            .rename_columns(&table_name, &args.input.rename)
+            .await
+            .context("Failed to rename columns")?;
+    }
+    Ok(table_name)
+}
+
+/// Run `task`, cancelling it once it has run for `seconds`
+///
+/// Cancelling drops the task's future, which stops DataFusion's execution and
+/// removes any partly written output file.
+async fn with_timeout(seconds: Option<u64>, task: impl Future<Output = Result<()>>) -> Result<()> {
+    let Some(seconds) = seconds else {
+        return task.await;
+    };
+
+    let limit = Duration::from_secs(seconds);
+    tokio::time::timeout(limit, task)
         .await
-        .context("Failed to register file")?;
+        .map_err(|_| SqlStreamError::Timeout(limit))?
+}
+
+/// Execute a SQL query and print or write its results
+async fn run_query(
+    engine: &QueryEngine,
+    table_name: &str,
+    args: &QueryArgs,
+    null_value: Option<&str>,
+) -> Result<()> {
+    // With --timeout-partial only the streamed results stop quietly at the deadline
+    let partial = args
+        .timeout
+        .filter(|_| args.timeout_partial)
+        .map(|seconds| {
+            let limit = Duration::from_secs(seconds);
+            (Instant::now() + limit, limit)
+        });
+    let script = args
+        .sql(table_name)
+        .context("Failed to read query file")?
+        .context("A query is required")?;
+    let script = sql::substitute_params(&script, &args.params)?;
+
+    let mut query = before_deadline(partial, prepare_query(engine, args, &script))
+        .instrument(info_span!("planning"))
+        .await?;
+
+    if args.dry_run {
+        let mut dataframe = before_deadline(partial, async {
+            engine.plan_query(&query).await.context("Invalid query")
+        })
+        .await?;
+        if let Some(column) = &args.unnest {
+            dataframe = transform::unnest(dataframe, column).context("Failed to unnest column")?;
+        }
+
+        let table = analysis::schema_table(dataframe.schema().as_arrow())?;
+        println!("{}", output::format_table(&[table])?);
+        info!("Query is valid");
+        return Ok(());
+    }
+
+    if args.explain_files {
+        let files = before_deadline(partial, async {
+            engine
+                .scanned_files(&query)
+                .await
+                .context("Failed to plan query")
+        })
+        .await?;
+
+        for file in &files {
+            println!("{}", file);
+        }
+        info!("Query scans {} file(s)", files.len());
+        return Ok(());
+    }
+
+    if args.explain {
+        query = sql::explain(&query, args.analyze);
+    }
+
+    if let Some(repeat) = args.repeat {
+        let timings = engine
+            .benchmark_query(&query, args.warmup as usize, repeat as usize)
+            .await
+            .context("Failed to benchmark query")?;
+        eprintln!("{}", timings.summary());
+    }
 
-    info!(
-        "Registered file '{}' as table '{}'",
-        args.file.display(),
-        args.table_name
-    );
+    let started = Instant::now();
 
-    // Execute the query
-    let dataframe = engine
-        .execute_query(&args.query)
+    // Batches are written as they are produced, so execution and output share a span
+    async {
+        let mut dataframe = before_deadline(partial, async {
+            engine
+                .execute_query(&query)
+                .await
+                .context("Failed to execute query")
+        })
+        .await?;
+
+        if let Some(column) = &args.unnest {
+            dataframe = transform::unnest(dataframe, column).context("Failed to unnest column")?;
+        }
+
+        if args.count {
+            let rows = engine
+                .count_results(dataframe)
+                .await
+                .context("Failed to count rows")?;
+            println!("{}", rows);
+            Ok(())
+        } else {
+            write_query_results(engine, args, null_value, dataframe, partial).await
+        }
+    }
+    .instrument(info_span!("execution"))
+    .await?;
+
+    if args.stats {
+        eprintln!("{}", engine.stream_stats().summary(started.elapsed()));
+    }
+    Ok(())
+}
+
+/// Run `task` unless the `--timeout-partial` deadline, `limit` after the start, passes first
+///
+/// The results stream ends with the rows produced by the deadline, but any
+/// other step still running then fails with a timeout, as under `--timeout`.
+async fn before_deadline<T>(
+    partial: Option<(Instant, Duration)>,
+    task: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    let Some((deadline, limit)) = partial else {
+        return task.await;
+    };
+    tokio::time::timeout_at(deadline.into(), task)
+        .await
+        .map_err(|_| SqlStreamError::Timeout(limit))?
+}
+
+/// Run a script's leading statements and build the final query from its last one
+async fn prepare_query(engine: &QueryEngine, args: &QueryArgs, script: &str) -> Result<String> {
+    // Leading statements (views, settings) run now; options apply to the last one
+    let mut query = engine
+        .prepare_script(script)
         .await
         .context("Failed to execute query")?;
 
-    // Print results
+    if let (Some(spec), Some(order_by)) = (&args.rolling, &args.rolling_order) {
+        let spec = RollingSpec::parse(spec)?;
+        let schema = engine
+            .execute_query(&query)
+            .await
+            .context("Failed to plan query")?
+            .schema()
+            .as_arrow()
+            .clone();
+        query = transform::rolling_query(&query, &schema, &spec, order_by)
+            .context("Failed to build rolling window")?;
+    }
+
+    if !args.columns.is_empty() {
+        let schema = engine
+            .execute_query(&query)
+            .await
+            .context("Failed to plan query")?
+            .schema()
+            .as_arrow()
+            .clone();
+        query = transform::select_columns(&query, &schema, &args.columns)?;
+    }
+    Ok(query)
+}
+
+/// Print the query results, or write them to the --output file or topic
+async fn write_query_results(
+    engine: &QueryEngine,
+    args: &QueryArgs,
+    null_value: Option<&str>,
+    dataframe: DataFrame,
+    partial: Option<(Instant, Duration)>,
+) -> Result<()> {
+    let write_options = WriteOptions {
+        format: args.output_format(),
+        flush_interval: Duration::from_millis(args.flush_interval_ms),
+        overwrite: args.force,
+        null_value: null_value.map(str::to_string),
+        table_style: args.table_style(),
+        precision: args.precision,
+        max_rows: args.max_rows,
+        pretty: args.pretty,
+        compression: args.output_compression,
+        deadline: partial.map(|(deadline, _)| deadline),
+    };
+
+    if let Some(column) = &args.group_json {
+        engine
+            .write_grouped_json(dataframe, column, args.output.as_deref(), &write_options)
+            .await
+            .context("Failed to write grouped JSON")?;
+        return Ok(());
+    }
+
+    if let Some(path) = &args.convert_to {
+        engine
+            .convert_results(dataframe, path, &write_options)
+            .await
+            .context("Failed to convert results")?;
+        return Ok(());
+    }
+
+    #[cfg(feature = "sqlite")]
+    if let (Some(path), Some(table)) = (&args.to_sqlite, &args.to_table) {
+        before_deadline(partial, async {
+            engine
+                .write_sqlite(dataframe, path, table)
+                .await
+                .context("Failed to write results to SQLite")
+        })
+        .await?;
+        return Ok(());
+    }
+
+    #[cfg(feature = "kafka")]
+    if let Some(output) = args.output.as_ref().and_then(|p| p.to_str()) {
+        if output.starts_with(sql_stream::kafka::KAFKA_SCHEME) {
+            let target = sql_stream::kafka::KafkaTarget::parse(output)?;
+            before_deadline(partial, async {
+                engine
+                    .publish_results(dataframe, &target)
+                    .await
+                    .context("Failed to publish results to Kafka")
+            })
+            .await?;
+            return Ok(());
+        }
+    }
+
+    if let Some(output) = &args.output {
+        engine
+            .write_results_with(dataframe, output, &write_options)
+            .await
+            .context("Failed to write results")?;
+    } else {
+        engine
+            .print_results_with(dataframe, args.format, &write_options)
+            .await
+            .context("Failed to print results")?;
+
+        if let (Some(max_rows), true) = (args.max_rows, engine.stream_stats().truncated) {
+            eprintln!(
+                "Warning: showing the first {} rows; raise --max-rows or add a LIMIT to see more",
+                max_rows
+            );
+        }
+    }
+
+    let stats = engine.stream_stats();
+    if stats.timed_out {
+        eprintln!("partial result: timed out after {} rows", stats.rows);
+    }
+    Ok(())
+}
+
+/// Write the whole table (or a query over it) straight to the output file
+async fn run_convert(
+    engine: &QueryEngine,
+    table_name: &str,
+    args: &ConvertArgs,
+    null_value: Option<&str>,
+) -> Result<()> {
+    let dataframe = match &args.query {
+        Some(query) => engine.execute_script(query).await,
+        None => engine.table(table_name).await,
+    }
+    .context("Failed to read input")?;
+
+    let write_options = WriteOptions {
+        overwrite: args.force,
+        null_value: null_value.map(str::to_string),
+        compression: args.output_compression,
+        ..Default::default()
+    };
     engine
-        .print_results(dataframe)
+        .write_results_with(dataframe, &args.output, &write_options)
         .await
-        .context("Failed to print results")?;
+        .context("Failed to convert file")?;
+    Ok(())
+}
+
+/// Print every registered table's inferred schema, or CREATE TABLE DDL for it
+async fn run_schema(engine: &QueryEngine, args: &SchemaArgs) -> Result<()> {
+    let table_names = engine.list_tables();
+    for (i, table_name) in table_names.iter().enumerate() {
+        let schema = engine
+            .table_schema(table_name)
+            .await
+            .with_context(|| format!("Failed to read schema of table '{}'", table_name))?;
 
+        if i > 0 {
+            println!();
+        }
+        match args.ddl {
+            Some(dialect) => println!("{}", ddl::create_table(&schema, table_name, dialect)),
+            None => {
+                if table_names.len() > 1 {
+                    println!("Table: {}", table_name);
+                }
+                let table = analysis::schema_table(&schema)?;
+                println!("{}", output::format_table(&[table])?);
+            }
+        }
+    }
     Ok(())
 }
 
-/// Initialize tracing subscriber with appropriate log level
-fn init_tracing(verbose: bool) {
-    let filter = if verbose {
-        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("debug"))
+/// Run the exploratory analysis selected in `args`
+async fn run_describe(engine: &QueryEngine, table_name: &str, args: &DescribeArgs) -> Result<()> {
+    if let Some(column) = &args.chart {
+        let frequencies = analysis::value_frequencies(engine, table_name, column, args.chart_top)
+            .await
+            .context("Failed to compute value frequencies")?;
+
+        let width = std::env::var("COLUMNS")
+            .ok()
+            .and_then(|c| c.parse().ok())
+            .unwrap_or(DEFAULT_TERMINAL_WIDTH);
+        println!("{}", analysis::render_bar_chart(&frequencies, width));
+    } else if let Some(columns) = &args.corr {
+        let value = analysis::correlation(engine, table_name, &columns[0], &columns[1])
+            .await
+            .context("Failed to compute correlation")?;
+
+        match value {
+            Some(value) => println!("{}", value),
+            None => println!("NULL"),
+        }
+    } else if args.corr_matrix {
+        let matrix = analysis::correlation_matrix(engine, table_name)
+            .await
+            .context("Failed to compute correlation matrix")?;
+
+        println!("{}", output::format_table(&[matrix])?);
+    } else if args.profile {
+        let profile = analysis::profile(engine, table_name)
+            .await
+            .context("Failed to profile columns")?;
+
+        println!("{}", output::format_table(&[profile])?);
+    } else if let Some(columns) = &args.find_duplicates {
+        let duplicates = analysis::find_duplicates(engine, table_name, columns)
+            .await
+            .context("Failed to find duplicates")?;
+
+        println!("{}", output::format_table(&duplicates)?);
+        let keys: usize = duplicates.iter().map(|b| b.num_rows()).sum();
+        if keys > 0 {
+            // Fail so the check can gate CI pipelines
+            anyhow::bail!("Found {} duplicated key(s)", keys);
+        }
+    }
+    Ok(())
+}
+
+/// Initialize tracing subscriber with the log level of `-v` given `verbosity` times
+///
+/// No flag logs at info level, `-v` at debug and `-vv` at trace. `-vvv` also
+/// logs each span as it closes with the time it took, which times the
+/// registration, planning and execution stages.
+fn init_tracing(verbosity: u8) {
+    let level = match verbosity {
+        0 => "info",
+        1 => "debug",
+        _ => "trace",
+    };
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(level));
+    let span_events = if verbosity >= 3 {
+        FmtSpan::CLOSE
     } else {
-        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"))
+        FmtSpan::NONE
     };
 
     tracing_subscriber::registry()
-        .with(fmt::layer().with_target(false))
+        .with(
+            fmt::layer()
+                .with_writer(std::io::stderr)
+                .with_target(false)
+                .with_span_events(span_events),
+        )
         .with(filter)
         .init();
 }
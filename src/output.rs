@@ -0,0 +1,1388 @@
+//! Output writers for query results
+//!
+//! This module streams query results into files using Arrow's CSV, JSON and
+//! Parquet writers. Batches are written as they arrive from DataFusion so the
+//! full result set never needs to be held in memory.
+
+use crate::analysis::require_column;
+use crate::error::{Result, SqlStreamError};
+use clap::ValueEnum;
+use datafusion::arrow::array::{ArrayRef, AsArray, StringArray};
+use datafusion::arrow::compute::cast;
+use datafusion::arrow::csv::WriterBuilder as CsvWriterBuilder;
+use datafusion::arrow::datatypes::{DataType, Field, Float64Type, Schema, SchemaRef};
+use datafusion::arrow::error::ArrowError;
+use datafusion::arrow::json::{ArrayWriter, LineDelimitedWriter};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::arrow::util::display::{ArrayFormatter, FormatOptions};
+use datafusion::arrow::util::pretty::pretty_format_batches;
+use datafusion::execution::SendableRecordBatchStream;
+use datafusion::parquet::arrow::ArrowWriter;
+use datafusion::parquet::basic::{Compression, GzipLevel, ZstdLevel};
+use datafusion::parquet::file::properties::WriterProperties;
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use flate2::write::GzEncoder;
+use futures::StreamExt;
+use serde_json::{Map, Value};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// Rows and batches streamed out of a [`QueryEngine`](crate::QueryEngine)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamStats {
+    /// Rows across all streamed batches
+    pub rows: usize,
+    /// Record batches streamed
+    pub batches: usize,
+    /// Some result was cut short by a row cap (see
+    /// [`WriteOptions::max_rows`](crate::WriteOptions::max_rows))
+    pub truncated: bool,
+    /// Some result was cut short by a deadline (see
+    /// [`WriteOptions::deadline`](crate::WriteOptions::deadline))
+    pub timed_out: bool,
+}
+
+impl StreamStats {
+    /// A one-line summary such as `10 rows in 2 batches (0.012s)`
+    pub fn summary(&self, elapsed: Duration) -> String {
+        let plural = |n: usize| if n == 1 { "" } else { "s" };
+        format!(
+            "{} row{} in {} batch{} ({:.3}s)",
+            self.rows,
+            plural(self.rows),
+            self.batches,
+            if self.batches == 1 { "" } else { "es" },
+            elapsed.as_secs_f64()
+        )
+    }
+}
+
+/// The collected result of a query, with its schema and timing
+///
+/// Returned by [`QueryEngine::run`](crate::QueryEngine::run).
+#[derive(Debug, Clone)]
+pub struct QueryResult {
+    /// Schema of the result, known even when no rows come back
+    pub schema: SchemaRef,
+    /// The result rows
+    pub batches: Vec<RecordBatch>,
+    /// Total rows across `batches`
+    pub row_count: usize,
+    /// Time taken to plan and execute the query
+    pub elapsed: Duration,
+}
+
+/// Durations of repeated runs of a query
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryTimings {
+    /// Duration of each measured run, in run order
+    pub runs: Vec<Duration>,
+}
+
+impl QueryTimings {
+    /// The shortest run
+    pub fn min(&self) -> Option<Duration> {
+        self.runs.iter().min().copied()
+    }
+
+    /// The longest run
+    pub fn max(&self) -> Option<Duration> {
+        self.runs.iter().max().copied()
+    }
+
+    /// The average run
+    pub fn mean(&self) -> Option<Duration> {
+        let count = u32::try_from(self.runs.len()).ok().filter(|&n| n > 0)?;
+        Some(self.runs.iter().sum::<Duration>() / count)
+    }
+
+    /// The middle run, or the average of the two middle runs for an even count
+    pub fn median(&self) -> Option<Duration> {
+        let mut sorted = self.runs.clone();
+        sorted.sort();
+        let middle = sorted.len() / 2;
+        match sorted.len() {
+            0 => None,
+            n if n % 2 == 1 => Some(sorted[middle]),
+            _ => Some((sorted[middle - 1] + sorted[middle]) / 2),
+        }
+    }
+
+    /// A one-line summary such as `5 runs: min 0.010s, median 0.012s, mean 0.013s, max 0.020s`
+    pub fn summary(&self) -> String {
+        let seconds = |d: Option<Duration>| d.unwrap_or_default().as_secs_f64();
+        format!(
+            "{} run{}: min {:.3}s, median {:.3}s, mean {:.3}s, max {:.3}s",
+            self.runs.len(),
+            if self.runs.len() == 1 { "" } else { "s" },
+            seconds(self.min()),
+            seconds(self.median()),
+            seconds(self.mean()),
+            seconds(self.max())
+        )
+    }
+}
+
+/// Counters updated as batches pass through the streams it wraps
+#[derive(Debug, Default)]
+pub(crate) struct StreamCounter {
+    rows: AtomicUsize,
+    batches: AtomicUsize,
+    truncated: AtomicBool,
+    timed_out: AtomicBool,
+}
+
+impl StreamCounter {
+    /// Wrap `stream` so every batch it yields is counted
+    pub(crate) fn count(
+        self: &Arc<Self>,
+        stream: SendableRecordBatchStream,
+    ) -> SendableRecordBatchStream {
+        let counter = Arc::clone(self);
+        let schema = stream.schema();
+        let counted = stream.inspect(move |batch| {
+            if let Ok(batch) = batch {
+                counter.rows.fetch_add(batch.num_rows(), Ordering::Relaxed);
+                counter.batches.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+        Box::pin(RecordBatchStreamAdapter::new(schema, counted))
+    }
+
+    /// Wrap `stream` so it yields at most `max_rows` rows
+    ///
+    /// Rows past the cap are dropped and mark the counter as truncated, so
+    /// the stream should come from a plan limited to `max_rows + 1` rows: one
+    /// extra row is enough to know the result was cut short.
+    pub(crate) fn cap(
+        self: &Arc<Self>,
+        stream: SendableRecordBatchStream,
+        max_rows: usize,
+    ) -> SendableRecordBatchStream {
+        let counter = Arc::clone(self);
+        let schema = stream.schema();
+        let mut remaining = max_rows;
+        let capped = stream.map(move |batch| {
+            batch.map(|batch| {
+                if batch.num_rows() <= remaining {
+                    remaining -= batch.num_rows();
+                    return batch;
+                }
+                counter.truncated.store(true, Ordering::Relaxed);
+                let kept = batch.slice(0, remaining);
+                remaining = 0;
+                kept
+            })
+        });
+        Box::pin(RecordBatchStreamAdapter::new(schema, capped))
+    }
+
+    /// Wrap `stream` so it ends at `deadline`, keeping the batches yielded before it
+    ///
+    /// A stream still running at the deadline marks the counter as timed out;
+    /// dropping its remainder stops the query's execution.
+    pub(crate) fn deadline(
+        self: &Arc<Self>,
+        stream: SendableRecordBatchStream,
+        deadline: Instant,
+    ) -> SendableRecordBatchStream {
+        let counter = Arc::clone(self);
+        let schema = stream.schema();
+        let expired = async move {
+            tokio::time::sleep_until(deadline.into()).await;
+            counter.timed_out.store(true, Ordering::Relaxed);
+        };
+        Box::pin(RecordBatchStreamAdapter::new(
+            schema,
+            stream.take_until(expired),
+        ))
+    }
+
+    /// The totals counted so far
+    pub(crate) fn stats(&self) -> StreamStats {
+        StreamStats {
+            rows: self.rows.load(Ordering::Relaxed),
+            batches: self.batches.load(Ordering::Relaxed),
+            truncated: self.truncated.load(Ordering::Relaxed),
+            timed_out: self.timed_out.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Render floating-point columns of a stream as text rounded to `precision` decimals
+///
+/// Float16, Float32 and Float64 columns become Utf8 columns formatted like
+/// `70333.33`; NULLs stay NULL and every other column passes through
+/// unchanged. This only changes how values print, so use it for text outputs
+/// (tables and CSV) and leave JSON and Parquet exact.
+pub fn round_floats(
+    stream: SendableRecordBatchStream,
+    precision: usize,
+) -> SendableRecordBatchStream {
+    let schema = stream.schema();
+    let fields: Vec<Field> = schema
+        .fields()
+        .iter()
+        .map(|field| {
+            if field.data_type().is_floating() {
+                field.as_ref().clone().with_data_type(DataType::Utf8)
+            } else {
+                field.as_ref().clone()
+            }
+        })
+        .collect();
+    let rounded_schema = Arc::new(Schema::new_with_metadata(fields, schema.metadata().clone()));
+
+    let output_schema = Arc::clone(&rounded_schema);
+    let rounded = stream.map(move |batch| -> datafusion::error::Result<RecordBatch> {
+        let batch = batch?;
+        let columns = batch
+            .columns()
+            .iter()
+            .map(|column| round_column(column, precision))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(RecordBatch::try_new(Arc::clone(&output_schema), columns)?)
+    });
+    Box::pin(RecordBatchStreamAdapter::new(rounded_schema, rounded))
+}
+
+/// Format a floating-point column to `precision` decimals, passing others through
+fn round_column(column: &ArrayRef, precision: usize) -> std::result::Result<ArrayRef, ArrowError> {
+    if !column.data_type().is_floating() {
+        return Ok(Arc::clone(column));
+    }
+
+    let values = cast(column, &DataType::Float64)?;
+    let text: StringArray = values
+        .as_primitive::<Float64Type>()
+        .iter()
+        .map(|value| value.map(|v| format!("{:.*}", precision, v)))
+        .collect();
+    Ok(Arc::new(text))
+}
+
+/// Supported output file formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Comma-separated values with a header row
+    Csv,
+    /// A single JSON array of objects
+    Json,
+    /// Newline-delimited JSON, one object per line
+    Ndjson,
+    /// Apache Parquet columnar format
+    Parquet,
+}
+
+impl OutputFormat {
+    /// Detect the output format from a file extension
+    ///
+    /// Supported extensions: `.csv`, `.json`, `.ndjson`, `.jsonl`, `.parquet`.
+    /// A `.gz` or `.zst` suffix on a text format is looked past, so
+    /// `out.csv.gz` is CSV (see [`OutputCompression::from_path`]).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the path has no extension or the extension is not
+    /// supported, including a compressed Parquet file name
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let unsupported = || SqlStreamError::UnsupportedFormat(path.to_string_lossy().to_string());
+        let compressed = OutputCompression::from_path(path).is_some();
+        let named = if compressed {
+            Path::new(path.file_stem().ok_or_else(unsupported)?)
+        } else {
+            path
+        };
+        let extension = named
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .ok_or_else(unsupported)?;
+
+        match extension.to_lowercase().as_str() {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            "ndjson" | "jsonl" => Ok(Self::Ndjson),
+            "parquet" if compressed => Err(SqlStreamError::UnsupportedFormat(format!(
+                "{} (Parquet compresses its columns itself; use --output-compression instead)",
+                path.display()
+            ))),
+            "parquet" => Ok(Self::Parquet),
+            _ => Err(SqlStreamError::UnsupportedFormat(extension.to_string())),
+        }
+    }
+}
+
+/// Compression of written result files
+///
+/// Text formats are compressed as a whole stream through a
+/// [`CompressedWriter`]; Parquet compresses each column chunk with the
+/// matching codec instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputCompression {
+    /// No compression
+    None,
+    /// gzip, conventionally named `.gz`
+    Gzip,
+    /// Zstandard, conventionally named `.zst`
+    Zstd,
+    /// Snappy, a Parquet codec only
+    Snappy,
+}
+
+impl OutputCompression {
+    /// The compression named by a `.gz` or `.zst` file extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "gz" => Some(Self::Gzip),
+            "zst" => Some(Self::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Check that files in `format` can be written with this compression
+    ///
+    /// # Errors
+    ///
+    /// Returns [`SqlStreamError::InvalidOption`] for snappy with a text format
+    pub fn check(self, format: OutputFormat) -> Result<()> {
+        if self == Self::Snappy && format != OutputFormat::Parquet {
+            return Err(SqlStreamError::InvalidOption(format!(
+                "snappy compression is only available for Parquet output, not {:?}; use gzip or zstd",
+                format
+            )));
+        }
+        Ok(())
+    }
+
+    /// The codec Parquet column chunks are compressed with
+    pub fn parquet_codec(self) -> Compression {
+        match self {
+            Self::None => Compression::UNCOMPRESSED,
+            Self::Gzip => Compression::GZIP(GzipLevel::default()),
+            Self::Zstd => Compression::ZSTD(ZstdLevel::default()),
+            Self::Snappy => Compression::SNAPPY,
+        }
+    }
+}
+
+/// A writer compressing text output as a gzip or zstd stream
+///
+/// The compressed stream is only complete once [`finish`](Self::finish) has
+/// been called; dropping the writer before then leaves a truncated file.
+pub enum CompressedWriter<W: Write> {
+    /// Writes pass through unchanged
+    Plain(W),
+    /// Writes are gzip compressed
+    Gzip(GzEncoder<W>),
+    /// Writes are zstd compressed
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> CompressedWriter<W> {
+    /// Wrap `writer` in the stream encoder for `compression`
+    ///
+    /// [`OutputCompression::Snappy`] has no stream encoding and writes through
+    /// unchanged; [`OutputCompression::check`] rejects it for text formats.
+    ///
+    /// # Errors
+    ///
+    /// Returns an IO error if the zstd encoder cannot be created
+    pub fn new(writer: W, compression: OutputCompression) -> Result<Self> {
+        Ok(match compression {
+            OutputCompression::Gzip => {
+                Self::Gzip(GzEncoder::new(writer, flate2::Compression::default()))
+            }
+            // Level 0 picks zstd's default level
+            OutputCompression::Zstd => Self::Zstd(zstd::Encoder::new(writer, 0)?),
+            OutputCompression::None | OutputCompression::Snappy => Self::Plain(writer),
+        })
+    }
+
+    /// Write the end of the compressed stream, returning the inner writer
+    ///
+    /// # Errors
+    ///
+    /// Returns an IO error if the trailer cannot be written
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Self::Plain(writer) => Ok(writer),
+            Self::Gzip(encoder) => encoder.finish(),
+            Self::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for CompressedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(writer) => writer.write(buf),
+            Self::Gzip(encoder) => encoder.write(buf),
+            Self::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(writer) => writer.flush(),
+            Self::Gzip(encoder) => encoder.flush(),
+            Self::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Formats for printing query results to stdout
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum PrintFormat {
+    /// Aligned text table
+    #[default]
+    Table,
+    /// Comma-separated values with a header row
+    Csv,
+    /// A single JSON array of objects
+    Json,
+    /// Newline-delimited JSON, one object per line
+    Ndjson,
+}
+
+impl PrintFormat {
+    /// The streaming writer format backing this print format
+    ///
+    /// Returns `None` for [`PrintFormat::Table`], which has no file equivalent.
+    pub fn output_format(self) -> Option<OutputFormat> {
+        match self {
+            Self::Table => None,
+            Self::Csv => Some(OutputFormat::Csv),
+            Self::Json => Some(OutputFormat::Json),
+            Self::Ndjson => Some(OutputFormat::Ndjson),
+        }
+    }
+}
+
+/// A writer that flushes its inner writer at most once per interval
+///
+/// Each write flushes if at least `interval` has passed since the last flush,
+/// so an interval of zero flushes on every write (at least once per batch).
+/// Longer intervals trade latency for fewer syscalls when output is consumed
+/// while it is being written. The final flush happens on drop or an explicit
+/// [`flush`](Write::flush).
+pub struct IntervalFlush<W: Write> {
+    inner: W,
+    interval: Duration,
+    last_flush: Instant,
+}
+
+impl<W: Write> IntervalFlush<W> {
+    /// Wrap `inner`, flushing it at most every `interval`
+    pub fn new(inner: W, interval: Duration) -> Self {
+        Self {
+            inner,
+            interval,
+            last_flush: Instant::now(),
+        }
+    }
+}
+
+impl<W: Write> Write for IntervalFlush<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if self.last_flush.elapsed() >= self.interval {
+            self.flush()?;
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()?;
+        self.last_flush = Instant::now();
+        Ok(())
+    }
+}
+
+impl<W: Write> Drop for IntervalFlush<W> {
+    fn drop(&mut self) {
+        let _ = self.inner.flush();
+    }
+}
+
+/// Render batches as an aligned text table
+///
+/// Results without any rows render as `(0 rows)` rather than an empty frame.
+///
+/// # Errors
+///
+/// Returns an error if a value cannot be formatted
+pub fn format_table(batches: &[RecordBatch]) -> Result<String> {
+    format_table_with(batches, &TableStyle::default())
+}
+
+/// Presentation options for text table output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TableStyle {
+    /// Widest a cell may be, in characters; longer values and column names are
+    /// cut short and end with `…`
+    pub max_col_width: Option<usize>,
+    /// Bold the header row and print NULLs as a dimmed `NULL` using ANSI escape
+    /// codes; only set this when writing to a terminal
+    pub color: bool,
+}
+
+const ANSI_BOLD: &str = "\x1b[1m";
+const ANSI_DIM: &str = "\x1b[2m";
+const ANSI_RESET: &str = "\x1b[0m";
+
+/// Render batches as an aligned text table in the given style
+///
+/// The default style renders exactly like [`format_table`].
+///
+/// # Errors
+///
+/// Returns an error if a value cannot be formatted
+pub fn format_table_with(batches: &[RecordBatch], style: &TableStyle) -> Result<String> {
+    let rows: usize = batches.iter().map(|b| b.num_rows()).sum();
+    if rows == 0 {
+        return Ok("(0 rows)".to_string());
+    }
+    if *style == TableStyle::default() {
+        return Ok(pretty_format_batches(batches)?.to_string());
+    }
+
+    let header_code = style.color.then_some(ANSI_BOLD);
+    let header: Vec<TableCell> = batches[0]
+        .schema()
+        .fields()
+        .iter()
+        .map(|field| TableCell::new(field.name(), header_code, style))
+        .collect();
+
+    let null_code = style.color.then_some(ANSI_DIM);
+    let null_text = if style.color { "NULL" } else { "" };
+    let options = FormatOptions::default().with_display_error(true);
+    let mut body = Vec::with_capacity(rows);
+    for batch in batches {
+        let formatters = batch
+            .columns()
+            .iter()
+            .map(|column| ArrayFormatter::try_new(column.as_ref(), &options))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        for row in 0..batch.num_rows() {
+            let cells = batch
+                .columns()
+                .iter()
+                .zip(&formatters)
+                .map(|(column, formatter)| {
+                    if column.is_null(row) {
+                        TableCell::new(null_text, null_code, style)
+                    } else {
+                        TableCell::new(&formatter.value(row).to_string(), None, style)
+                    }
+                })
+                .collect::<Vec<_>>();
+            body.push(cells);
+        }
+    }
+
+    let mut widths: Vec<usize> = header.iter().map(|cell| cell.width).collect();
+    for cells in &body {
+        for (width, cell) in widths.iter_mut().zip(cells) {
+            *width = (*width).max(cell.width);
+        }
+    }
+
+    let border = widths.iter().fold("+".to_string(), |mut line, width| {
+        line.push_str(&"-".repeat(width + 2));
+        line.push('+');
+        line
+    });
+    let render_row = |cells: &[TableCell]| {
+        cells
+            .iter()
+            .zip(&widths)
+            .fold("|".to_string(), |mut line, (cell, width)| {
+                line.push(' ');
+                match cell.code {
+                    Some(code) => {
+                        line.push_str(code);
+                        line.push_str(&cell.text);
+                        line.push_str(ANSI_RESET);
+                    }
+                    None => line.push_str(&cell.text),
+                }
+                line.push_str(&" ".repeat(width - cell.width + 1));
+                line.push('|');
+                line
+            })
+    };
+
+    let mut lines = vec![border.clone(), render_row(&header), border.clone()];
+    lines.extend(body.iter().map(|cells| render_row(cells)));
+    lines.push(border);
+    Ok(lines.join("\n"))
+}
+
+/// A table cell's text, its width in characters and its ANSI style
+struct TableCell {
+    text: String,
+    width: usize,
+    code: Option<&'static str>,
+}
+
+impl TableCell {
+    fn new(text: &str, code: Option<&'static str>, style: &TableStyle) -> Self {
+        let text = match style.max_col_width {
+            Some(max) if text.chars().count() > max => {
+                let mut cut: String = text.chars().take(max.saturating_sub(1)).collect();
+                cut.push('…');
+                cut
+            }
+            _ => text.to_string(),
+        };
+        Self {
+            width: text.chars().count(),
+            text,
+            code,
+        }
+    }
+}
+
+/// Rows gathered before a window of a streamed result is rendered as a table
+pub const TABLE_WINDOW_ROWS: usize = 8192;
+
+/// Render a record batch stream as text tables, one per window of rows
+///
+/// Column widths have to be known before a table can be printed, so batches
+/// are buffered until at least [`TABLE_WINDOW_ROWS`] rows have arrived and
+/// that window is printed as its own table. Memory stays bounded by a single
+/// window however large the result is. Results that fit in one window print
+/// exactly like [`format_table`].
+///
+/// Returns the number of rows written.
+///
+/// # Errors
+///
+/// Returns an error if the stream fails or the output cannot be written
+pub async fn write_table_stream<W: Write>(
+    stream: SendableRecordBatchStream,
+    writer: W,
+) -> Result<usize> {
+    write_table_stream_with(stream, writer, &TableStyle::default()).await
+}
+
+/// Render a record batch stream as text tables like [`write_table_stream`], in
+/// the given style
+///
+/// # Errors
+///
+/// Returns an error if the stream fails or the output cannot be written
+pub async fn write_table_stream_with<W: Write>(
+    mut stream: SendableRecordBatchStream,
+    mut writer: W,
+    style: &TableStyle,
+) -> Result<usize> {
+    let mut window = Vec::new();
+    let mut window_rows = 0;
+    let mut rows = 0;
+    let mut printed = false;
+
+    while let Some(batch) = stream.next().await {
+        let batch = batch?;
+        if batch.num_rows() == 0 {
+            continue;
+        }
+        window_rows += batch.num_rows();
+        rows += batch.num_rows();
+        window.push(batch);
+
+        if window_rows >= TABLE_WINDOW_ROWS {
+            writeln!(writer, "{}", format_table_with(&window, style)?)?;
+            writer.flush()?;
+            window.clear();
+            window_rows = 0;
+            printed = true;
+        }
+    }
+
+    if !window.is_empty() || !printed {
+        writeln!(writer, "{}", format_table_with(&window, style)?)?;
+    }
+
+    debug!("Printed {} rows as tables", rows);
+    Ok(rows)
+}
+
+/// Write a record batch stream to `writer` in the given format
+///
+/// Returns the number of rows written. Empty results still produce valid
+/// output: a header-only CSV (nothing at all when there are no columns), an
+/// empty JSON array, or a Parquet file carrying just the schema.
+///
+/// # Errors
+///
+/// Returns an error if the stream fails or a batch cannot be written
+pub async fn write_stream<W: Write + Send>(
+    stream: SendableRecordBatchStream,
+    format: OutputFormat,
+    writer: W,
+) -> Result<usize> {
+    write_stream_with(stream, format, writer, None).await
+}
+
+/// Write a record batch stream like [`write_stream`], writing CSV nulls as `csv_null`
+///
+/// CSV output writes nulls as empty fields unless `csv_null` is given; other
+/// formats have a native null and ignore it.
+///
+/// # Errors
+///
+/// Returns an error if the stream fails or a batch cannot be written
+pub async fn write_stream_with<W: Write + Send>(
+    stream: SendableRecordBatchStream,
+    format: OutputFormat,
+    writer: W,
+    csv_null: Option<&str>,
+) -> Result<usize> {
+    write_batches(stream, format, writer, csv_null, None).await
+}
+
+/// Write a record batch stream like [`write_stream_with`], compressed
+///
+/// Text formats are written through a [`CompressedWriter`], which is
+/// finished before returning; Parquet compresses its column chunks with
+/// [`OutputCompression::parquet_codec`].
+///
+/// # Errors
+///
+/// Returns [`SqlStreamError::InvalidOption`] if `compression` doesn't suit
+/// `format`, or an error if the stream fails or the output cannot be written
+pub async fn write_stream_compressed<W: Write + Send>(
+    stream: SendableRecordBatchStream,
+    format: OutputFormat,
+    writer: W,
+    csv_null: Option<&str>,
+    compression: OutputCompression,
+) -> Result<usize> {
+    compression.check(format)?;
+    if format == OutputFormat::Parquet {
+        let properties = WriterProperties::builder()
+            .set_compression(compression.parquet_codec())
+            .build();
+        return write_batches(stream, format, writer, csv_null, Some(properties)).await;
+    }
+
+    let mut writer = CompressedWriter::new(writer, compression)?;
+    let rows = write_batches(stream, format, &mut writer, csv_null, None).await?;
+    writer.finish()?.flush()?;
+    Ok(rows)
+}
+
+/// Write a record batch stream, giving Parquet files the writer `properties`
+async fn write_batches<W: Write + Send>(
+    mut stream: SendableRecordBatchStream,
+    format: OutputFormat,
+    writer: W,
+    csv_null: Option<&str>,
+    properties: Option<WriterProperties>,
+) -> Result<usize> {
+    let mut rows = 0;
+
+    match format {
+        OutputFormat::Csv => {
+            let schema = stream.schema();
+            let mut builder = CsvWriterBuilder::new();
+            if let Some(null) = csv_null {
+                builder = builder.with_null(null.to_string());
+            }
+            let mut csv = builder.build(writer);
+            let mut written = false;
+            while let Some(batch) = stream.next().await {
+                let batch = batch?;
+                rows += batch.num_rows();
+                csv.write(&batch)?;
+                written = true;
+            }
+
+            // The header is only emitted alongside the first batch
+            if !written && !schema.fields().is_empty() {
+                csv.write(&RecordBatch::new_empty(schema))?;
+            }
+        }
+        OutputFormat::Json => {
+            let mut json = ArrayWriter::new(writer);
+            while let Some(batch) = stream.next().await {
+                let batch = batch?;
+                rows += batch.num_rows();
+                json.write(&batch)?;
+            }
+            json.finish()?;
+        }
+        OutputFormat::Ndjson => {
+            let mut json = LineDelimitedWriter::new(writer);
+            while let Some(batch) = stream.next().await {
+                let batch = batch?;
+                rows += batch.num_rows();
+                json.write(&batch)?;
+            }
+            json.finish()?;
+        }
+        OutputFormat::Parquet => {
+            let mut parquet = ArrowWriter::try_new(writer, stream.schema(), properties)
+                .map_err(|e| SqlStreamError::Output(e.to_string()))?;
+            while let Some(batch) = stream.next().await {
+                let batch = batch?;
+                rows += batch.num_rows();
+                parquet
+                    .write(&batch)
+                    .map_err(|e| SqlStreamError::Output(e.to_string()))?;
+            }
+            parquet
+                .close()
+                .map_err(|e| SqlStreamError::Output(e.to_string()))?;
+        }
+    }
+
+    debug!("Wrote {} rows as {:?}", rows, format);
+    Ok(rows)
+}
+
+/// Write a record batch stream as indented JSON, one object per row
+///
+/// [`OutputFormat::Json`] gives an array with each object indented inside it
+/// (unterminated after the closing bracket, like the compact writer);
+/// [`OutputFormat::Ndjson`] gives the objects one after another, each ending
+/// in a newline. Rows are converted a batch at a time, so only one batch is
+/// held as JSON values. As in compact output, NULL fields are omitted.
+///
+/// Returns the number of rows written.
+///
+/// # Errors
+///
+/// Returns an error if `format` is not a JSON format, the stream fails or
+/// the output cannot be written
+pub async fn write_pretty_json<W: Write>(
+    mut stream: SendableRecordBatchStream,
+    format: OutputFormat,
+    mut writer: W,
+) -> Result<usize> {
+    let array = match format {
+        OutputFormat::Json => true,
+        OutputFormat::Ndjson => false,
+        _ => {
+            return Err(SqlStreamError::InvalidOption(format!(
+                "pretty printing needs JSON output, not {:?}",
+                format
+            )))
+        }
+    };
+
+    let mut rows = 0;
+    if array {
+        write!(writer, "[")?;
+    }
+    while let Some(batch) = stream.next().await {
+        for row in json_objects(&batch?)? {
+            let text = serde_json::to_string_pretty(&row)
+                .map_err(|e| SqlStreamError::Output(e.to_string()))?;
+            if !array {
+                writeln!(writer, "{}", text)?;
+            } else {
+                // String values escape their newlines, so each line is safe to indent
+                writeln!(writer, "{}", if rows == 0 { "" } else { "," })?;
+                for (i, line) in text.lines().enumerate() {
+                    if i > 0 {
+                        writeln!(writer)?;
+                    }
+                    write!(writer, "  {}", line)?;
+                }
+            }
+            rows += 1;
+        }
+    }
+    if array {
+        if rows > 0 {
+            writeln!(writer)?;
+        }
+        write!(writer, "]")?;
+    }
+    writer.flush()?;
+
+    debug!("Wrote {} rows as pretty {:?}", rows, format);
+    Ok(rows)
+}
+
+/// The rows of a batch as JSON objects, as Arrow's JSON writer encodes them
+fn json_objects(batch: &RecordBatch) -> Result<Vec<Map<String, Value>>> {
+    let mut json = ArrayWriter::new(Vec::new());
+    json.write(batch)?;
+    json.finish()?;
+    serde_json::from_slice(&json.into_inner()).map_err(|e| SqlStreamError::Output(e.to_string()))
+}
+
+/// Object key used for rows whose group column is NULL
+pub const NULL_GROUP_KEY: &str = "null";
+
+/// Write a record batch stream as a JSON object grouping rows by `column`
+///
+/// Produces `{"NYC": [row, row], "LA": [row]}` with groups in order of first
+/// appearance. Each row keeps every column, including the grouping column.
+/// Non-string keys use their JSON text and NULL keys use [`NULL_GROUP_KEY`].
+/// Unlike [`write_stream`], all rows are held in memory before writing.
+///
+/// Returns the number of rows written.
+///
+/// # Errors
+///
+/// Returns an error if the column does not exist, the stream fails or the
+/// output cannot be written
+pub async fn write_grouped_json<W: Write>(
+    mut stream: SendableRecordBatchStream,
+    column: &str,
+    writer: W,
+) -> Result<usize> {
+    require_column(&stream.schema(), column)?;
+
+    let mut rows = Vec::new();
+    while let Some(batch) = stream.next().await {
+        rows.extend(json_objects(&batch?)?);
+    }
+    let count = rows.len();
+
+    let mut groups = Map::new();
+    for row in rows {
+        // Arrow's JSON writer omits NULL fields, so a missing key is a NULL
+        let key = match row.get(column) {
+            None | Some(Value::Null) => NULL_GROUP_KEY.to_string(),
+            Some(Value::String(value)) => value.clone(),
+            Some(value) => value.to_string(),
+        };
+        if let Value::Array(members) = groups
+            .entry(key)
+            .or_insert_with(|| Value::Array(Vec::new()))
+        {
+            members.push(Value::Object(row));
+        }
+    }
+
+    serde_json::to_writer(writer, &groups).map_err(|e| SqlStreamError::Output(e.to_string()))?;
+    debug!("Wrote {} rows in {} JSON groups", count, groups.len());
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+    use std::sync::Arc;
+
+    fn empty_stream(schema: Schema) -> SendableRecordBatchStream {
+        Box::pin(RecordBatchStreamAdapter::new(
+            Arc::new(schema),
+            futures::stream::empty::<datafusion::error::Result<RecordBatch>>(),
+        ))
+    }
+
+    fn two_columns() -> Schema {
+        Schema::new(vec![
+            Field::new("id", DataType::Int64, true),
+            Field::new("name", DataType::Utf8, true),
+        ])
+    }
+
+    async fn write_empty(schema: Schema, format: OutputFormat) -> String {
+        let mut buffer = Vec::new();
+        let rows = write_stream(empty_stream(schema), format, &mut buffer)
+            .await
+            .unwrap();
+        assert_eq!(rows, 0);
+        String::from_utf8(buffer).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_empty_csv_writes_header() {
+        assert_eq!(
+            write_empty(two_columns(), OutputFormat::Csv).await,
+            "id,name\n"
+        );
+        assert_eq!(write_empty(Schema::empty(), OutputFormat::Csv).await, "");
+    }
+
+    #[tokio::test]
+    async fn test_empty_json_writes_array() {
+        assert_eq!(write_empty(two_columns(), OutputFormat::Json).await, "[]");
+        assert_eq!(write_empty(two_columns(), OutputFormat::Ndjson).await, "");
+    }
+
+    #[tokio::test]
+    async fn test_empty_parquet_is_readable() {
+        let mut buffer = Vec::new();
+        write_stream(
+            empty_stream(two_columns()),
+            OutputFormat::Parquet,
+            &mut buffer,
+        )
+        .await
+        .unwrap();
+        assert!(buffer.starts_with(b"PAR1"));
+    }
+
+    #[tokio::test]
+    async fn test_grouped_json() {
+        use datafusion::arrow::array::{Int64Array, StringArray};
+
+        let batch = RecordBatch::try_new(
+            Arc::new(two_columns()),
+            vec![
+                Arc::new(Int64Array::from(vec![1, 2, 3, 4])),
+                Arc::new(StringArray::from(vec![
+                    Some("NYC"),
+                    Some("LA"),
+                    None,
+                    Some("NYC"),
+                ])),
+            ],
+        )
+        .unwrap();
+        let stream = Box::pin(RecordBatchStreamAdapter::new(
+            Arc::new(two_columns()),
+            futures::stream::iter(vec![Ok(batch)]),
+        ));
+
+        let mut buffer = Vec::new();
+        let rows = write_grouped_json(stream, "name", &mut buffer)
+            .await
+            .unwrap();
+        assert_eq!(rows, 4);
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            r#"{"NYC":[{"id":1,"name":"NYC"},{"id":4,"name":"NYC"}],"LA":[{"id":2,"name":"LA"}],"null":[{"id":3}]}"#
+        );
+
+        let result = write_grouped_json(empty_stream(two_columns()), "city", Vec::new()).await;
+        assert!(matches!(result, Err(SqlStreamError::ColumnNotFound { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_pretty_json() {
+        use datafusion::arrow::array::{Int64Array, StringArray};
+
+        let pretty = |format: OutputFormat| async move {
+            let batch = RecordBatch::try_new(
+                Arc::new(two_columns()),
+                vec![
+                    Arc::new(Int64Array::from(vec![1, 2])),
+                    Arc::new(StringArray::from(vec![Some("a\nb"), None])),
+                ],
+            )
+            .unwrap();
+            let stream = Box::pin(RecordBatchStreamAdapter::new(
+                Arc::new(two_columns()),
+                futures::stream::iter(vec![Ok(batch)]),
+            ));
+            let mut buffer = Vec::new();
+            let rows = write_pretty_json(stream, format, &mut buffer)
+                .await
+                .unwrap();
+            assert_eq!(rows, 2);
+            String::from_utf8(buffer).unwrap()
+        };
+
+        let array = pretty(OutputFormat::Json).await;
+        assert_eq!(
+            array,
+            "[\n  {\n    \"id\": 1,\n    \"name\": \"a\\nb\"\n  },\n  {\n    \"id\": 2\n  }\n]"
+        );
+        let parsed: Vec<Value> = serde_json::from_str(&array).unwrap();
+        assert_eq!(parsed.len(), 2);
+
+        let ndjson = pretty(OutputFormat::Ndjson).await;
+        assert!(ndjson.lines().count() > 2);
+        let objects: Vec<Value> = serde_json::Deserializer::from_str(&ndjson)
+            .into_iter()
+            .collect::<std::result::Result<_, _>>()
+            .unwrap();
+        assert_eq!(objects.len(), 2);
+
+        let mut buffer = Vec::new();
+        write_pretty_json(empty_stream(two_columns()), OutputFormat::Json, &mut buffer)
+            .await
+            .unwrap();
+        assert_eq!(buffer, b"[]");
+        assert!(
+            write_pretty_json(empty_stream(two_columns()), OutputFormat::Csv, Vec::new())
+                .await
+                .is_err()
+        );
+    }
+
+    /// Records how many times it was flushed
+    #[derive(Default)]
+    struct CountingWriter {
+        flushes: usize,
+    }
+
+    impl Write for CountingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushes += 1;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_csv_null_value() {
+        use datafusion::arrow::array::Int64Array;
+        use datafusion::arrow::datatypes::{DataType, Field, Schema};
+        use datafusion::physical_plan::memory::MemoryStream;
+
+        let schema = Arc::new(Schema::new(vec![Field::new("n", DataType::Int64, true)]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Int64Array::from(vec![Some(1), None]))],
+        )
+        .unwrap();
+        let stream = MemoryStream::try_new(vec![batch], schema, None).unwrap();
+
+        let mut buffer = Vec::new();
+        write_stream_with(Box::pin(stream), OutputFormat::Csv, &mut buffer, Some("NA"))
+            .await
+            .unwrap();
+        assert_eq!(String::from_utf8(buffer).unwrap(), "n\n1\nNA\n");
+    }
+
+    #[test]
+    fn test_stream_stats_summary() {
+        let stats = StreamStats {
+            rows: 10,
+            batches: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            stats.summary(Duration::from_millis(12)),
+            "10 rows in 2 batches (0.012s)"
+        );
+        let single = StreamStats {
+            rows: 1,
+            batches: 1,
+            ..Default::default()
+        };
+        assert_eq!(single.summary(Duration::ZERO), "1 row in 1 batch (0.000s)");
+    }
+
+    #[test]
+    fn test_query_timings() {
+        let ms = Duration::from_millis;
+        let timings = QueryTimings {
+            runs: vec![ms(30), ms(10), ms(20), ms(40)],
+        };
+        assert_eq!(timings.min(), Some(ms(10)));
+        assert_eq!(timings.max(), Some(ms(40)));
+        assert_eq!(timings.mean(), Some(ms(25)));
+        assert_eq!(timings.median(), Some(ms(25)));
+        assert_eq!(
+            timings.summary(),
+            "4 runs: min 0.010s, median 0.025s, mean 0.025s, max 0.040s"
+        );
+
+        let single = QueryTimings { runs: vec![ms(5)] };
+        assert_eq!(single.median(), Some(ms(5)));
+        assert_eq!(QueryTimings::default().mean(), None);
+    }
+
+    #[test]
+    fn test_interval_flush() {
+        let mut eager = IntervalFlush::new(CountingWriter::default(), Duration::ZERO);
+        eager.write_all(b"a").unwrap();
+        eager.write_all(b"b").unwrap();
+        assert_eq!(eager.inner.flushes, 2);
+
+        let mut lazy = IntervalFlush::new(CountingWriter::default(), Duration::from_secs(3600));
+        lazy.write_all(b"a").unwrap();
+        lazy.write_all(b"b").unwrap();
+        assert_eq!(lazy.inner.flushes, 0);
+    }
+
+    #[tokio::test]
+    async fn test_round_floats() {
+        use datafusion::arrow::array::{Float32Array, Float64Array, Int64Array};
+        use datafusion::physical_plan::memory::MemoryStream;
+
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("avg", DataType::Float64, true),
+            Field::new("ratio", DataType::Float32, false),
+            Field::new("n", DataType::Int64, false),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Float64Array::from(vec![Some(70333.33333333333), None])),
+                Arc::new(Float32Array::from(vec![0.5, 2.0])),
+                Arc::new(Int64Array::from(vec![3, 4])),
+            ],
+        )
+        .unwrap();
+        let stream = MemoryStream::try_new(vec![batch], schema, None).unwrap();
+
+        let mut buffer = Vec::new();
+        write_stream(
+            round_floats(Box::pin(stream), 2),
+            OutputFormat::Csv,
+            &mut buffer,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "avg,ratio,n\n70333.33,0.50,3\n,2.00,4\n"
+        );
+    }
+
+    #[test]
+    fn test_table_style() {
+        use datafusion::arrow::array::{Int64Array, StringArray};
+
+        let batch = RecordBatch::try_new(
+            Arc::new(two_columns()),
+            vec![
+                Arc::new(Int64Array::from(vec![Some(1), None])),
+                Arc::new(StringArray::from(vec!["alpha", "a much longer name"])),
+            ],
+        )
+        .unwrap();
+
+        let narrow = TableStyle {
+            max_col_width: Some(8),
+            color: false,
+        };
+        assert_eq!(
+            format_table_with(std::slice::from_ref(&batch), &narrow).unwrap(),
+            "+----+----------+\n\
+             | id | name     |\n\
+             +----+----------+\n\
+             | 1  | alpha    |\n\
+             |    | a much … |\n\
+             +----+----------+"
+        );
+
+        let colored = TableStyle {
+            max_col_width: None,
+            color: true,
+        };
+        let table = format_table_with(std::slice::from_ref(&batch), &colored).unwrap();
+        assert!(table.contains("| \x1b[1mid\x1b[0m   |"), "{}", table);
+        assert!(table.contains("| \x1b[2mNULL\x1b[0m |"), "{}", table);
+
+        assert_eq!(
+            format_table_with(std::slice::from_ref(&batch), &TableStyle::default()).unwrap(),
+            format_table(&[batch]).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_table_stream_windows() {
+        use datafusion::arrow::array::Int64Array;
+
+        let schema = Arc::new(Schema::new(vec![Field::new("n", DataType::Int64, false)]));
+        let batches: Vec<_> = (0..5)
+            .map(|i| {
+                let start = i * 4096;
+                let values = Int64Array::from_iter_values(start..start + 4096);
+                Ok(RecordBatch::try_new(schema.clone(), vec![Arc::new(values)]).unwrap())
+            })
+            .collect();
+        let stream = Box::pin(RecordBatchStreamAdapter::new(
+            schema,
+            futures::stream::iter(batches),
+        ));
+
+        let mut buffer = Vec::new();
+        let rows = write_table_stream(stream, &mut buffer).await.unwrap();
+        assert_eq!(rows, 5 * 4096);
+
+        // 20480 rows print as windows of 8192, 8192 and 4096 rows
+        let text = String::from_utf8(buffer).unwrap();
+        assert_eq!(text.matches("| n ").count(), 3);
+
+        let mut buffer = Vec::new();
+        write_table_stream(empty_stream(two_columns()), &mut buffer)
+            .await
+            .unwrap();
+        assert_eq!(String::from_utf8(buffer).unwrap(), "(0 rows)\n");
+    }
+
+    #[test]
+    fn test_empty_table() {
+        let batch = RecordBatch::new_empty(Arc::new(two_columns()));
+        assert_eq!(format_table(&[batch]).unwrap(), "(0 rows)");
+        assert_eq!(format_table(&[]).unwrap(), "(0 rows)");
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(
+            OutputFormat::from_path(Path::new("out.parquet")).unwrap(),
+            OutputFormat::Parquet
+        );
+        assert_eq!(
+            OutputFormat::from_path(Path::new("out.JSONL")).unwrap(),
+            OutputFormat::Ndjson
+        );
+        assert!(matches!(
+            OutputFormat::from_path(Path::new("out.txt")),
+            Err(SqlStreamError::UnsupportedFormat(_))
+        ));
+        assert_eq!(
+            OutputFormat::from_path(Path::new("out.csv.gz")).unwrap(),
+            OutputFormat::Csv
+        );
+        assert!(OutputFormat::from_path(Path::new("out.parquet.zst")).is_err());
+        assert!(OutputFormat::from_path(Path::new("out.gz")).is_err());
+    }
+
+    #[test]
+    fn test_output_compression() {
+        assert_eq!(
+            OutputCompression::from_path(Path::new("out.ndjson.ZST")),
+            Some(OutputCompression::Zstd)
+        );
+        assert_eq!(OutputCompression::from_path(Path::new("out.csv")), None);
+
+        assert!(OutputCompression::Snappy
+            .check(OutputFormat::Parquet)
+            .is_ok());
+        assert!(matches!(
+            OutputCompression::Snappy.check(OutputFormat::Csv),
+            Err(SqlStreamError::InvalidOption(_))
+        ));
+        assert!(OutputCompression::Gzip.check(OutputFormat::Json).is_ok());
+        assert_eq!(
+            OutputCompression::Snappy.parquet_codec(),
+            Compression::SNAPPY
+        );
+    }
+
+    #[test]
+    fn test_compressed_writer() {
+        use std::io::Read;
+
+        let mut writer = CompressedWriter::new(Vec::new(), OutputCompression::Gzip).unwrap();
+        writer.write_all(b"id\n1\n").unwrap();
+        let compressed = writer.finish().unwrap();
+        assert_eq!(&compressed[..2], [0x1f, 0x8b]);
+        let mut text = String::new();
+        flate2::read::GzDecoder::new(&compressed[..])
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(text, "id\n1\n");
+
+        let mut writer = CompressedWriter::new(Vec::new(), OutputCompression::Zstd).unwrap();
+        writer.write_all(b"id\n1\n").unwrap();
+        let compressed = writer.finish().unwrap();
+        assert_eq!(zstd::decode_all(&compressed[..]).unwrap(), b"id\n1\n");
+    }
+}
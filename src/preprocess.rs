@@ -0,0 +1,1018 @@
+//! Input preprocessing for irregular data files
+//!
+//! Some real-world exports can't be handed to DataFusion's readers as-is. This
+//! module rewrites such files into a cleaned temporary copy which is then
+//! registered in place of the original.
+
+use crate::error::{Result, SqlStreamError};
+use clap::ValueEnum;
+use datafusion::datasource::file_format::file_compression_type::FileCompressionType;
+use regex::Regex;
+use serde_json::Value;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tempfile::NamedTempFile;
+use tracing::debug;
+use url::Url;
+
+/// Copy a CSV file starting at the first line matching `pattern`
+///
+/// Every line before the matching one is treated as preamble and dropped, so
+/// the matching line becomes the header of the returned temporary file. A
+/// leading UTF-8 BOM is dropped too, so patterns anchored with `^` still match.
+///
+/// # Errors
+///
+/// Returns [`SqlStreamError::HeaderNotFound`] if no line matches, or an IO error
+/// if the file cannot be read or the temporary copy cannot be written
+pub fn skip_to_header(path: &Path, pattern: &Regex) -> Result<NamedTempFile> {
+    let reader = BufReader::new(open(path)?);
+    let mut temp = tempfile::Builder::new().suffix(".csv").tempfile()?;
+
+    let mut found = false;
+    {
+        let mut writer = BufWriter::new(temp.as_file_mut());
+        for (index, line) in reader.lines().enumerate() {
+            let mut line = line?;
+            if index == 0 && line.starts_with(BOM) {
+                line.drain(..BOM.len());
+            }
+            if !found {
+                if !pattern.is_match(&line) {
+                    continue;
+                }
+                debug!("Header found on line {}", index + 1);
+                found = true;
+            }
+            writeln!(writer, "{}", line)?;
+        }
+        writer.flush()?;
+    }
+
+    if !found {
+        return Err(SqlStreamError::HeaderNotFound {
+            file: path.to_path_buf(),
+            pattern: pattern.to_string(),
+        });
+    }
+
+    Ok(temp)
+}
+
+/// UTF-8 byte order mark
+const BOM: &str = "\u{feff}";
+
+/// Whether a file, once decompressed, holds nothing but whitespace
+///
+/// A leading UTF-8 BOM is ignored too. Only the start of the file is read,
+/// up to the first other byte.
+///
+/// # Errors
+///
+/// Returns an IO error if the file cannot be read
+pub fn is_blank(path: &Path) -> Result<bool> {
+    if std::fs::metadata(path)?.len() == 0 {
+        return Ok(true);
+    }
+    let mut reader = BufReader::new(open(path)?);
+    Ok(skip_to_content(&mut reader)?.is_none())
+}
+
+/// Whether a CSV file has a single non-empty line, its header, and no data rows
+///
+/// # Errors
+///
+/// Returns an IO error if the file cannot be read
+pub fn csv_header_only(path: &Path) -> Result<bool> {
+    let reader = BufReader::new(open(path)?);
+    let mut lines = 0;
+    for line in reader.lines() {
+        if !line?.trim_start_matches(BOM).trim().is_empty() {
+            lines += 1;
+            if lines > 1 {
+                return Ok(false);
+            }
+        }
+    }
+    Ok(lines == 1)
+}
+
+/// Whether a file, once decompressed, starts with a UTF-8 BOM
+///
+/// # Errors
+///
+/// Returns an IO error if the file cannot be read
+pub fn has_bom(path: &Path) -> Result<bool> {
+    let mut start = Vec::with_capacity(BOM.len());
+    open(path)?.take(BOM.len() as u64).read_to_end(&mut start)?;
+    Ok(start == BOM.as_bytes())
+}
+
+/// Copy a CSV file without its leading UTF-8 BOM
+///
+/// Spreadsheet exports (notably Excel's "CSV UTF-8") start with a BOM, which
+/// the CSV reader would otherwise keep as part of the first column's name.
+/// The copy is decompressed.
+///
+/// # Errors
+///
+/// Returns an IO error if the file cannot be read or the copy cannot be written
+pub fn strip_bom(path: &Path) -> Result<NamedTempFile> {
+    let mut reader = BufReader::new(open(path)?);
+    if reader.fill_buf()?.starts_with(BOM.as_bytes()) {
+        reader.consume(BOM.len());
+    }
+
+    debug!("Stripping the UTF-8 BOM from {}", path.display());
+    spool(reader, "csv")
+}
+
+/// Layout of a `.json` file's records
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum JsonMode {
+    /// Detect the layout from the first character after any BOM and whitespace
+    #[default]
+    Auto,
+    /// One object per line
+    Ndjson,
+    /// A single array of objects
+    Array,
+}
+
+/// Copy a JSON file as clean NDJSON
+///
+/// Exports from some tools start with a UTF-8 BOM or separate records with
+/// empty or whitespace-only lines, both of which the NDJSON reader rejects;
+/// these are dropped. A file holding a single JSON array of objects
+/// (`[{...}, {...}]`) is rewritten with one object per line. `mode` says
+/// which of the two the file holds; [`JsonMode::Auto`] decides from a
+/// leading `[`.
+///
+/// # Errors
+///
+/// Returns an IO error if the file cannot be read or the copy cannot be written,
+/// or [`SqlStreamError::JsonArray`] if a JSON array file is malformed or holds
+/// something other than objects, or `mode` is [`JsonMode::Array`] and the file
+/// is not an array
+pub fn clean_ndjson(path: &Path, mode: JsonMode) -> Result<NamedTempFile> {
+    let mut reader = BufReader::new(open(path)?);
+    let mut temp = tempfile::Builder::new().suffix(".json").tempfile()?;
+
+    let first = skip_to_content(&mut reader)?;
+    let is_array = match mode {
+        JsonMode::Auto => first == Some(b'['),
+        JsonMode::Ndjson => false,
+        JsonMode::Array => {
+            if first != Some(b'[') {
+                return Err(SqlStreamError::JsonArray {
+                    file: path.to_path_buf(),
+                    reason: "it does not start with '['".to_string(),
+                });
+            }
+            true
+        }
+    };
+    if is_array {
+        array_to_ndjson(path, reader, temp.as_file_mut())?;
+        return Ok(temp);
+    }
+
+    let mut skipped = 0;
+    {
+        let mut writer = BufWriter::new(temp.as_file_mut());
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                skipped += 1;
+                continue;
+            }
+            writeln!(writer, "{}", line)?;
+        }
+        writer.flush()?;
+    }
+
+    if skipped > 0 {
+        debug!("Skipped {} blank lines in {}", skipped, path.display());
+    }
+    Ok(temp)
+}
+
+/// Consume a leading BOM and whitespace, returning the first content byte
+fn skip_to_content<R: BufRead>(reader: &mut R) -> io::Result<Option<u8>> {
+    let mut at_start = true;
+    loop {
+        let buffer = reader.fill_buf()?;
+        if at_start && buffer.starts_with(BOM.as_bytes()) {
+            reader.consume(BOM.len());
+            at_start = false;
+            continue;
+        }
+        at_start = false;
+
+        let Some(&first) = buffer.first() else {
+            return Ok(None);
+        };
+        if !first.is_ascii_whitespace() {
+            return Ok(Some(first));
+        }
+        reader.consume(1);
+    }
+}
+
+/// Write the objects of a JSON array as NDJSON, one object per line
+///
+/// The whole array is parsed in memory before anything is written.
+fn array_to_ndjson<R: Read, W: Write>(path: &Path, reader: R, output: W) -> Result<()> {
+    let invalid = |reason: String| SqlStreamError::JsonArray {
+        file: path.to_path_buf(),
+        reason,
+    };
+
+    let records: Vec<Value> = serde_json::from_reader(reader)
+        .map_err(|e| invalid(format!("it could not be parsed: {}", e)))?;
+    if let Some(index) = records.iter().position(|record| !record.is_object()) {
+        return Err(invalid(format!("element {} is not an object", index)));
+    }
+
+    let mut writer = BufWriter::new(output);
+    for record in &records {
+        serde_json::to_writer(&mut writer, record).map_err(|e| invalid(e.to_string()))?;
+        writeln!(writer)?;
+    }
+    writer.flush()?;
+
+    debug!(
+        "Converted a JSON array of {} objects in {}",
+        records.len(),
+        path.display()
+    );
+    Ok(())
+}
+
+/// The compression of a file, detected from a trailing `.gz` or `.zst`
+pub fn compression(path: &Path) -> FileCompressionType {
+    let extension = path.extension().and_then(|ext| ext.to_str());
+    match extension.map(str::to_lowercase).as_deref() {
+        Some("gz") => FileCompressionType::GZIP,
+        Some("zst") => FileCompressionType::ZSTD,
+        _ => FileCompressionType::UNCOMPRESSED,
+    }
+}
+
+/// The extension naming a file's data format, looking past any compression suffix
+///
+/// `events.csv.gz` and `events.csv` both give `csv`.
+pub fn format_extension(path: &Path) -> Option<&str> {
+    let path = if compression(path).is_compressed() {
+        Path::new(path.file_stem()?)
+    } else {
+        path
+    };
+    path.extension()?.to_str()
+}
+
+/// Open a file for reading, decompressing it if it is gzip or zstd compressed
+///
+/// # Errors
+///
+/// Returns an IO error if the file cannot be opened
+pub fn open(path: &Path) -> Result<Box<dyn Read + Send>> {
+    let file = File::open(path)?;
+    Ok(compression(path).convert_read(file)?)
+}
+
+/// Whether a path is a named pipe (or a character device such as a terminal)
+///
+/// Such inputs can only be read once, front to back, so they are copied to a
+/// temporary file with [`spool`] before DataFusion scans them.
+///
+/// # Errors
+///
+/// Returns [`SqlStreamError::UnsupportedSpecialFile`] for other special files,
+/// such as sockets and block devices, or an IO error if the path cannot be
+/// inspected
+pub fn is_pipe(path: &Path) -> Result<bool> {
+    let file_type = std::fs::metadata(path)?.file_type();
+    if file_type.is_file() || file_type.is_dir() {
+        return Ok(false);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        if file_type.is_fifo() || file_type.is_char_device() {
+            return Ok(true);
+        }
+    }
+    Err(SqlStreamError::UnsupportedSpecialFile(path.to_path_buf()))
+}
+
+/// Wait before the first retry of a file that failed to open; doubled for each later one
+const OPEN_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Open a file, retrying errors that may clear up on their own
+///
+/// A file being written by another process can briefly refuse to open, with a
+/// sharing violation on Windows or a busy file on NFS. After such an error
+/// `open` is tried again, up to `retries` more times, waiting 100ms, 200ms,
+/// 400ms, ... in between.
+///
+/// # Errors
+///
+/// Returns [`SqlStreamError::FileAccess`] if access is denied or the file is
+/// still locked after the last retry, or an IO error for any other failure
+pub async fn open_with_retry<T>(
+    path: &Path,
+    retries: u32,
+    mut open: impl FnMut() -> io::Result<T>,
+) -> Result<T> {
+    let mut backoff = OPEN_BACKOFF;
+    for attempt in 0.. {
+        match open() {
+            Err(e) if attempt < retries && is_transient(&e) => {
+                debug!(
+                    "Opening {} failed ({}), retrying in {:?}",
+                    path.display(),
+                    e,
+                    backoff
+                );
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+            result => return result.map_err(|e| open_error(path, e)),
+        }
+    }
+    unreachable!("the retry loop always returns")
+}
+
+/// Whether an open failure may succeed if tried again shortly
+fn is_transient(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    ) || is_locked(err)
+}
+
+/// Whether an error means another process holds the file
+fn is_locked(err: &io::Error) -> bool {
+    // ERROR_SHARING_VIOLATION and ERROR_LOCK_VIOLATION
+    #[cfg(windows)]
+    const LOCKED: [i32; 2] = [32, 33];
+    // EBUSY and ETXTBSY
+    #[cfg(not(windows))]
+    const LOCKED: [i32; 2] = [16, 26];
+    err.raw_os_error()
+        .is_some_and(|code| LOCKED.contains(&code))
+}
+
+/// Describe a failure to open an input file
+fn open_error(path: &Path, err: io::Error) -> SqlStreamError {
+    let detail = if err.kind() == io::ErrorKind::PermissionDenied {
+        "permission denied"
+    } else if is_locked(&err) {
+        "it is locked or in use by another process"
+    } else {
+        return SqlStreamError::Io(err);
+    };
+    SqlStreamError::FileAccess {
+        file: path.to_path_buf(),
+        detail: detail.to_string(),
+    }
+}
+
+/// Copy everything from `reader` into a temporary file with the given extension
+///
+/// Used for inputs without a path, such as stdin, so they can be registered
+/// like any other file.
+///
+/// # Errors
+///
+/// Returns an IO error if reading fails or the temporary file cannot be written
+pub fn spool<R: Read>(mut reader: R, extension: &str) -> Result<NamedTempFile> {
+    let mut temp = tempfile::Builder::new()
+        .suffix(&format!(".{}", extension))
+        .tempfile()?;
+
+    let bytes = {
+        let mut writer = BufWriter::new(temp.as_file_mut());
+        let bytes = io::copy(&mut reader, &mut writer)?;
+        writer.flush()?;
+        bytes
+    };
+
+    debug!("Spooled {} bytes to {}", bytes, temp.path().display());
+    Ok(temp)
+}
+
+/// Parse `path` as an `http://`, `https://` or `s3://` URL, if it is one
+pub fn remote_url(path: &str) -> Option<Url> {
+    let url = Url::parse(path).ok()?;
+    matches!(url.scheme(), "http" | "https" | "s3").then_some(url)
+}
+
+/// Whether `path` contains glob metacharacters
+pub fn is_glob(path: &str) -> bool {
+    path.contains(['*', '?', '['])
+}
+
+/// Expand a glob pattern or a directory into the files it covers, sorted by path
+///
+/// Directories list their regular files, skipping hidden files and files
+/// starting with `_` (such as Spark's `_SUCCESS` markers). Subdirectories are
+/// not descended into.
+///
+/// # Errors
+///
+/// Returns [`SqlStreamError::FileNotFound`] if nothing matches, or
+/// [`SqlStreamError::InvalidOption`] for a malformed pattern
+pub fn expand_paths(pattern: &str) -> Result<Vec<PathBuf>> {
+    let mut files = if is_glob(pattern) {
+        let options = glob::MatchOptions {
+            require_literal_leading_dot: true,
+            ..Default::default()
+        };
+        glob::glob_with(pattern, options)
+            .map_err(|e| {
+                SqlStreamError::InvalidOption(format!("Invalid glob '{}': {}", pattern, e))
+            })?
+            .filter_map(|entry| entry.ok())
+            .filter(|path| path.is_file())
+            .collect::<Vec<_>>()
+    } else {
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(pattern)? {
+            let path = entry?.path();
+            let hidden = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(['.', '_']));
+            if path.is_file() && !hidden {
+                files.push(path);
+            }
+        }
+        files
+    };
+
+    if files.is_empty() {
+        return Err(SqlStreamError::FileNotFound(PathBuf::from(pattern)));
+    }
+
+    files.sort();
+    debug!("{} matched {} files", pattern, files.len());
+    Ok(files)
+}
+
+/// A directory whose files sit in Hive-style `key=value` subdirectories
+#[derive(Debug, PartialEq)]
+pub struct HiveLayout {
+    /// Partition keys, outermost first, each with its distinct values as written
+    pub columns: Vec<(String, Vec<String>)>,
+    /// The files below the partition directories, sorted
+    pub files: Vec<PathBuf>,
+}
+
+/// Detect a Hive-style partitioned layout, such as `year=2024/month=01/part.parquet`
+///
+/// Hidden and `_`-prefixed entries are skipped, as in [`expand_paths`].
+/// Returns `None` if `dir` has no `key=value` subdirectories.
+///
+/// # Errors
+///
+/// Returns [`SqlStreamError::InvalidOption`] if the layout is inconsistent:
+/// a subdirectory that isn't `key=value`, different keys at the same depth,
+/// files beside partition directories or partitions of different depths
+pub fn hive_layout(dir: &Path) -> Result<Option<HiveLayout>> {
+    let inconsistent = |path: &Path, detail: &str| {
+        SqlStreamError::InvalidOption(format!(
+            "{} is not a consistent partitioned layout: {}",
+            path.display(),
+            detail
+        ))
+    };
+
+    let mut columns: Vec<(String, Vec<String>)> = Vec::new();
+    let mut files: Vec<(PathBuf, usize)> = Vec::new();
+    let mut pending = vec![(dir.to_path_buf(), 0)];
+    while let Some((current, depth)) = pending.pop() {
+        let mut partitions = Vec::new();
+        let mut leaves = Vec::new();
+        for entry in std::fs::read_dir(&current)? {
+            let path = entry?.path();
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            if name.starts_with(['.', '_']) {
+                continue;
+            }
+            if path.is_dir() {
+                partitions.push((path, name));
+            } else if path.is_file() {
+                leaves.push(path);
+            }
+        }
+
+        let is_partition =
+            |name: &str| name.split_once('=').is_some_and(|(key, _)| !key.is_empty());
+        if depth == 0 && !partitions.iter().any(|(_, name)| is_partition(name)) {
+            return Ok(None);
+        }
+        if !partitions.is_empty() && !leaves.is_empty() {
+            return Err(inconsistent(
+                &current,
+                "it holds both files and partition directories",
+            ));
+        }
+        files.extend(leaves.into_iter().map(|file| (file, depth)));
+
+        for (path, name) in partitions {
+            let Some((key, value)) = name.split_once('=').filter(|(key, _)| !key.is_empty()) else {
+                return Err(inconsistent(&path, "expected a key=value directory name"));
+            };
+            match columns.get_mut(depth) {
+                Some((expected, values)) if expected == key => {
+                    if !values.iter().any(|known| known == value) {
+                        values.push(value.to_string());
+                    }
+                }
+                Some((expected, _)) => {
+                    return Err(inconsistent(
+                        &path,
+                        &format!("expected a '{}' partition at this depth", expected),
+                    ));
+                }
+                None => columns.push((key.to_string(), vec![value.to_string()])),
+            }
+            pending.push((path, depth + 1));
+        }
+    }
+
+    if let Some((file, _)) = files.iter().find(|(_, depth)| *depth != columns.len()) {
+        return Err(inconsistent(
+            file,
+            &format!("every file must be {} partition levels deep", columns.len()),
+        ));
+    }
+    let mut files: Vec<PathBuf> = files.into_iter().map(|(file, _)| file).collect();
+    files.sort();
+    for (_, values) in &mut columns {
+        values.sort();
+    }
+    debug!(
+        "{} has {} partition columns over {} files",
+        dir.display(),
+        columns.len(),
+        files.len()
+    );
+    Ok(Some(HiveLayout { columns, files }))
+}
+
+/// Candidate delimiters considered when sniffing, in order of preference on ties
+const DELIMITER_CANDIDATES: [u8; 4] = [b',', b'\t', b';', b'|'];
+
+/// Number of non-empty lines inspected when sniffing a delimiter
+const SNIFF_LINES: usize = 20;
+
+/// Detect the delimiter of a delimited text file from its first lines
+///
+/// Each candidate is counted per line, ignoring characters inside double quotes.
+/// A candidate that appears the same non-zero number of times on every line is
+/// preferred; among those the one with the most columns wins. If no candidate
+/// is consistent, the one with the highest total count is used. Returns `None`
+/// if no candidate appears at all.
+///
+/// # Errors
+///
+/// Returns an IO error if the file cannot be read
+pub fn sniff_delimiter(path: &Path) -> Result<Option<u8>> {
+    let reader = BufReader::new(open(path)?);
+    let mut lines = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            lines.push(line);
+        }
+        if lines.len() == SNIFF_LINES {
+            break;
+        }
+    }
+
+    Ok(pick_delimiter(&lines))
+}
+
+/// Choose the most consistent delimiter for a sample of lines
+fn pick_delimiter(lines: &[String]) -> Option<u8> {
+    let mut best: Option<(bool, usize, u8)> = None;
+
+    for &candidate in &DELIMITER_CANDIDATES {
+        let counts: Vec<usize> = lines
+            .iter()
+            .map(|line| count_unquoted(line, candidate))
+            .collect();
+        let total: usize = counts.iter().sum();
+        if total == 0 {
+            continue;
+        }
+
+        let consistent = counts.iter().all(|&c| c == counts[0]);
+        let score = if consistent { counts[0] } else { total };
+        debug!(
+            "Delimiter {:?}: consistent={}, score={}",
+            candidate as char, consistent, score
+        );
+
+        let better = match best {
+            None => true,
+            Some((best_consistent, best_score, _)) => {
+                (consistent, score) > (best_consistent, best_score)
+            }
+        };
+        if better {
+            best = Some((consistent, score, candidate));
+        }
+    }
+
+    best.map(|(_, _, delimiter)| delimiter)
+}
+
+/// The fields of the first non-empty line of a CSV file
+///
+/// Used to compare a file's header (or first row) with a declared schema.
+/// Returns `None` for a file without any non-empty line.
+///
+/// # Errors
+///
+/// Returns an IO error if the file cannot be read
+pub fn csv_first_record(path: &Path, delimiter: u8) -> Result<Option<Vec<String>>> {
+    let reader = BufReader::new(open(path)?);
+    for line in reader.lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            return Ok(Some(split_unquoted(&line, delimiter)));
+        }
+    }
+    Ok(None)
+}
+
+/// Split a line at `delimiter`s outside double quotes, unquoting each field
+fn split_unquoted(line: &str, delimiter: u8) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = Vec::new();
+    let mut in_quotes = false;
+    for byte in line.bytes() {
+        if byte == b'"' {
+            in_quotes = !in_quotes;
+        } else if byte == delimiter && !in_quotes {
+            fields.push(String::from_utf8_lossy(&field).into_owned());
+            field.clear();
+        } else {
+            field.push(byte);
+        }
+    }
+    fields.push(String::from_utf8_lossy(&field).into_owned());
+    fields
+}
+
+/// Count occurrences of `delimiter` outside double-quoted sections of a line
+fn count_unquoted(line: &str, delimiter: u8) -> usize {
+    let mut in_quotes = false;
+    let mut count = 0;
+    for byte in line.bytes() {
+        if byte == b'"' {
+            in_quotes = !in_quotes;
+        } else if byte == delimiter && !in_quotes {
+            count += 1;
+        }
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_open_with_retry() {
+        let path = Path::new("data.csv");
+        let failing = |failures: u32, kind: io::ErrorKind| {
+            let mut attempts = 0;
+            move || {
+                attempts += 1;
+                if attempts <= failures {
+                    Err(io::Error::from(kind))
+                } else {
+                    Ok(attempts)
+                }
+            }
+        };
+
+        // Transient failures are retried until the file opens
+        let attempts = open_with_retry(path, 2, failing(2, io::ErrorKind::WouldBlock))
+            .await
+            .unwrap();
+        assert_eq!(attempts, 3);
+        let result = open_with_retry(path, 1, failing(2, io::ErrorKind::WouldBlock)).await;
+        assert!(matches!(result, Err(SqlStreamError::Io(_))));
+
+        // Another process holding the file is retried, then reported as such
+        let locked = || {
+            Err::<(), _>(io::Error::from_raw_os_error(if cfg!(windows) {
+                32
+            } else {
+                16
+            }))
+        };
+        let result = open_with_retry(path, 1, locked).await;
+        assert!(
+            matches!(&result, Err(SqlStreamError::FileAccess { detail, .. }) if detail.contains("in use"))
+        );
+
+        // Denied access is not retried
+        let result = open_with_retry(path, 3, failing(1, io::ErrorKind::PermissionDenied)).await;
+        match result {
+            Err(err @ SqlStreamError::FileAccess { .. }) => {
+                assert_eq!(err.to_string(), "Cannot open data.csv: permission denied")
+            }
+            other => panic!("expected FileAccess, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_spool() {
+        let temp = spool("id,name\n1,a\n".as_bytes(), "csv").unwrap();
+        assert_eq!(temp.path().extension().unwrap(), "csv");
+        assert_eq!(
+            std::fs::read_to_string(temp.path()).unwrap(),
+            "id,name\n1,a\n"
+        );
+    }
+
+    #[test]
+    fn test_is_blank() {
+        let check = |contents: &str| {
+            let mut file = tempfile::Builder::new().suffix(".csv").tempfile().unwrap();
+            write!(file, "{}", contents).unwrap();
+            (
+                is_blank(file.path()).unwrap(),
+                csv_header_only(file.path()).unwrap(),
+            )
+        };
+
+        assert_eq!(check(""), (true, false));
+        assert_eq!(check(&format!("{} \n\n", BOM)), (true, false));
+        assert_eq!(check("id,name\n"), (false, true));
+        assert_eq!(check("id,name\n\n"), (false, true));
+        assert_eq!(check("id,name\n1,a\n"), (false, false));
+    }
+
+    #[test]
+    fn test_strip_bom() {
+        let mut file = tempfile::Builder::new().suffix(".csv").tempfile().unwrap();
+        write!(file, "{}name,age\nAda,36\n", BOM).unwrap();
+        assert!(has_bom(file.path()).unwrap());
+
+        let stripped = strip_bom(file.path()).unwrap();
+        assert!(!has_bom(stripped.path()).unwrap());
+        assert_eq!(
+            std::fs::read_to_string(stripped.path()).unwrap(),
+            "name,age\nAda,36\n"
+        );
+
+        let header = skip_to_header(file.path(), &Regex::new("^name,").unwrap()).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(header.path()).unwrap(),
+            "name,age\nAda,36\n"
+        );
+    }
+
+    #[test]
+    fn test_remote_url() {
+        let url = remote_url("https://example.com/data/events.csv?raw=1").unwrap();
+        assert_eq!(url.path(), "/data/events.csv");
+        assert!(remote_url("http://localhost:8080/a.json").is_some());
+        let url = remote_url("s3://bucket/logs/a.csv.gz").unwrap();
+        assert_eq!(
+            (url.host_str(), url.path()),
+            (Some("bucket"), "/logs/a.csv.gz")
+        );
+        assert!(remote_url("ftp://example.com/a.csv").is_none());
+        assert!(remote_url("data/events.csv").is_none());
+        assert!(remote_url("C:\\data\\events.csv").is_none());
+    }
+
+    #[test]
+    fn test_expand_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["b.csv", "a.csv", "notes.txt", "_SUCCESS", ".hidden.csv"] {
+            std::fs::write(dir.path().join(name), "id\n1\n").unwrap();
+        }
+        std::fs::create_dir(dir.path().join("nested")).unwrap();
+
+        let names = |files: Vec<PathBuf>| {
+            files
+                .iter()
+                .map(|f| f.file_name().unwrap().to_string_lossy().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let pattern = dir.path().join("*.csv");
+        let files = expand_paths(pattern.to_str().unwrap()).unwrap();
+        assert_eq!(names(files), ["a.csv", "b.csv"]);
+
+        let files = expand_paths(dir.path().to_str().unwrap()).unwrap();
+        assert_eq!(names(files), ["a.csv", "b.csv", "notes.txt"]);
+
+        let pattern = dir.path().join("*.json");
+        assert!(matches!(
+            expand_paths(pattern.to_str().unwrap()),
+            Err(SqlStreamError::FileNotFound(_))
+        ));
+        assert!(is_glob("logs/part-[0-9].csv"));
+        assert!(!is_glob("logs/part-0.csv"));
+    }
+
+    #[test]
+    fn test_hive_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        for partition in [
+            "year=2023/month=12",
+            "year=2024/month=01",
+            "year=2024/month=02",
+        ] {
+            let path = dir.path().join(partition);
+            std::fs::create_dir_all(&path).unwrap();
+            std::fs::write(path.join("part-0.csv"), "id\n1\n").unwrap();
+        }
+        std::fs::write(dir.path().join("_SUCCESS"), "").unwrap();
+
+        let layout = hive_layout(dir.path()).unwrap().unwrap();
+        assert_eq!(
+            layout.columns,
+            [
+                (
+                    "year".to_string(),
+                    vec!["2023".to_string(), "2024".to_string()]
+                ),
+                (
+                    "month".to_string(),
+                    vec!["01".to_string(), "02".to_string(), "12".to_string()]
+                ),
+            ]
+        );
+        assert_eq!(layout.files.len(), 3);
+        assert!(layout.files[0].ends_with("year=2023/month=12/part-0.csv"));
+
+        // Files beside partition directories make the layout ambiguous
+        std::fs::write(dir.path().join("year=2024/stray.csv"), "id\n1\n").unwrap();
+        assert!(matches!(
+            hive_layout(dir.path()),
+            Err(SqlStreamError::InvalidOption(_))
+        ));
+
+        let flat = tempfile::tempdir().unwrap();
+        std::fs::write(flat.path().join("a.csv"), "id\n1\n").unwrap();
+        std::fs::create_dir(flat.path().join("nested")).unwrap();
+        assert_eq!(hive_layout(flat.path()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_compressed_extensions() {
+        assert_eq!(format_extension(Path::new("a/events.csv.gz")), Some("csv"));
+        assert_eq!(format_extension(Path::new("events.JSON.zst")), Some("JSON"));
+        assert_eq!(format_extension(Path::new("events.csv")), Some("csv"));
+        assert_eq!(format_extension(Path::new("events.gz")), None);
+        assert!(compression(Path::new("events.csv.GZ")).is_compressed());
+        assert!(!compression(Path::new("events.csv")).is_compressed());
+    }
+
+    #[test]
+    fn test_skip_to_header() {
+        let mut input = NamedTempFile::new().unwrap();
+        writeln!(input, "Exported: 2024-03-14\nRows: 2\n\nid,name\n1,a\n2,b").unwrap();
+
+        let pattern = Regex::new(r"^id,name$").unwrap();
+        let output = skip_to_header(input.path(), &pattern).unwrap();
+
+        let contents = std::fs::read_to_string(output.path()).unwrap();
+        assert_eq!(contents, "id,name\n1,a\n2,b\n");
+    }
+
+    #[test]
+    fn test_header_not_found() {
+        let mut input = NamedTempFile::new().unwrap();
+        writeln!(input, "a,b\n1,2").unwrap();
+
+        let pattern = Regex::new("missing").unwrap();
+        let result = skip_to_header(input.path(), &pattern);
+        assert!(matches!(result, Err(SqlStreamError::HeaderNotFound { .. })));
+    }
+
+    #[test]
+    fn test_clean_ndjson() {
+        let mut input = NamedTempFile::new().unwrap();
+        write!(input, "\u{feff}{{\"a\": 1}}\n\n  \n{{\"a\": 2}}\r\n\n").unwrap();
+
+        let output = clean_ndjson(input.path(), JsonMode::Auto).unwrap();
+        let contents = std::fs::read_to_string(output.path()).unwrap();
+        assert_eq!(contents, "{\"a\": 1}\n{\"a\": 2}\n");
+    }
+
+    #[test]
+    fn test_clean_json_array() {
+        let mut input = NamedTempFile::new().unwrap();
+        write!(
+            input,
+            "\u{feff}\n  [\n  {{\"a\": 1}},\n  {{\"a\": 2, \"b\": [1, 2]}}\n]\n"
+        )
+        .unwrap();
+
+        let output = clean_ndjson(input.path(), JsonMode::Auto).unwrap();
+        let contents = std::fs::read_to_string(output.path()).unwrap();
+        assert_eq!(contents, "{\"a\":1}\n{\"a\":2,\"b\":[1,2]}\n");
+        let output = clean_ndjson(input.path(), JsonMode::Array).unwrap();
+        let contents = std::fs::read_to_string(output.path()).unwrap();
+        assert_eq!(contents, "{\"a\":1}\n{\"a\":2,\"b\":[1,2]}\n");
+
+        for invalid in ["[{\"a\": 1}, 2]", "[{\"a\": 1},"] {
+            let mut input = NamedTempFile::new().unwrap();
+            write!(input, "{}", invalid).unwrap();
+            assert!(matches!(
+                clean_ndjson(input.path(), JsonMode::Auto),
+                Err(SqlStreamError::JsonArray { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_clean_json_modes() {
+        let mut input = NamedTempFile::new().unwrap();
+        write!(input, "\n{{\"a\": 1}}\n{{\"a\": 2}}\n").unwrap();
+
+        // NDJSON never goes through the array parser, and array mode rejects it
+        let output = clean_ndjson(input.path(), JsonMode::Ndjson).unwrap();
+        let contents = std::fs::read_to_string(output.path()).unwrap();
+        assert_eq!(contents, "{\"a\": 1}\n{\"a\": 2}\n");
+        assert!(matches!(
+            clean_ndjson(input.path(), JsonMode::Array),
+            Err(SqlStreamError::JsonArray { .. })
+        ));
+
+        // An array read as NDJSON is copied line by line, not parsed
+        let mut input = NamedTempFile::new().unwrap();
+        writeln!(input, "[{{\"a\": 1}}]").unwrap();
+        let output = clean_ndjson(input.path(), JsonMode::Ndjson).unwrap();
+        let contents = std::fs::read_to_string(output.path()).unwrap();
+        assert_eq!(contents, "[{\"a\": 1}]\n");
+    }
+
+    fn sniff(contents: &str) -> Option<u8> {
+        let mut input = NamedTempFile::new().unwrap();
+        write!(input, "{}", contents).unwrap();
+        sniff_delimiter(input.path()).unwrap()
+    }
+
+    #[test]
+    fn test_sniff_comma() {
+        assert_eq!(
+            sniff("id,name,city\n1,\"Smith; J\",NYC\n2,Bob,LA\n"),
+            Some(b',')
+        );
+    }
+
+    #[test]
+    fn test_sniff_tab() {
+        assert_eq!(sniff("id\tname\tnote\n1\ta\tx, y\n2\tb\tz\n"), Some(b'\t'));
+    }
+
+    #[test]
+    fn test_sniff_semicolon() {
+        assert_eq!(sniff("id;amount\n1;1,50\n2;20,00\n"), Some(b';'));
+    }
+
+    #[test]
+    fn test_sniff_pipe() {
+        assert_eq!(sniff("id|name|city\n1|a|b\n2|c|d\n"), Some(b'|'));
+    }
+
+    #[test]
+    fn test_sniff_no_delimiter() {
+        assert_eq!(sniff("single\ncolumn\n"), None);
+    }
+
+    #[test]
+    fn test_csv_first_record() {
+        let temp = spool("\nid,\"Name, full\",city\n1,a,b\n".as_bytes(), "csv").unwrap();
+        assert_eq!(
+            csv_first_record(temp.path(), b',').unwrap(),
+            Some(vec![
+                "id".to_string(),
+                "Name, full".to_string(),
+                "city".to_string()
+            ])
+        );
+
+        let empty = spool("".as_bytes(), "csv").unwrap();
+        assert_eq!(csv_first_record(empty.path(), b',').unwrap(), None);
+    }
+}
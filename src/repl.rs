@@ -0,0 +1,411 @@
+//! Interactive SQL shell
+//!
+//! Reads statements from an input stream and runs each one against the same
+//! engine, so files are registered and their schemas inferred only once per
+//! session. Statements may span several lines and end with `;`. The `\d`
+//! command lists the registered tables and `\d name` prints a table's schema;
+//! `\load path name` registers another file, or reloads one when tables are
+//! replaceable. `\pager` toggles paging results through `$PAGER`, and `\copy`
+//! puts the last result on the clipboard as tab-separated values (with the
+//! `clipboard` feature).
+
+use crate::analysis::schema_table;
+use crate::engine::{CacheStatus, QueryEngine, ReadOptions};
+use crate::error::{Result, SqlStreamError};
+use crate::output::{format_table, PrintFormat};
+use crate::sql::StatementBuffer;
+use datafusion::arrow::csv::WriterBuilder;
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::arrow::record_batch::RecordBatch;
+use std::io::{ErrorKind, Write};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+use tracing::debug;
+
+/// Prompt shown when waiting for a new statement
+pub const PROMPT: &str = "sql> ";
+
+/// Prompt shown while a statement continues over several lines
+pub const CONTINUATION_PROMPT: &str = "  -> ";
+
+/// Command that ends the session
+pub const QUIT_COMMAND: &str = "\\q";
+
+/// Command that lists tables, or describes one table's schema
+pub const DESCRIBE_COMMAND: &str = "\\d";
+
+/// Command that lists tables
+pub const TABLES_COMMAND: &str = "\\dt";
+
+/// Command that registers a file as a table
+pub const LOAD_COMMAND: &str = "\\load";
+
+/// Command that turns paging of results through `$PAGER` on or off
+pub const PAGER_COMMAND: &str = "\\pager";
+
+/// Command that copies the last result to the clipboard
+pub const COPY_COMMAND: &str = "\\copy";
+
+/// Pager used when `$PAGER` is unset or blank; `-S` keeps wide rows unwrapped
+pub const DEFAULT_PAGER: &str = "less -S";
+
+/// Settings and state carried between the statements of a session
+#[derive(Debug, Default)]
+struct Session {
+    /// Pipe results through the pager instead of printing them
+    pager: bool,
+    /// Report to stderr whether the result cache answered each query
+    cache_status: bool,
+    /// Schema and batches of the last statement that succeeded, for `\copy`
+    last_result: Option<(SchemaRef, Vec<RecordBatch>)>,
+}
+
+/// Run statements read from `input` until EOF or `\q`
+///
+/// Prompts and results are written to `output`, normally stdout, with
+/// results in `format` or through the pager once `\pager` turns it on. A
+/// statement that fails prints its error to stderr
+/// and the session continues with the next one. At EOF a
+/// final statement without a trailing `;` is still executed. Files loaded with
+/// `\load` are read with `options`; set [`ReadOptions::replace`] to let them
+/// replace existing tables. With `cache_status`, each query run through the
+/// engine's result cache is followed by a line on stderr saying whether it
+/// was a hit.
+///
+/// # Errors
+///
+/// Returns an error only if reading the input or writing the output fails
+pub async fn run<R: AsyncBufRead + Unpin, W: Write>(
+    engine: &mut QueryEngine,
+    input: R,
+    output: &mut W,
+    format: PrintFormat,
+    cache_status: bool,
+    options: &ReadOptions,
+) -> Result<()> {
+    let mut lines = input.lines();
+    let mut statements = StatementBuffer::default();
+    let mut session = Session {
+        cache_status,
+        ..Default::default()
+    };
+
+    loop {
+        let prompt = if statements.is_empty() {
+            PROMPT
+        } else {
+            CONTINUATION_PROMPT
+        };
+        write!(output, "{}", prompt)?;
+        output.flush()?;
+
+        let Some(line) = lines.next_line().await? else {
+            writeln!(output)?;
+            if let Some(statement) = statements.take_remaining() {
+                execute(engine, &statement, format, &mut session, output).await;
+            }
+            break;
+        };
+
+        if statements.is_empty() {
+            let line = line.trim();
+            if line == QUIT_COMMAND {
+                break;
+            }
+            if line == PAGER_COMMAND {
+                session.pager = !session.pager;
+                if session.pager {
+                    let (program, args) = pager_command(std::env::var("PAGER").ok().as_deref());
+                    let command: Vec<_> = std::iter::once(program).chain(args).collect();
+                    writeln!(output, "Pager is on: {}", command.join(" "))?;
+                } else {
+                    writeln!(output, "Pager is off")?;
+                }
+                continue;
+            }
+            if line == COPY_COMMAND {
+                if let Err(e) = copy(&session, output) {
+                    eprintln!("Error: {}", e);
+                }
+                continue;
+            }
+            if let Some(args) = line.strip_prefix(LOAD_COMMAND) {
+                if let Err(e) = load(engine, args.trim(), options, output).await {
+                    eprintln!("Error: {}", e);
+                }
+                continue;
+            }
+            if line == TABLES_COMMAND {
+                list_tables(engine, output)?;
+                continue;
+            }
+            if let Some(table) = line.strip_prefix(DESCRIBE_COMMAND) {
+                if let Err(e) = describe(engine, table.trim(), output).await {
+                    eprintln!("Error: {}", e);
+                }
+                continue;
+            }
+        }
+
+        for statement in statements.push_line(&line) {
+            execute(engine, &statement, format, &mut session, output).await;
+        }
+    }
+
+    debug!("Interactive session ended");
+    Ok(())
+}
+
+/// Write the registered tables, one per line
+fn list_tables(engine: &QueryEngine, output: &mut impl Write) -> Result<()> {
+    for name in engine.list_tables() {
+        writeln!(output, "{}", name)?;
+    }
+    Ok(())
+}
+
+/// List the registered tables, or print the schema of `table` when given
+async fn describe(engine: &QueryEngine, table: &str, output: &mut impl Write) -> Result<()> {
+    if table.is_empty() {
+        return list_tables(engine, output);
+    }
+
+    let schema = engine.table_schema(table).await?;
+    writeln!(output, "{}", format_table(&[schema_table(&schema)?])?)?;
+    Ok(())
+}
+
+/// Register the file named in `\load path name` arguments as table `name`
+async fn load(
+    engine: &mut QueryEngine,
+    args: &str,
+    options: &ReadOptions,
+    output: &mut impl Write,
+) -> Result<()> {
+    let Some((path, table)) = args.rsplit_once(char::is_whitespace) else {
+        return Err(SqlStreamError::InvalidOption(format!(
+            "usage: {} PATH TABLE",
+            LOAD_COMMAND
+        )));
+    };
+    let path = path.trim();
+    engine.register_file_with(path, table, options).await?;
+    writeln!(output, "Loaded {} as table {}", path, table)?;
+    Ok(())
+}
+
+/// Execute one statement and show its results, reporting errors to stderr
+async fn execute(
+    engine: &QueryEngine,
+    sql: &str,
+    format: PrintFormat,
+    session: &mut Session,
+    output: &mut impl Write,
+) {
+    if let Err(e) = execute_statement(engine, sql, format, session, output).await {
+        eprintln!("Error: {}", e);
+    }
+}
+
+/// Execute one statement, keep its result for `\copy` and show it
+///
+/// The result is collected and rendered in full before it is shown, so the
+/// pager gets it in one piece.
+async fn execute_statement(
+    engine: &QueryEngine,
+    sql: &str,
+    format: PrintFormat,
+    session: &mut Session,
+    output: &mut impl Write,
+) -> Result<()> {
+    let (dataframe, status) = engine.execute_cached_status(sql).await?;
+    if session.cache_status {
+        if let Some(report) = cache_report(&status) {
+            eprintln!("{}", report);
+        }
+    }
+    let planned = dataframe.schema().inner().clone();
+    let batches = dataframe
+        .collect()
+        .await
+        .map_err(SqlStreamError::execution)?;
+    let schema = batches.first().map_or(planned, RecordBatch::schema);
+
+    let mut rendered = Vec::new();
+    let frame = engine.cached_frame(Arc::clone(&schema), batches.clone())?;
+    engine.write_formatted(frame, format, &mut rendered).await?;
+    session.last_result = Some((schema, batches));
+    show(&rendered, session.pager, output)
+}
+
+/// The `--cache-status` line for a statement, if it went through the cache
+fn cache_report(status: &CacheStatus) -> Option<String> {
+    match status {
+        CacheStatus::Hit { key, age } => Some(format!(
+            "cache hit ({:.1}s old): {}",
+            age.as_secs_f64(),
+            key
+        )),
+        CacheStatus::Miss { key } => Some(format!("cache miss: {}", key)),
+        CacheStatus::Bypassed => None,
+    }
+}
+
+/// Program and arguments of the pager `pager` names, normally `$PAGER`
+///
+/// Falls back to [`DEFAULT_PAGER`] when `pager` is unset or blank.
+fn pager_command(pager: Option<&str>) -> (String, Vec<String>) {
+    let pager = pager
+        .filter(|pager| !pager.trim().is_empty())
+        .unwrap_or(DEFAULT_PAGER);
+    let mut words = pager.split_whitespace().map(str::to_string);
+    let program = words.next().unwrap_or_default();
+    (program, words.collect())
+}
+
+/// Show rendered results, through the pager when `pager` is set
+///
+/// A pager that cannot be started is reported and the results written to
+/// `output` directly.
+fn show(rendered: &[u8], pager: bool, output: &mut impl Write) -> Result<()> {
+    if pager {
+        let (program, args) = pager_command(std::env::var("PAGER").ok().as_deref());
+        match Command::new(&program)
+            .args(&args)
+            .stdin(Stdio::piped())
+            .spawn()
+        {
+            Ok(mut child) => {
+                if let Some(mut stdin) = child.stdin.take() {
+                    // Quitting the pager early closes its input
+                    match stdin.write_all(rendered) {
+                        Err(e) if e.kind() != ErrorKind::BrokenPipe => return Err(e.into()),
+                        _ => {}
+                    }
+                }
+                child.wait()?;
+                return Ok(());
+            }
+            Err(e) => eprintln!("Cannot start pager '{}': {}", program, e),
+        }
+    }
+
+    output.write_all(rendered)?;
+    output.flush()?;
+    Ok(())
+}
+
+/// Copy the last result to the clipboard as tab-separated values
+fn copy(session: &Session, output: &mut impl Write) -> Result<()> {
+    let Some((schema, batches)) = &session.last_result else {
+        return Err(SqlStreamError::InvalidOption(
+            "there is no result to copy yet".to_string(),
+        ));
+    };
+    set_clipboard(tsv(schema, batches)?)?;
+    let rows: usize = batches.iter().map(RecordBatch::num_rows).sum();
+    writeln!(output, "Copied {} rows to the clipboard", rows)?;
+    Ok(())
+}
+
+/// Render batches as tab-separated values with a header row
+///
+/// This is the layout spreadsheets expect when pasting. Fields holding a
+/// tab, quote or line break are quoted as in CSV.
+fn tsv(schema: &SchemaRef, batches: &[RecordBatch]) -> Result<String> {
+    let mut buffer = Vec::new();
+    {
+        let mut writer = WriterBuilder::new()
+            .with_delimiter(b'\t')
+            .with_header(true)
+            .build(&mut buffer);
+        // The header is written with the first batch, even an empty one
+        writer.write(&RecordBatch::new_empty(Arc::clone(schema)))?;
+        for batch in batches {
+            writer.write(batch)?;
+        }
+    }
+    String::from_utf8(buffer).map_err(|e| SqlStreamError::Output(e.to_string()))
+}
+
+/// Put `text` on the system clipboard
+#[cfg(feature = "clipboard")]
+fn set_clipboard(text: String) -> Result<()> {
+    let unavailable = |e: arboard::Error| SqlStreamError::Output(format!("clipboard: {}", e));
+    arboard::Clipboard::new()
+        .map_err(unavailable)?
+        .set_text(text)
+        .map_err(unavailable)
+}
+
+/// Stand-in for [`set_clipboard`] when the `clipboard` feature is disabled
+#[cfg(not(feature = "clipboard"))]
+fn set_clipboard(_text: String) -> Result<()> {
+    Err(SqlStreamError::InvalidOption(format!(
+        "{} requires sql-stream to be built with the `clipboard` feature",
+        COPY_COMMAND
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_report() {
+        let hit = CacheStatus::Hit {
+            key: "SELECT 1".to_string(),
+            age: std::time::Duration::from_millis(2500),
+        };
+        assert_eq!(
+            cache_report(&hit).as_deref(),
+            Some("cache hit (2.5s old): SELECT 1")
+        );
+        let miss = CacheStatus::Miss {
+            key: "SELECT 1".to_string(),
+        };
+        assert_eq!(cache_report(&miss).as_deref(), Some("cache miss: SELECT 1"));
+        assert_eq!(cache_report(&CacheStatus::Bypassed), None);
+    }
+
+    #[test]
+    fn test_pager_command() {
+        assert_eq!(
+            pager_command(None),
+            ("less".to_string(), vec!["-S".to_string()])
+        );
+        assert_eq!(pager_command(Some("  ")), pager_command(None));
+        assert_eq!(pager_command(Some("more")), ("more".to_string(), vec![]));
+        assert_eq!(
+            pager_command(Some("less -R  -S")),
+            ("less".to_string(), vec!["-R".to_string(), "-S".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_tsv() {
+        use datafusion::arrow::array::{Int64Array, StringArray};
+        use datafusion::arrow::datatypes::{DataType, Field, Schema};
+
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("name", DataType::Utf8, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            Arc::clone(&schema),
+            vec![
+                Arc::new(Int64Array::from(vec![1, 2])),
+                Arc::new(StringArray::from(vec![Some("Alice, Jr."), None])),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(
+            tsv(&schema, &[batch]).unwrap(),
+            "id\tname\n1\tAlice, Jr.\n2\t\n"
+        );
+        // An empty result still has its header
+        assert_eq!(tsv(&schema, &[]).unwrap(), "id\tname\n");
+    }
+}
@@ -0,0 +1,328 @@
+//! Explicit schemas for input files
+//!
+//! Schema inference guesses column types from a sample of rows, which can go
+//! wrong on brittle data. A declared schema replaces inference entirely. It is
+//! written either inline as `name:Type` pairs, such as `id:Int64,name:Utf8`,
+//! or as a JSON file listing the fields:
+//!
+//! ```json
+//! {"fields": [{"name": "id", "type": "Int64", "nullable": false},
+//!             {"name": "name", "type": "Utf8"}]}
+//! ```
+//!
+//! Types use Arrow's names as printed by `schema` mode (`Int64`, `Float64`,
+//! `Utf8`, `Date32`, `Timestamp(Nanosecond, None)`, ...) or one of a few SQL
+//! aliases such as `bigint`, `double`, `text` and `date`. Fields are nullable
+//! unless a JSON field says otherwise. The same type names are accepted by
+//! `--cast` (see [`parse_cast`]).
+
+use crate::error::{Result, SqlStreamError};
+use datafusion::arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Parse a `--schema` value: a JSON schema file if `spec` names one, otherwise an inline spec
+///
+/// # Errors
+///
+/// Returns [`SqlStreamError::InvalidOption`] if the spec or file is malformed,
+/// names an unknown type or declares a column twice
+pub fn parse_schema(spec: &str) -> Result<Schema> {
+    let path = Path::new(spec);
+    if path.is_file() {
+        let text = std::fs::read_to_string(path)?;
+        return parse_json(&text);
+    }
+    parse_inline(spec)
+}
+
+/// Parse an inline `name:Type,name:Type` schema
+///
+/// Commas inside a type's parentheses, as in `Timestamp(Millisecond, None)`,
+/// do not separate fields.
+///
+/// # Errors
+///
+/// Returns [`SqlStreamError::InvalidOption`] if a field has no name or type,
+/// a type is unknown, or a column is declared twice
+pub fn parse_inline(spec: &str) -> Result<Schema> {
+    let fields = split_fields(spec)
+        .into_iter()
+        .map(|part| {
+            let (name, data_type) = part
+                .split_once(':')
+                .ok_or_else(|| invalid(format!("expected name:Type, got '{}'", part.trim())))?;
+            let name = name.trim();
+            if name.is_empty() {
+                return Err(invalid(format!("missing column name in '{}'", part.trim())));
+            }
+            Ok(Field::new(name, parse_type(data_type)?, true))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    build_schema(fields)
+}
+
+/// Parse a JSON schema: an object with a `fields` array, or the array itself
+///
+/// Each field is an object with a `name`, a `type` and an optional `nullable`
+/// flag (default true).
+///
+/// # Errors
+///
+/// Returns [`SqlStreamError::InvalidOption`] if the JSON is malformed, a field
+/// lacks a name or type, a type is unknown, or a column is declared twice
+pub fn parse_json(text: &str) -> Result<Schema> {
+    let value: Value =
+        serde_json::from_str(text).map_err(|e| invalid(format!("invalid JSON: {}", e)))?;
+    let fields = match &value {
+        Value::Array(fields) => fields,
+        Value::Object(object) => object
+            .get("fields")
+            .and_then(Value::as_array)
+            .ok_or_else(|| invalid("JSON schema object needs a \"fields\" array".to_string()))?,
+        _ => {
+            return Err(invalid(
+                "JSON schema must be an object or an array".to_string(),
+            ))
+        }
+    };
+
+    let fields = fields
+        .iter()
+        .map(|field| {
+            let text = |key: &str| {
+                field
+                    .get(key)
+                    .and_then(Value::as_str)
+                    .ok_or_else(|| invalid(format!("field {} needs a string \"{}\"", field, key)))
+            };
+            let nullable = match field.get("nullable") {
+                None => true,
+                Some(value) => value.as_bool().ok_or_else(|| {
+                    invalid(format!("\"nullable\" must be true or false in {}", field))
+                })?,
+            };
+            Ok(Field::new(
+                text("name")?,
+                parse_type(text("type")?)?,
+                nullable,
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    build_schema(fields)
+}
+
+/// Parse a `--cast` value: a column name and the type to cast it to, as `name:Type`
+///
+/// Types are written as in a schema spec; the column name ends at the first colon.
+///
+/// # Errors
+///
+/// Returns [`SqlStreamError::InvalidOption`] if the name or type is missing or
+/// the type is unknown
+pub fn parse_cast(spec: &str) -> Result<(String, DataType)> {
+    let invalid =
+        |reason: String| SqlStreamError::InvalidOption(format!("Invalid --cast: {}", reason));
+    let (name, data_type) = spec
+        .split_once(':')
+        .ok_or_else(|| invalid(format!("expected COLUMN:TYPE, got '{}'", spec.trim())))?;
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(invalid(format!("missing column name in '{}'", spec.trim())));
+    }
+    let data_type = parse_type(data_type)
+        .map_err(|_| invalid(format!("unknown type '{}'", data_type.trim())))?;
+    Ok((name.to_string(), data_type))
+}
+
+/// Write a schema as an inline `name:Type` spec that [`parse_inline`] reads back
+///
+/// Nullability is not part of the inline form; every field parses as nullable.
+pub fn to_inline(schema: &Schema) -> String {
+    schema
+        .fields()
+        .iter()
+        .map(|field| format!("{}:{}", field.name(), field.data_type()))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Write a schema as JSON that [`parse_json`] reads back
+pub fn to_json(schema: &Schema) -> Value {
+    let fields: Vec<Value> = schema
+        .fields()
+        .iter()
+        .map(|field| {
+            json!({
+                "name": field.name(),
+                "type": field.data_type().to_string(),
+                "nullable": field.is_nullable(),
+            })
+        })
+        .collect();
+    json!({ "fields": fields })
+}
+
+/// Parse an Arrow type name or SQL alias
+fn parse_type(name: &str) -> Result<DataType> {
+    let name = name.trim();
+    let alias = match name.to_lowercase().as_str() {
+        "string" | "text" | "varchar" => Some(DataType::Utf8),
+        "int" | "integer" => Some(DataType::Int32),
+        "bigint" | "long" => Some(DataType::Int64),
+        "float" | "real" => Some(DataType::Float32),
+        "double" => Some(DataType::Float64),
+        "bool" | "boolean" => Some(DataType::Boolean),
+        "date" => Some(DataType::Date32),
+        "timestamp" => Some(DataType::Timestamp(TimeUnit::Nanosecond, None)),
+        _ => None,
+    };
+
+    match alias {
+        Some(data_type) => Ok(data_type),
+        None => DataType::from_str(name).map_err(|_| invalid(format!("unknown type '{}'", name))),
+    }
+}
+
+/// Split an inline spec at commas outside parentheses
+fn split_fields(spec: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (i, c) in spec.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                parts.push(&spec[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&spec[start..]);
+    parts
+}
+
+/// Build a schema, rejecting empty and duplicated column lists
+fn build_schema(fields: Vec<Field>) -> Result<Schema> {
+    if fields.is_empty() {
+        return Err(invalid("no columns declared".to_string()));
+    }
+
+    let mut seen = HashSet::new();
+    for field in &fields {
+        if !seen.insert(field.name().as_str()) {
+            return Err(invalid(format!("column '{}' declared twice", field.name())));
+        }
+    }
+    Ok(Schema::new(fields))
+}
+
+fn invalid(reason: String) -> SqlStreamError {
+    SqlStreamError::InvalidOption(format!("Invalid --schema: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn schema() -> Schema {
+        Schema::new(vec![
+            Field::new("id", DataType::Int64, true),
+            Field::new("name", DataType::Utf8, true),
+            Field::new("score", DataType::Float64, true),
+            Field::new(
+                "seen",
+                DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+                true,
+            ),
+            Field::new("joined", DataType::Date32, true),
+        ])
+    }
+
+    #[test]
+    fn test_inline_round_trip() {
+        let spec = to_inline(&schema());
+        assert_eq!(parse_inline(&spec).unwrap(), schema());
+
+        let parsed = parse_inline(" id : bigint , name:text,score:double ").unwrap();
+        let types: Vec<_> = parsed.fields().iter().map(|f| f.data_type()).collect();
+        assert_eq!(
+            types,
+            [&DataType::Int64, &DataType::Utf8, &DataType::Float64]
+        );
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let mut fields = schema().fields().to_vec();
+        fields[0] = Arc::new(Field::new("id", DataType::Int64, false));
+        let schema = Schema::new(fields);
+
+        let text = to_json(&schema).to_string();
+        assert_eq!(parse_json(&text).unwrap(), schema);
+
+        // A bare array of fields is accepted too
+        let parsed = parse_json(r#"[{"name": "id", "type": "Int32"}]"#).unwrap();
+        assert_eq!(parsed.field(0).data_type(), &DataType::Int32);
+        assert!(parsed.field(0).is_nullable());
+    }
+
+    #[test]
+    fn test_invalid_schemas() {
+        for spec in [
+            "",
+            "id",
+            "id:Int64,",
+            ":Int64",
+            "id:Nope",
+            "id:Int64,id:Utf8",
+        ] {
+            assert!(
+                matches!(parse_inline(spec), Err(SqlStreamError::InvalidOption(_))),
+                "{}",
+                spec
+            );
+        }
+        assert!(parse_json("{}").is_err());
+        assert!(parse_json(r#"[{"name": "id"}]"#).is_err());
+        assert!(parse_json(r#"[{"name": "id", "type": "Int64", "nullable": "no"}]"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_cast() {
+        assert_eq!(
+            parse_cast("zip:Utf8").unwrap(),
+            ("zip".to_string(), DataType::Utf8)
+        );
+        assert_eq!(
+            parse_cast(" ts : Timestamp(Millisecond, None)").unwrap(),
+            (
+                "ts".to_string(),
+                DataType::Timestamp(TimeUnit::Millisecond, None)
+            )
+        );
+        for spec in ["zip", ":Utf8", "zip:Nope"] {
+            assert!(
+                matches!(parse_cast(spec), Err(SqlStreamError::InvalidOption(_))),
+                "{}",
+                spec
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_schema_reads_files() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, to_json(&schema()).to_string().as_bytes()).unwrap();
+        let path = file.path().to_str().unwrap();
+        assert_eq!(parse_schema(path).unwrap(), schema());
+        assert_eq!(parse_schema("id:Int64").unwrap().fields().len(), 1);
+    }
+}
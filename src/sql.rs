@@ -0,0 +1,691 @@
+//! Helpers for building SQL text
+//!
+//! Several CLI shortcuts synthesize SQL around user-supplied column names.
+//! These helpers keep the quoting rules in one place.
+
+use crate::error::{Result, SqlStreamError};
+
+/// Quote an identifier so it is used verbatim, preserving case and special characters
+///
+/// Embedded double quotes are escaped by doubling them.
+pub fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Quote a string literal, escaping embedded single quotes by doubling them
+pub fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Whether `name` can be written in SQL without quotes
+///
+/// Plain identifiers are ASCII letters, digits and underscores, not starting
+/// with a digit.
+pub fn is_plain_ident(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Reserved words that cannot be used as table names without quoting
+///
+/// Not every SQL keyword: only those the parser reads as syntax where a table
+/// name is expected, or right after one, such as `order`, `select` or `join`.
+const RESERVED_WORDS: &[&str] = &[
+    "all",
+    "and",
+    "as",
+    "between",
+    "by",
+    "case",
+    "cross",
+    "distinct",
+    "else",
+    "end",
+    "except",
+    "exists",
+    "false",
+    "from",
+    "full",
+    "group",
+    "having",
+    "in",
+    "inner",
+    "intersect",
+    "into",
+    "is",
+    "join",
+    "lateral",
+    "left",
+    "like",
+    "limit",
+    "natural",
+    "not",
+    "null",
+    "offset",
+    "on",
+    "or",
+    "order",
+    "outer",
+    "right",
+    "select",
+    "table",
+    "then",
+    "true",
+    "union",
+    "unnest",
+    "using",
+    "values",
+    "when",
+    "where",
+    "window",
+    "with",
+];
+
+/// Whether `name` is a reserved SQL word, compared case-insensitively
+pub fn is_reserved_word(name: &str) -> bool {
+    RESERVED_WORDS.contains(&name.to_lowercase().as_str())
+}
+
+/// A plain, non-reserved identifier close to `name`, to suggest in its place
+///
+/// Characters other than ASCII letters, digits and underscores become
+/// underscores, a leading digit gets a `t_` prefix and a reserved word a
+/// `_table` suffix.
+pub fn suggest_table_name(name: &str) -> String {
+    let suggestion: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if suggestion.is_empty() {
+        DEFAULT_SUGGESTION.to_string()
+    } else if suggestion.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("t_{}", suggestion)
+    } else if is_reserved_word(&suggestion) {
+        format!("{}_table", suggestion)
+    } else {
+        suggestion
+    }
+}
+
+/// Suggested in place of an empty table name
+const DEFAULT_SUGGESTION: &str = "data";
+
+/// The name a table registered as `name` is known by in SQL
+///
+/// Like most SQL engines, DataFusion folds unquoted identifiers to lower case,
+/// so a plain name such as `MyData` is registered, and must be quoted, as
+/// `mydata`. Names that need quoting anyway are kept verbatim.
+pub fn normalize_table_name(name: &str) -> String {
+    if is_plain_ident(name) {
+        name.to_lowercase()
+    } else {
+        name.to_string()
+    }
+}
+
+/// A query returning every row and column of a table
+pub fn table_query(table_name: &str) -> String {
+    format!(
+        "SELECT * FROM {}",
+        quote_ident(&normalize_table_name(table_name))
+    )
+}
+
+/// A query returning the first `rows` rows of a table, for `--sample`
+pub fn sample_query(table_name: &str, rows: u64) -> String {
+    format!("{} LIMIT {}", table_query(table_name), rows)
+}
+
+/// A query's text with whitespace runs outside quotes collapsed to one space
+///
+/// Leading and trailing whitespace and a trailing `;` are dropped too, so
+/// queries differing only in layout compare equal. Quoted text is kept as is.
+pub fn normalize_query(sql: &str) -> String {
+    let mut normalized = String::with_capacity(sql.len());
+    let mut quote = None;
+    let mut space = false;
+    for c in sql.trim().trim_end_matches(';').trim_end().chars() {
+        match quote {
+            Some(open) if c == open => quote = None,
+            Some(_) => {}
+            None if c.is_whitespace() => {
+                space = true;
+                continue;
+            }
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None => {}
+        }
+        if std::mem::take(&mut space) {
+            normalized.push(' ');
+        }
+        normalized.push(c);
+    }
+    normalized
+}
+
+/// Whether a statement is a read-only query, starting with `SELECT`, `WITH` or `VALUES`
+pub fn is_query(sql: &str) -> bool {
+    let first = sql
+        .trim_start_matches(|c: char| c.is_whitespace() || c == '(')
+        .split(|c: char| !c.is_ascii_alphabetic())
+        .next()
+        .unwrap_or_default();
+    ["select", "with", "values"]
+        .iter()
+        .any(|keyword| first.eq_ignore_ascii_case(keyword))
+}
+
+/// Prefix a query with `EXPLAIN`, or `EXPLAIN ANALYZE` to also run it and collect metrics
+pub fn explain(query: &str, analyze: bool) -> String {
+    let keyword = if analyze {
+        "EXPLAIN ANALYZE"
+    } else {
+        "EXPLAIN"
+    };
+    format!("{} {}", keyword, query.trim())
+}
+
+/// Accumulates input lines and splits them into complete statements
+///
+/// A statement ends at a `;` outside string literals, quoted identifiers and
+/// `--` comments.
+#[derive(Debug, Default)]
+pub struct StatementBuffer {
+    buffer: String,
+    quote: Option<char>,
+}
+
+impl StatementBuffer {
+    /// Add a line of input, returning every statement it completes
+    pub fn push_line(&mut self, line: &str) -> Vec<String> {
+        let mut statements = Vec::new();
+        let mut chars = line.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match self.quote {
+                Some(quote) => {
+                    self.buffer.push(c);
+                    if c == quote {
+                        self.quote = None;
+                    }
+                }
+                None => match c {
+                    '\'' | '"' => {
+                        self.buffer.push(c);
+                        self.quote = Some(c);
+                    }
+                    '-' if chars.peek() == Some(&'-') => {
+                        // The rest of the line is a comment
+                        self.buffer.push(c);
+                        self.buffer.extend(chars.by_ref());
+                    }
+                    ';' => {
+                        let statement = self.buffer.trim().to_string();
+                        if !statement.is_empty() {
+                            statements.push(statement);
+                        }
+                        self.buffer.clear();
+                    }
+                    _ => self.buffer.push(c),
+                },
+            }
+        }
+
+        if !self.buffer.trim().is_empty() {
+            self.buffer.push('\n');
+        } else {
+            self.buffer.clear();
+        }
+        statements
+    }
+
+    /// Whether no partial statement is pending
+    pub fn is_empty(&self) -> bool {
+        self.buffer.trim().is_empty()
+    }
+
+    /// Take a pending statement that was never terminated with `;`
+    pub fn take_remaining(&mut self) -> Option<String> {
+        let statement = self.buffer.trim().to_string();
+        self.buffer.clear();
+        self.quote = None;
+        (!statement.is_empty()).then_some(statement)
+    }
+}
+
+/// Split a script into its `;`-separated statements
+///
+/// Semicolons inside string literals, quoted identifiers and `--` comments do
+/// not end a statement, and the last statement needs no trailing `;`. Pieces
+/// holding only comments are dropped.
+pub fn split_statements(script: &str) -> Vec<String> {
+    let mut buffer = StatementBuffer::default();
+    let mut statements: Vec<String> = script
+        .lines()
+        .flat_map(|line| buffer.push_line(line))
+        .collect();
+    statements.extend(buffer.take_remaining());
+
+    statements.retain(|statement| {
+        statement.lines().any(|line| {
+            let line = line.trim();
+            !line.is_empty() && !line.starts_with("--")
+        })
+    });
+    statements
+}
+
+/// A `--param` value, written into a query as a SQL literal
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryParam {
+    /// Name used by the `${name}` and `$name` placeholders
+    pub name: String,
+    /// The literal substituted for the placeholders
+    pub literal: String,
+}
+
+/// Parse a `NAME=VALUE` or `NAME:TYPE=VALUE` query parameter
+///
+/// The type decides how the value is written: `int` and `float` as numbers
+/// (negative ones in parentheses, so a preceding `-` can't start a comment),
+/// `bool` as `TRUE` or `FALSE`, and `string`, the default, as a quoted string
+/// literal. A value that doesn't parse as its type is rejected, so no value
+/// can change the structure of the query.
+///
+/// # Errors
+///
+/// Returns [`SqlStreamError::InvalidOption`] if the spec is malformed, the
+/// name is not a plain identifier, the type is unknown or the value doesn't
+/// parse as the type
+pub fn parse_param(spec: &str) -> Result<QueryParam> {
+    let invalid =
+        |reason: String| SqlStreamError::InvalidOption(format!("Invalid --param: {}", reason));
+    let (key, value) = spec
+        .split_once('=')
+        .ok_or_else(|| invalid(format!("expected NAME=VALUE, got '{}'", spec)))?;
+    let (name, kind) = match key.split_once(':') {
+        Some((name, kind)) => (name.trim(), kind.trim().to_lowercase()),
+        None => (key.trim(), "string".to_string()),
+    };
+    if !is_plain_ident(name) {
+        return Err(invalid(format!(
+            "'{}' is not a valid parameter name; use letters, digits and underscores",
+            name
+        )));
+    }
+
+    let mismatch = || {
+        invalid(format!(
+            "'{}' is not a valid {} for '{}'",
+            value, kind, name
+        ))
+    };
+    let negative = |literal: String| {
+        if literal.starts_with('-') {
+            format!("({})", literal)
+        } else {
+            literal
+        }
+    };
+    let literal = match kind.as_str() {
+        "int" | "integer" => {
+            let number: i64 = value.trim().parse().map_err(|_| mismatch())?;
+            negative(number.to_string())
+        }
+        "float" | "double" => {
+            let number: f64 = value.trim().parse().map_err(|_| mismatch())?;
+            if !number.is_finite() {
+                return Err(mismatch());
+            }
+            negative(format!("{:?}", number))
+        }
+        "bool" | "boolean" => match value.trim().to_lowercase().as_str() {
+            "true" => "TRUE".to_string(),
+            "false" => "FALSE".to_string(),
+            _ => return Err(mismatch()),
+        },
+        "string" | "str" | "text" => quote_literal(value),
+        _ => {
+            return Err(invalid(format!(
+                "unknown type '{}'; expected int, float, bool or string",
+                kind
+            )))
+        }
+    };
+    Ok(QueryParam {
+        name: name.to_string(),
+        literal,
+    })
+}
+
+/// Replace `${name}` and `$name` placeholders with the literals of `params`
+///
+/// Placeholders inside string literals, quoted identifiers, `--` comments and
+/// `/* ... */` comments are left alone, as are positional ones like `$1`. When several params
+/// share a name, the last one is used.
+///
+/// # Errors
+///
+/// Returns [`SqlStreamError::InvalidOption`] for a placeholder without a
+/// param, or a malformed `${...}`
+pub fn substitute_params(sql: &str, params: &[QueryParam]) -> Result<String> {
+    let mut substituted = String::with_capacity(sql.len());
+    let mut chars = sql.char_indices().peekable();
+    let mut quote = None;
+    let mut comment = false;
+    let mut block_comment = false;
+    while let Some((i, c)) = chars.next() {
+        if comment {
+            comment = c != '\n';
+        } else if block_comment {
+            if c == '*' && sql[i + 1..].starts_with('/') {
+                block_comment = false;
+                substituted.push(c);
+                chars.next();
+                substituted.push('/');
+                continue;
+            }
+        } else if let Some(open) = quote {
+            if c == open {
+                quote = None;
+            }
+        } else if c == '\'' || c == '"' {
+            quote = Some(c);
+        } else if c == '-' && sql[i + 1..].starts_with('-') {
+            comment = true;
+        } else if c == '/' && sql[i + 1..].starts_with('*') {
+            // The `*` is consumed here so `/*/` does not also close the comment
+            block_comment = true;
+            substituted.push(c);
+            chars.next();
+            substituted.push('*');
+            continue;
+        } else if c == '$' {
+            let rest = &sql[i + 1..];
+            let (name, end) = match rest.strip_prefix('{') {
+                Some(braced) => {
+                    let close = braced.find('}').ok_or_else(|| {
+                        SqlStreamError::InvalidOption(format!(
+                            "Unclosed query parameter '${}'",
+                            rest.lines().next().unwrap_or_default()
+                        ))
+                    })?;
+                    let name = &braced[..close];
+                    if !is_plain_ident(name) {
+                        return Err(SqlStreamError::InvalidOption(format!(
+                            "Invalid query parameter '${{{}}}'",
+                            name
+                        )));
+                    }
+                    (name, i + close + 3)
+                }
+                None => {
+                    let len = rest
+                        .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                        .unwrap_or(rest.len());
+                    (&rest[..len], i + len + 1)
+                }
+            };
+
+            if is_plain_ident(name) {
+                let param = params
+                    .iter()
+                    .rev()
+                    .find(|param| param.name == name)
+                    .ok_or_else(|| {
+                        SqlStreamError::InvalidOption(format!(
+                            "Unresolved query parameter '{}'; give its value with --param {}=VALUE",
+                            name, name
+                        ))
+                    })?;
+                substituted.push_str(&param.literal);
+                while chars.next_if(|(j, _)| *j < end).is_some() {}
+                continue;
+            }
+        }
+        substituted.push(c);
+    }
+    Ok(substituted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote_ident() {
+        assert_eq!(quote_ident("age"), "\"age\"");
+        assert_eq!(quote_ident("First Name"), "\"First Name\"");
+        assert_eq!(quote_ident("a\"b"), "\"a\"\"b\"");
+    }
+
+    #[test]
+    fn test_quote_literal() {
+        assert_eq!(quote_literal("%m/%d/%Y"), "'%m/%d/%Y'");
+        assert_eq!(quote_literal("%d 'de' %B"), "'%d ''de'' %B'");
+    }
+
+    #[test]
+    fn test_sample_query() {
+        assert_eq!(sample_query("data", 5), "SELECT * FROM \"data\" LIMIT 5");
+        assert_eq!(
+            sample_query("MyData", 10),
+            "SELECT * FROM \"mydata\" LIMIT 10"
+        );
+    }
+
+    #[test]
+    fn test_normalize_query() {
+        assert_eq!(
+            normalize_query("  SELECT *\n  FROM   data\tWHERE x = 'a  b' ;\n"),
+            "SELECT * FROM data WHERE x = 'a  b'"
+        );
+        assert_eq!(
+            normalize_query("SELECT \"my  col\" FROM data"),
+            "SELECT \"my  col\" FROM data"
+        );
+        assert_ne!(
+            normalize_query("SELECT 'a b'"),
+            normalize_query("SELECT 'a  b'")
+        );
+    }
+
+    #[test]
+    fn test_is_query() {
+        for query in [
+            "SELECT 1",
+            "with t AS (SELECT 1) SELECT * FROM t",
+            "(SELECT 1)",
+            "VALUES (1)",
+        ] {
+            assert!(is_query(query), "{}", query);
+        }
+        for statement in [
+            "CREATE VIEW v AS SELECT 1",
+            "SET a = 1",
+            "EXPLAIN SELECT 1",
+            "",
+        ] {
+            assert!(!is_query(statement), "{}", statement);
+        }
+    }
+
+    #[test]
+    fn test_explain() {
+        assert_eq!(
+            explain(" SELECT * FROM data ", false),
+            "EXPLAIN SELECT * FROM data"
+        );
+        assert_eq!(
+            explain("SELECT * FROM data", true),
+            "EXPLAIN ANALYZE SELECT * FROM data"
+        );
+    }
+
+    #[test]
+    fn test_table_names() {
+        for plain in ["data", "MyData", "_tmp", "sales_2024"] {
+            assert!(is_plain_ident(plain), "{}", plain);
+        }
+        for quoted in ["", "my data", "2024", "a.b", "sales-2024", "café"] {
+            assert!(!is_plain_ident(quoted), "{}", quoted);
+        }
+        assert_eq!(normalize_table_name("MyData"), "mydata");
+        assert_eq!(normalize_table_name("My Data"), "My Data");
+    }
+
+    #[test]
+    fn test_reserved_words() {
+        for reserved in ["order", "SELECT", "Join", "values"] {
+            assert!(is_reserved_word(reserved), "{}", reserved);
+        }
+        for plain in ["data", "orders", "user_order", "sales"] {
+            assert!(!is_reserved_word(plain), "{}", plain);
+        }
+    }
+
+    #[test]
+    fn test_suggest_table_name() {
+        assert_eq!(suggest_table_name("my-data"), "my_data");
+        assert_eq!(suggest_table_name("my data.csv"), "my_data_csv");
+        assert_eq!(suggest_table_name("123tbl"), "t_123tbl");
+        assert_eq!(suggest_table_name("Order"), "Order_table");
+        assert_eq!(suggest_table_name(""), "data");
+        for name in ["my-data", "123tbl", "Order", "", "café"] {
+            let suggestion = suggest_table_name(name);
+            assert!(
+                is_plain_ident(&suggestion) && !is_reserved_word(&suggestion),
+                "{} -> {}",
+                name,
+                suggestion
+            );
+        }
+    }
+
+    #[test]
+    fn test_split_statements() {
+        assert_eq!(
+            split_statements("CREATE VIEW v AS SELECT 1 AS x; SELECT * FROM v"),
+            vec!["CREATE VIEW v AS SELECT 1 AS x", "SELECT * FROM v"]
+        );
+        assert_eq!(
+            split_statements("-- setup\nSET a = 'x;y';\nSELECT 1; -- done\n"),
+            vec!["-- setup\nSET a = 'x;y'", "SELECT 1"]
+        );
+        assert!(split_statements(" ; -- nothing").is_empty());
+    }
+
+    #[test]
+    fn test_statements_span_lines() {
+        let mut buffer = StatementBuffer::default();
+        assert!(buffer.push_line("SELECT name").is_empty());
+        assert!(!buffer.is_empty());
+        assert_eq!(
+            buffer.push_line("FROM data; SELECT 1;"),
+            vec!["SELECT name\nFROM data", "SELECT 1"]
+        );
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_semicolons_in_quotes_and_comments() {
+        let mut buffer = StatementBuffer::default();
+        assert!(buffer
+            .push_line("SELECT 'a;b' AS \"x;y\" -- trailing;")
+            .is_empty());
+        assert_eq!(
+            buffer.push_line("FROM data;"),
+            vec!["SELECT 'a;b' AS \"x;y\" -- trailing;\nFROM data"]
+        );
+
+        assert!(buffer.push_line("SELECT 'unterminated;").is_empty());
+        assert_eq!(
+            buffer.take_remaining().as_deref(),
+            Some("SELECT 'unterminated;")
+        );
+        assert!(buffer.take_remaining().is_none());
+    }
+
+    #[test]
+    fn test_parse_param() {
+        let literal = |spec: &str| parse_param(spec).unwrap().literal;
+        assert_eq!(literal("region=EMEA"), "'EMEA'");
+        assert_eq!(literal("name=O'Brien"), "'O''Brien'");
+        assert_eq!(literal("filter=a=b"), "'a=b'");
+        assert_eq!(literal("limit:int=5"), "5");
+        assert_eq!(literal("delta:int=-3"), "(-3)");
+        assert_eq!(literal("ratio:float=0.5"), "0.5");
+        assert_eq!(literal("active:bool=True"), "TRUE");
+        assert_eq!(parse_param(" year :int=2024").unwrap().name, "year");
+
+        for spec in [
+            "region",
+            "1st=a",
+            "limit:int=5; DROP TABLE data",
+            "ratio:float=NaN",
+            "active:bool=yes",
+            "when:date=2024-01-01",
+        ] {
+            assert!(
+                matches!(parse_param(spec), Err(SqlStreamError::InvalidOption(_))),
+                "{}",
+                spec
+            );
+        }
+    }
+
+    #[test]
+    fn test_substitute_params() {
+        let params = [
+            parse_param("region=EMEA").unwrap(),
+            parse_param("min:int=-5").unwrap(),
+        ];
+        assert_eq!(
+            substitute_params(
+                "SELECT * FROM t WHERE region = ${region} AND x > $min",
+                &params
+            )
+            .unwrap(),
+            "SELECT * FROM t WHERE region = 'EMEA' AND x > (-5)"
+        );
+        assert_eq!(
+            substitute_params(
+                "SELECT x -$min, '$region', \"$min\" -- $other\nFROM t",
+                &params
+            )
+            .unwrap(),
+            "SELECT x -(-5), '$region', \"$min\" -- $other\nFROM t"
+        );
+        assert_eq!(
+            substitute_params(
+                "SELECT /* $other\n ${other} */ $min /*/ $other */, 4 / 2 * $min",
+                &params
+            )
+            .unwrap(),
+            "SELECT /* $other\n ${other} */ (-5) /*/ $other */, 4 / 2 * (-5)"
+        );
+        assert_eq!(
+            substitute_params("SELECT $1, price$", &params).unwrap(),
+            "SELECT $1, price$"
+        );
+
+        for sql in [
+            "SELECT ${missing}",
+            "SELECT $missing",
+            "SELECT ${region",
+            "SELECT ${}",
+        ] {
+            assert!(
+                matches!(
+                    substitute_params(sql, &params),
+                    Err(SqlStreamError::InvalidOption(_))
+                ),
+                "{}",
+                sql
+            );
+        }
+    }
+}
@@ -0,0 +1,226 @@
+//! SQLite output sink
+//!
+//! Writes query results into a new table of a SQLite database file. The table
+//! is created from the result's Arrow schema with the same column types as
+//! `schema --ddl sqlite`, and every record batch is inserted in its own
+//! transaction as it streams out of DataFusion.
+//!
+//! Integers and booleans are stored as INTEGER, floats as REAL, strings as TEXT
+//! and binary as BLOB. Other types, such as dates, timestamps and decimals, are
+//! stored as their text form (ISO 8601 for dates and timestamps), which SQLite's
+//! date functions and NUMERIC affinity understand.
+
+use crate::ddl::{self, SqlDialect};
+use crate::error::{Result, SqlStreamError};
+use datafusion::arrow::array::{Array, ArrayRef, AsArray};
+use datafusion::arrow::datatypes::{
+    DataType, Float32Type, Float64Type, Int16Type, Int32Type, Int64Type, Int8Type, Schema,
+    UInt16Type, UInt32Type, UInt64Type, UInt8Type,
+};
+use datafusion::arrow::util::display::{ArrayFormatter, FormatOptions};
+use datafusion::execution::SendableRecordBatchStream;
+use futures::StreamExt;
+use rusqlite::types::Value;
+use rusqlite::{params_from_iter, Connection};
+use std::path::Path;
+use tracing::{debug, info};
+
+/// Write every row of a record batch stream into a new SQLite table
+///
+/// The database file is created if it does not exist. Returns the number of
+/// rows inserted.
+///
+/// # Errors
+///
+/// Returns [`SqlStreamError::Export`] if the database cannot be opened, the
+/// table already exists, or a row cannot be inserted
+pub async fn write_stream(
+    mut stream: SendableRecordBatchStream,
+    path: &Path,
+    table_name: &str,
+) -> Result<usize> {
+    let mut connection = Connection::open(path).map_err(export_error)?;
+    let schema = stream.schema();
+    connection
+        .execute_batch(&ddl::create_table(&schema, table_name, SqlDialect::Sqlite))
+        .map_err(export_error)?;
+
+    info!(
+        "Writing results to table '{}' of {}",
+        table_name,
+        path.display()
+    );
+
+    let insert = insert_statement(&schema, table_name);
+    let options = FormatOptions::default();
+    let mut rows = 0;
+    while let Some(batch) = stream.next().await {
+        let batch = batch?;
+        let formatters = batch
+            .columns()
+            .iter()
+            .map(|column| ArrayFormatter::try_new(column.as_ref(), &options))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        let transaction = connection.transaction().map_err(export_error)?;
+        {
+            let mut statement = transaction.prepare_cached(&insert).map_err(export_error)?;
+            for row in 0..batch.num_rows() {
+                let values = batch
+                    .columns()
+                    .iter()
+                    .zip(&formatters)
+                    .map(|(column, formatter)| sqlite_value(column, formatter, row))
+                    .collect::<Result<Vec<_>>>()?;
+                statement
+                    .execute(params_from_iter(values))
+                    .map_err(export_error)?;
+            }
+        }
+        transaction.commit().map_err(export_error)?;
+
+        rows += batch.num_rows();
+        debug!("Inserted {} rows so far", rows);
+    }
+
+    info!("Wrote {} rows to table '{}'", rows, table_name);
+    Ok(rows)
+}
+
+/// Build an `INSERT` statement with one positional parameter per column
+fn insert_statement(schema: &Schema, table_name: &str) -> String {
+    let columns = schema
+        .fields()
+        .iter()
+        .map(|field| SqlDialect::Sqlite.quote(field.name()))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let params = vec!["?"; schema.fields().len()].join(", ");
+
+    format!(
+        "INSERT INTO {} ({}) VALUES ({})",
+        SqlDialect::Sqlite.quote(table_name),
+        columns,
+        params
+    )
+}
+
+/// Convert one cell of an Arrow column to a SQLite value
+fn sqlite_value(column: &ArrayRef, formatter: &ArrayFormatter, row: usize) -> Result<Value> {
+    if column.is_null(row) {
+        return Ok(Value::Null);
+    }
+
+    let value = match column.data_type() {
+        DataType::Boolean => Value::Integer(column.as_boolean().value(row).into()),
+        DataType::Int8 => Value::Integer(column.as_primitive::<Int8Type>().value(row).into()),
+        DataType::Int16 => Value::Integer(column.as_primitive::<Int16Type>().value(row).into()),
+        DataType::Int32 => Value::Integer(column.as_primitive::<Int32Type>().value(row).into()),
+        DataType::Int64 => Value::Integer(column.as_primitive::<Int64Type>().value(row)),
+        DataType::UInt8 => Value::Integer(column.as_primitive::<UInt8Type>().value(row).into()),
+        DataType::UInt16 => Value::Integer(column.as_primitive::<UInt16Type>().value(row).into()),
+        DataType::UInt32 => Value::Integer(column.as_primitive::<UInt32Type>().value(row).into()),
+        DataType::UInt64 => {
+            let value = column.as_primitive::<UInt64Type>().value(row);
+            let value = i64::try_from(value).map_err(|_| {
+                SqlStreamError::Export(format!("{} is too large for a SQLite INTEGER", value))
+            })?;
+            Value::Integer(value)
+        }
+        DataType::Float32 => Value::Real(column.as_primitive::<Float32Type>().value(row).into()),
+        DataType::Float64 => Value::Real(column.as_primitive::<Float64Type>().value(row)),
+        DataType::Utf8 => Value::Text(column.as_string::<i32>().value(row).to_string()),
+        DataType::LargeUtf8 => Value::Text(column.as_string::<i64>().value(row).to_string()),
+        DataType::Utf8View => Value::Text(column.as_string_view().value(row).to_string()),
+        DataType::Binary => Value::Blob(column.as_binary::<i32>().value(row).to_vec()),
+        DataType::LargeBinary => Value::Blob(column.as_binary::<i64>().value(row).to_vec()),
+        DataType::FixedSizeBinary(_) => {
+            Value::Blob(column.as_fixed_size_binary().value(row).to_vec())
+        }
+        _ => Value::Text(formatter.value(row).to_string()),
+    };
+    Ok(value)
+}
+
+fn export_error(err: rusqlite::Error) -> SqlStreamError {
+    SqlStreamError::Export(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::array::{
+        BooleanArray, Date32Array, Float64Array, Int64Array, StringArray,
+    };
+    use datafusion::arrow::datatypes::Field;
+    use datafusion::arrow::record_batch::RecordBatch;
+    use datafusion::physical_plan::memory::MemoryStream;
+    use std::sync::Arc;
+
+    fn stream() -> SendableRecordBatchStream {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("name", DataType::Utf8, true),
+            Field::new("score", DataType::Float64, true),
+            Field::new("active", DataType::Boolean, true),
+            Field::new("joined", DataType::Date32, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int64Array::from(vec![1, 2])),
+                Arc::new(StringArray::from(vec![Some("alpha"), None])),
+                Arc::new(Float64Array::from(vec![Some(1.5), None])),
+                Arc::new(BooleanArray::from(vec![Some(true), Some(false)])),
+                Arc::new(Date32Array::from(vec![Some(19723), None])),
+            ],
+        )
+        .unwrap();
+        Box::pin(MemoryStream::try_new(vec![batch], schema, None).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_write_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("results.db");
+
+        let rows = write_stream(stream(), &path, "results").await.unwrap();
+        assert_eq!(rows, 2);
+
+        let connection = Connection::open(&path).unwrap();
+        let first: (i64, String, f64, i64, String) = connection
+            .query_row(
+                "SELECT id, name, score, active, joined FROM results WHERE id = 1",
+                [],
+                |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                    ))
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            first,
+            (1, "alpha".to_string(), 1.5, 1, "2024-01-01".to_string())
+        );
+
+        let nulls: i64 = connection
+            .query_row(
+                "SELECT COUNT(*) FROM results WHERE name IS NULL AND score IS NULL",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(nulls, 1);
+
+        // The table is never silently replaced
+        assert!(matches!(
+            write_stream(stream(), &path, "results").await,
+            Err(SqlStreamError::Export(_))
+        ));
+    }
+}
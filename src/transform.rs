@@ -0,0 +1,342 @@
+//! DataFrame transformations behind CLI shortcuts
+//!
+//! Each helper wraps the user's query result in an extra plan step, so the
+//! shortcut composes with any query rather than only with whole tables.
+
+use crate::analysis::require_column;
+use crate::error::{Result, SqlStreamError};
+use crate::sql::{self, quote_ident, quote_literal};
+use clap::ValueEnum;
+use datafusion::arrow::datatypes::{DataType, Schema};
+use datafusion::common::UnnestOptions;
+use datafusion::prelude::*;
+
+/// Expand an array column into one row per element
+///
+/// Every other column is repeated for each element. Rows whose array is empty
+/// or NULL produce no output rows, matching PostgreSQL's `UNNEST`.
+///
+/// # Errors
+///
+/// Returns an error if the column does not exist or is not an array
+pub fn unnest(dataframe: DataFrame, column: &str) -> Result<DataFrame> {
+    let field = require_column(dataframe.schema().as_arrow(), column)?;
+    if !matches!(
+        field.data_type(),
+        DataType::List(_) | DataType::LargeList(_) | DataType::FixedSizeList(_, _)
+    ) {
+        return Err(SqlStreamError::InvalidOption(format!(
+            "Cannot unnest column '{}' of non-array type {}",
+            column,
+            field.data_type()
+        )));
+    }
+
+    let options = UnnestOptions::new().with_preserve_nulls(false);
+    Ok(dataframe.unnest_columns_with_options(&[column], options)?)
+}
+
+/// Aggregate functions accepted in a rolling window spec
+const ROLLING_AGGREGATES: [&str; 6] = ["avg", "sum", "min", "max", "count", "stddev"];
+
+/// A rolling window aggregation parsed from `column:size:aggregate`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RollingSpec {
+    /// Column to aggregate
+    pub column: String,
+    /// Number of rows in the window, including the current row
+    pub size: usize,
+    /// Aggregate function name, lowercased
+    pub aggregate: String,
+}
+
+impl RollingSpec {
+    /// Parse a `column:size:aggregate` spec, e.g. `price:7:avg`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the spec is malformed, the size is zero, or the
+    /// aggregate is not one of avg, sum, min, max, count, stddev
+    pub fn parse(spec: &str) -> Result<Self> {
+        let invalid = |reason: &str| {
+            SqlStreamError::InvalidOption(format!("Invalid rolling spec '{}': {}", spec, reason))
+        };
+
+        // Split from the right so column names may contain ':'
+        let mut parts = spec.rsplitn(3, ':');
+        let aggregate = parts.next().unwrap_or_default().to_lowercase();
+        let size = parts
+            .next()
+            .ok_or_else(|| invalid("expected column:size:aggregate"))?;
+        let column = parts
+            .next()
+            .filter(|c| !c.is_empty())
+            .ok_or_else(|| invalid("expected column:size:aggregate"))?;
+
+        let size: usize = size
+            .parse()
+            .map_err(|_| invalid("window size must be a positive integer"))?;
+        if size == 0 {
+            return Err(invalid("window size must be a positive integer"));
+        }
+        if !ROLLING_AGGREGATES.contains(&aggregate.as_str()) {
+            return Err(invalid(&format!(
+                "aggregate must be one of {}",
+                ROLLING_AGGREGATES.join(", ")
+            )));
+        }
+
+        Ok(Self {
+            column: column.to_string(),
+            size,
+            aggregate,
+        })
+    }
+
+    /// Name of the output column, e.g. `price_avg_7`
+    pub fn output_name(&self) -> String {
+        format!("{}_{}_{}", self.column, self.aggregate, self.size)
+    }
+}
+
+/// Wrap a query so its result gains a rolling window aggregate column
+///
+/// The window covers the current row and the `size - 1` rows before it when
+/// ordered by `order_by` (`ROWS BETWEEN size-1 PRECEDING AND CURRENT ROW`).
+/// The first rows therefore aggregate over fewer than `size` values.
+///
+/// # Errors
+///
+/// Returns an error if the aggregated or ordering column is missing from `schema`
+pub fn rolling_query(
+    query: &str,
+    schema: &Schema,
+    spec: &RollingSpec,
+    order_by: &str,
+) -> Result<String> {
+    require_column(schema, &spec.column)?;
+    require_column(schema, order_by)?;
+
+    let query = query.trim().trim_end_matches(';');
+    Ok(format!(
+        "SELECT *, {}({}) OVER (ORDER BY {} ROWS BETWEEN {} PRECEDING AND CURRENT ROW) AS {} \
+         FROM ({}) AS rolling_source",
+        spec.aggregate,
+        quote_ident(&spec.column),
+        quote_ident(order_by),
+        spec.size - 1,
+        quote_ident(&spec.output_name()),
+        query
+    ))
+}
+
+/// Wrap a query so its result keeps only `columns`, in that order
+///
+/// # Errors
+///
+/// Returns [`SqlStreamError::ColumnNotFound`], listing the query's columns, if
+/// one of `columns` is missing from `schema`
+pub fn select_columns(query: &str, schema: &Schema, columns: &[String]) -> Result<String> {
+    let columns = columns
+        .iter()
+        .map(|column| require_column(schema, column.trim()).map(|field| quote_ident(field.name())))
+        .collect::<Result<Vec<_>>>()?;
+
+    let query = query.trim().trim_end_matches(';');
+    Ok(format!(
+        "SELECT {} FROM ({}) AS selected_columns",
+        columns.join(", "),
+        query
+    ))
+}
+
+/// Parse a `--rename` value: a column name and its new name, as `old=new`
+///
+/// The old name ends at the last `=`, so it may contain one. The new name must
+/// be usable in SQL without quotes: ASCII letters, digits and underscores, not
+/// starting with a digit and not a reserved word.
+///
+/// # Errors
+///
+/// Returns [`SqlStreamError::InvalidOption`] if either name is missing or the
+/// new name is not a plain identifier
+pub fn parse_rename(spec: &str) -> Result<(String, String)> {
+    let invalid =
+        |reason: String| SqlStreamError::InvalidOption(format!("Invalid --rename: {}", reason));
+    let (old, new) = spec
+        .rsplit_once('=')
+        .ok_or_else(|| invalid(format!("expected OLD=NEW, got '{}'", spec.trim())))?;
+    let (old, new) = (old.trim(), new.trim());
+    if old.is_empty() {
+        return Err(invalid(format!("missing column name in '{}'", spec.trim())));
+    }
+    if !sql::is_plain_ident(new) || sql::is_reserved_word(new) {
+        return Err(invalid(format!(
+            "'{}' is not a valid column name; try '{}'",
+            new,
+            sql::suggest_table_name(new)
+        )));
+    }
+    Ok((old.to_string(), new.to_string()))
+}
+
+/// Decimal and digit-grouping conventions for `--parse-numeric`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum NumericLocale {
+    /// `.` decimal point, `,` grouping: `$1,234.56`
+    #[default]
+    Us,
+    /// `,` decimal point, `.` grouping: `1.234,56 €`
+    Eu,
+}
+
+/// SQL expression parsing a formatted text column as a DOUBLE
+///
+/// Every character other than digits, the minus sign and the locale's decimal
+/// separator is stripped, which removes currency symbols, grouping separators
+/// and whitespace. Values that still do not parse become NULL.
+pub fn numeric_expr(column: &str, locale: NumericLocale) -> String {
+    let text = format!("CAST({} AS VARCHAR)", quote_ident(column));
+    let digits = match locale {
+        NumericLocale::Us => format!("regexp_replace({}, '[^0-9.-]', '', 'g')", text),
+        NumericLocale::Eu => format!(
+            "replace(regexp_replace({}, '[^0-9,-]', '', 'g'), ',', '.')",
+            text
+        ),
+    };
+    format!("TRY_CAST({} AS DOUBLE)", digits)
+}
+
+/// SQL expression parsing a text column as a DATE in a chrono `format`, such as `%m/%d/%Y`
+pub fn date_expr(column: &str, format: &str) -> String {
+    format!(
+        "to_date({}, {})",
+        quote_ident(column),
+        quote_literal(format)
+    )
+}
+
+/// SQL expression parsing a text column as a TIMESTAMP in a chrono `format`,
+/// such as `%d/%m/%Y %H:%M`
+pub fn timestamp_expr(column: &str, format: &str) -> String {
+    format!(
+        "to_timestamp({}, {})",
+        quote_ident(column),
+        quote_literal(format)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::datatypes::Field;
+
+    #[test]
+    fn test_parse_rolling_spec() {
+        let spec = RollingSpec::parse("price:7:AVG").unwrap();
+        assert_eq!(spec.column, "price");
+        assert_eq!(spec.size, 7);
+        assert_eq!(spec.aggregate, "avg");
+        assert_eq!(spec.output_name(), "price_avg_7");
+
+        assert!(RollingSpec::parse("price:0:avg").is_err());
+        assert!(RollingSpec::parse("price:7:median").is_err());
+        assert!(RollingSpec::parse("7:avg").is_err());
+    }
+
+    #[test]
+    fn test_rolling_query() {
+        let schema = Schema::new(vec![
+            Field::new("ts", DataType::Int64, true),
+            Field::new("price", DataType::Float64, true),
+        ]);
+        let spec = RollingSpec::parse("price:3:sum").unwrap();
+
+        let sql = rolling_query("SELECT * FROM data;", &schema, &spec, "ts").unwrap();
+        assert_eq!(
+            sql,
+            "SELECT *, sum(\"price\") OVER (ORDER BY \"ts\" ROWS BETWEEN 2 PRECEDING AND CURRENT ROW) \
+             AS \"price_sum_3\" FROM (SELECT * FROM data) AS rolling_source"
+        );
+
+        let result = rolling_query("SELECT * FROM data", &schema, &spec, "date");
+        assert!(matches!(result, Err(SqlStreamError::ColumnNotFound { .. })));
+    }
+
+    #[test]
+    fn test_select_columns() {
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::Int64, true),
+            Field::new("Full Name", DataType::Utf8, true),
+        ]);
+        let columns = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+
+        let sql = select_columns(
+            "SELECT * FROM data;",
+            &schema,
+            &columns(&["Full Name", " id"]),
+        );
+        assert_eq!(
+            sql.unwrap(),
+            "SELECT \"Full Name\", \"id\" FROM (SELECT * FROM data) AS selected_columns"
+        );
+
+        match select_columns("SELECT * FROM data", &schema, &columns(&["id", "age"])) {
+            Err(SqlStreamError::ColumnNotFound { column, available }) => {
+                assert_eq!(column, "age");
+                assert_eq!(available, ["id", "Full Name"]);
+            }
+            other => panic!("expected ColumnNotFound, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_rename() {
+        assert_eq!(
+            parse_rename("Customer Name = customer_name").unwrap(),
+            ("Customer Name".to_string(), "customer_name".to_string())
+        );
+        assert_eq!(
+            parse_rename("a=b=c").unwrap(),
+            ("a=b".to_string(), "c".to_string())
+        );
+        for spec in [
+            "name",
+            "=name",
+            "name=",
+            "name=Full Name",
+            "name=1st",
+            "name=order",
+        ] {
+            assert!(
+                matches!(parse_rename(spec), Err(SqlStreamError::InvalidOption(_))),
+                "{}",
+                spec
+            );
+        }
+    }
+
+    #[test]
+    fn test_numeric_expr() {
+        assert_eq!(
+            numeric_expr("price", NumericLocale::Us),
+            "TRY_CAST(regexp_replace(CAST(\"price\" AS VARCHAR), '[^0-9.-]', '', 'g') AS DOUBLE)"
+        );
+        assert_eq!(
+            numeric_expr("price", NumericLocale::Eu),
+            "TRY_CAST(replace(regexp_replace(CAST(\"price\" AS VARCHAR), '[^0-9,-]', '', 'g'), ',', '.') AS DOUBLE)"
+        );
+    }
+
+    #[test]
+    fn test_temporal_exprs() {
+        assert_eq!(
+            date_expr("signup", "%m/%d/%Y"),
+            "to_date(\"signup\", '%m/%d/%Y')"
+        );
+        assert_eq!(
+            timestamp_expr("seen", "%Y/%m/%d %H:%M"),
+            "to_timestamp(\"seen\", '%Y/%m/%d %H:%M')"
+        );
+    }
+}
@@ -3,7 +3,11 @@
 //! These tests verify the end-to-end functionality of the query engine
 //! with real CSV and JSON files.
 
-use sql_stream::{QueryEngine, SqlStreamError};
+use sql_stream::transform::NumericLocale;
+use sql_stream::{
+    analysis, transform, CacheStatus, CancellationToken, EngineConfig, OutputFormat, PrintFormat,
+    QueryEngine, ReadOptions, SqlStreamError, WriteOptions,
+};
 use std::path::PathBuf;
 
 /// Helper function to get the path to test fixtures
@@ -158,6 +162,14 @@ async fn test_unsupported_format() {
         .await;
 
     assert!(matches!(result, Err(SqlStreamError::UnsupportedFormat(_))));
+
+    // The files of a glob are also checked for their format before being empty
+    File::create(dir.path().join("other.txt")).unwrap();
+    let pattern = dir.path().join("*.txt");
+    let result = engine
+        .register_file(pattern.to_str().unwrap(), "test")
+        .await;
+    assert!(matches!(result, Err(SqlStreamError::UnsupportedFormat(_))));
 }
 
 #[tokio::test]
@@ -201,6 +213,48 @@ async fn test_multiple_tables() {
     assert_eq!(batches.len(), 1);
 }
 
+#[tokio::test]
+async fn test_register_all() {
+    let mut engine = QueryEngine::new().unwrap();
+    let paths = [
+        fixture_path("sample.csv"),
+        fixture_path("sample.json"),
+        fixture_path("sample.csv.gz"),
+        fixture_path("parts"),
+        fixture_path("tabs.tsv"),
+    ];
+    let names = ["csv_data", "json_data", "gz_data", "parts", "tabs"];
+    let tables: Vec<(&str, &str)> = paths
+        .iter()
+        .map(|path| path.to_str().unwrap())
+        .zip(names)
+        .collect();
+
+    engine
+        .register_all(&tables, &ReadOptions::default())
+        .await
+        .unwrap();
+
+    let mut expected: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+    expected.sort();
+    assert_eq!(engine.list_tables(), expected);
+
+    let batches = engine
+        .collect_query("SELECT COUNT(*) FROM csv_data JOIN json_data USING (id)")
+        .await
+        .unwrap();
+    assert_eq!(batches[0].num_rows(), 1);
+
+    // Clashing names are rejected before anything is registered
+    let mut engine = QueryEngine::new().unwrap();
+    let clashing = [(tables[0].0, "data"), (tables[1].0, "DATA")];
+    assert!(engine
+        .register_all(&clashing, &ReadOptions::default())
+        .await
+        .is_err());
+    assert!(engine.list_tables().is_empty());
+}
+
 #[tokio::test]
 async fn test_print_results() {
     let mut engine = QueryEngine::new().unwrap();
@@ -217,6 +271,3122 @@ async fn test_print_results() {
         .unwrap();
 
     // This should not panic
-    let result = engine.print_results(df).await;
+    let result = engine.print_results(df, PrintFormat::Table).await;
     assert!(result.is_ok());
 }
+
+#[tokio::test]
+async fn test_scanned_files() {
+    let mut engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("sample.csv");
+
+    engine
+        .register_file(csv_path.to_str().unwrap(), "employees")
+        .await
+        .unwrap();
+
+    let files = engine
+        .scanned_files("SELECT name FROM employees WHERE age > 30")
+        .await
+        .unwrap();
+
+    assert_eq!(files.len(), 1);
+    assert!(files[0].ends_with("sample.csv"));
+}
+
+#[tokio::test]
+async fn test_convert_csv_to_parquet() {
+    use tempfile::tempdir;
+
+    let mut engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("sample.csv");
+    engine
+        .register_file(csv_path.to_str().unwrap(), "employees")
+        .await
+        .unwrap();
+
+    let dir = tempdir().unwrap();
+    let output_path = dir.path().join("employees.parquet");
+    let df = engine.table("employees").await.unwrap();
+    let rows = engine.write_results(df, &output_path).await.unwrap();
+    assert_eq!(rows, 10);
+
+    // Read the converted file back and check types survived the round trip
+    let ctx = datafusion::prelude::SessionContext::new();
+    let df = ctx
+        .read_parquet(
+            output_path.to_str().unwrap(),
+            datafusion::prelude::ParquetReadOptions::default(),
+        )
+        .await
+        .unwrap();
+    let schema = df.schema().clone();
+    assert_eq!(
+        schema.field_with_name(None, "age").unwrap().data_type(),
+        &datafusion::arrow::datatypes::DataType::Int64
+    );
+    assert_eq!(df.count().await.unwrap(), 10);
+}
+
+#[tokio::test]
+async fn test_compressed_output() {
+    use datafusion::parquet::basic::Compression;
+    use datafusion::parquet::file::reader::{FileReader, SerializedFileReader};
+    use sql_stream::OutputCompression;
+
+    let mut engine = QueryEngine::new().unwrap();
+    engine
+        .register_file(fixture_path("sample.csv").to_str().unwrap(), "employees")
+        .await
+        .unwrap();
+    let dir = tempfile::tempdir().unwrap();
+
+    // A gzipped CSV written by sql-stream reads back through the engine
+    let gz_path = dir.path().join("employees.csv.gz");
+    let df = engine.table("employees").await.unwrap();
+    let options = WriteOptions {
+        compression: Some(OutputCompression::Gzip),
+        ..Default::default()
+    };
+    let rows = engine
+        .write_results_with(df, &gz_path, &options)
+        .await
+        .unwrap();
+    assert_eq!(rows, 10);
+    assert_eq!(&std::fs::read(&gz_path).unwrap()[..2], [0x1f, 0x8b]);
+    engine
+        .register_file(gz_path.to_str().unwrap(), "round_trip")
+        .await
+        .unwrap();
+    let batches = engine
+        .collect_query("SELECT * FROM round_trip WHERE age > 30")
+        .await
+        .unwrap();
+    assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 5);
+
+    // The extension alone picks zstd for a converted file
+    let zst_path = dir.path().join("employees.ndjson.zst");
+    let df = engine.table("employees").await.unwrap();
+    engine
+        .convert_results(df, &zst_path, &WriteOptions::default())
+        .await
+        .unwrap();
+    let text = zstd::decode_all(std::fs::File::open(&zst_path).unwrap()).unwrap();
+    assert_eq!(String::from_utf8(text).unwrap().lines().count(), 10);
+
+    let parquet_path = dir.path().join("employees.parquet");
+    let df = engine.table("employees").await.unwrap();
+    let options = WriteOptions {
+        compression: Some(OutputCompression::Snappy),
+        ..Default::default()
+    };
+    engine
+        .write_results_with(df, &parquet_path, &options)
+        .await
+        .unwrap();
+    let reader = SerializedFileReader::new(std::fs::File::open(&parquet_path).unwrap()).unwrap();
+    let column = reader.metadata().row_group(0).column(0).clone();
+    assert_eq!(column.compression(), Compression::SNAPPY);
+
+    let df = engine.table("employees").await.unwrap();
+    let result = engine
+        .write_results_with(df, &dir.path().join("snappy.csv"), &options)
+        .await;
+    assert!(matches!(result, Err(SqlStreamError::InvalidOption(_))));
+}
+
+#[tokio::test]
+async fn test_convert_results() {
+    use datafusion::parquet::basic::Compression;
+    use datafusion::parquet::file::reader::{FileReader, SerializedFileReader};
+
+    let mut engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("sample.csv");
+    engine
+        .register_file(csv_path.to_str().unwrap(), "employees")
+        .await
+        .unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    let parquet_path = dir.path().join("seniors.parquet");
+    let df = engine
+        .execute_query("SELECT id, name, age FROM employees WHERE age > 30")
+        .await
+        .unwrap();
+    let rows = engine
+        .convert_results(df, &parquet_path, &WriteOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(rows, 5);
+
+    let reader = SerializedFileReader::new(std::fs::File::open(&parquet_path).unwrap()).unwrap();
+    let column = reader.metadata().row_group(0).column(0).clone();
+    assert!(matches!(column.compression(), Compression::ZSTD(_)));
+
+    // Read the converted file back through the engine
+    engine
+        .register_file(parquet_path.to_str().unwrap(), "seniors")
+        .await
+        .unwrap();
+    let schema = engine.table_schema("seniors").await.unwrap();
+    assert_eq!(
+        schema.field_with_name("age").unwrap().data_type(),
+        &datafusion::arrow::datatypes::DataType::Int64
+    );
+    let batches = engine
+        .collect_query("SELECT name FROM seniors WHERE age > 40 ORDER BY name")
+        .await
+        .unwrap();
+    assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 2);
+
+    let csv_out = dir.path().join("seniors.csv");
+    let df = engine.table("seniors").await.unwrap();
+    engine
+        .convert_results(df, &csv_out, &WriteOptions::default())
+        .await
+        .unwrap();
+    let text = std::fs::read_to_string(&csv_out).unwrap();
+    assert!(text.starts_with("id,name,age\n"));
+    assert_eq!(text.lines().count(), 6);
+
+    // Existing files are kept unless overwriting, and JSON arrays are not written
+    let df = engine.table("seniors").await.unwrap();
+    let result = engine
+        .convert_results(df, &csv_out, &WriteOptions::default())
+        .await;
+    assert!(matches!(result, Err(SqlStreamError::Output(_))));
+    let df = engine.table("seniors").await.unwrap();
+    let result = engine
+        .convert_results(
+            df,
+            &dir.path().join("seniors.json"),
+            &WriteOptions::default(),
+        )
+        .await;
+    assert!(matches!(result, Err(SqlStreamError::UnsupportedFormat(_))));
+}
+
+#[cfg(feature = "avro")]
+#[tokio::test]
+async fn test_avro_file() {
+    use datafusion::arrow::array::{Int64Array, StringArray};
+    use datafusion::arrow::datatypes::DataType;
+
+    let mut engine = QueryEngine::new().unwrap();
+    engine
+        .register_file(fixture_path("events.avro").to_str().unwrap(), "events")
+        .await
+        .unwrap();
+
+    // Nested records become structs and ["null", T] unions nullable columns
+    let schema = engine.table_schema("events").await.unwrap();
+    let id = schema.field_with_name("id").unwrap();
+    assert_eq!(id.data_type(), &DataType::Int64);
+    assert!(!id.is_nullable());
+    assert!(schema.field_with_name("amount").unwrap().is_nullable());
+    let DataType::Struct(fields) = schema.field_with_name("customer").unwrap().data_type() else {
+        panic!("customer should be a struct");
+    };
+    let names: Vec<_> = fields.iter().map(|f| f.name().as_str()).collect();
+    assert_eq!(names, ["name", "country"]);
+    assert!(fields[1].is_nullable());
+
+    let batches = engine
+        .collect_query(
+            "SELECT id, customer['name'] AS name FROM events \
+             WHERE customer['country'] IS NULL ORDER BY id",
+        )
+        .await
+        .unwrap();
+    let batch = &batches[0];
+    let ids = batch
+        .column(0)
+        .as_any()
+        .downcast_ref::<Int64Array>()
+        .unwrap();
+    let names = batch
+        .column(1)
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .unwrap();
+    assert_eq!(ids.values(), &[2, 5]);
+    assert_eq!(names.value(0), "Bob");
+}
+
+#[cfg(feature = "avro")]
+#[tokio::test]
+async fn test_avro_group_by() {
+    use datafusion::arrow::array::{Array, Float64Array, Int64Array, StringArray};
+
+    let mut engine = QueryEngine::new().unwrap();
+    engine
+        .register_file(fixture_path("events.avro").to_str().unwrap(), "events")
+        .await
+        .unwrap();
+
+    let batches = engine
+        .collect_query(
+            "SELECT kind, COUNT(*) AS n, SUM(amount) AS total FROM events \
+             GROUP BY kind ORDER BY kind",
+        )
+        .await
+        .unwrap();
+    let batch = &batches[0];
+    let kinds = batch
+        .column(0)
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .unwrap();
+    let counts = batch
+        .column(1)
+        .as_any()
+        .downcast_ref::<Int64Array>()
+        .unwrap();
+    let totals = batch
+        .column(2)
+        .as_any()
+        .downcast_ref::<Float64Array>()
+        .unwrap();
+    assert_eq!(
+        kinds.iter().flatten().collect::<Vec<_>>(),
+        ["purchase", "refund", "view"]
+    );
+    assert_eq!(counts.values(), &[3, 1, 1]);
+    assert_eq!(totals.value(0), 505.75);
+    assert_eq!(totals.value(1), -20.0);
+    // The only view has no amount
+    assert!(totals.is_null(2));
+}
+
+#[cfg(feature = "avro")]
+#[tokio::test]
+async fn test_avro_scanned_files() {
+    let mut engine = QueryEngine::new().unwrap();
+    engine
+        .register_file(fixture_path("events.avro").to_str().unwrap(), "events")
+        .await
+        .unwrap();
+
+    let files = engine
+        .scanned_files("SELECT kind FROM events WHERE id > 2")
+        .await
+        .unwrap();
+
+    assert_eq!(files.len(), 1);
+    assert!(files[0].ends_with("events.avro"));
+}
+
+#[cfg(feature = "excel")]
+#[tokio::test]
+async fn test_excel_file() {
+    use datafusion::arrow::array::Int64Array;
+    use datafusion::arrow::datatypes::DataType;
+
+    let xlsx_path = fixture_path("sample.xlsx");
+    let mut engine = QueryEngine::new().unwrap();
+    engine
+        .register_file(xlsx_path.to_str().unwrap(), "employees")
+        .await
+        .unwrap();
+
+    let schema = engine.table_schema("employees").await.unwrap();
+    let types: Vec<_> = schema
+        .fields()
+        .iter()
+        .map(|f| (f.name().as_str(), f.data_type().clone()))
+        .collect();
+    assert_eq!(
+        types,
+        [
+            ("id", DataType::Int64),
+            ("name", DataType::Utf8),
+            ("age", DataType::Int64),
+            ("city", DataType::Utf8),
+            ("salary", DataType::Float64),
+            ("active", DataType::Boolean),
+        ]
+    );
+
+    let batches = engine
+        .collect_query("SELECT COUNT(*) FROM employees WHERE active AND age > 28")
+        .await
+        .unwrap();
+    let count = batches[0]
+        .column(0)
+        .as_any()
+        .downcast_ref::<Int64Array>()
+        .unwrap()
+        .value(0);
+    assert_eq!(count, 2);
+
+    // Another worksheet is chosen by name
+    let options = ReadOptions {
+        sheet: Some("Regions".to_string()),
+        ..Default::default()
+    };
+    engine
+        .register_file_with(xlsx_path.to_str().unwrap(), "regions", &options)
+        .await
+        .unwrap();
+    let df = engine.execute_query("SELECT * FROM regions").await.unwrap();
+    assert_eq!(df.count().await.unwrap(), 2);
+
+    let options = ReadOptions {
+        sheet: Some("Missing".to_string()),
+        ..Default::default()
+    };
+    let result = engine
+        .register_file_with(xlsx_path.to_str().unwrap(), "missing", &options)
+        .await;
+    assert!(matches!(result, Err(SqlStreamError::InvalidOption(_))));
+
+    // Each worksheet becomes a table named after it, avoiding taken names
+    let names = engine
+        .register_each(xlsx_path.to_str().unwrap(), &ReadOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(names, ["employees_2", "regions_2"]);
+}
+
+#[cfg(feature = "orc")]
+#[tokio::test]
+async fn test_orc_file() {
+    use datafusion::arrow::array::{Float64Array, Int64Array, StringArray};
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use orc_rust::ArrowWriterBuilder;
+    use std::sync::Arc;
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Int64, false),
+        Field::new("name", DataType::Utf8, true),
+        Field::new("score", DataType::Float64, true),
+    ]));
+    let batch = sql_stream::RecordBatch::try_new(
+        schema.clone(),
+        vec![
+            Arc::new(Int64Array::from(vec![1, 2, 3])),
+            Arc::new(StringArray::from(vec![Some("a"), None, Some("c")])),
+            Arc::new(Float64Array::from(vec![1.5, 2.5, 3.5])),
+        ],
+    )
+    .unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    let orc_path = dir.path().join("scores.orc");
+    let mut writer = ArrowWriterBuilder::new(std::fs::File::create(&orc_path).unwrap(), schema)
+        .try_build()
+        .unwrap();
+    writer.write(&batch).unwrap();
+    writer.close().unwrap();
+
+    let mut engine = QueryEngine::new().unwrap();
+    engine
+        .register_file(orc_path.to_str().unwrap(), "scores")
+        .await
+        .unwrap();
+
+    let table_schema = engine.table_schema("scores").await.unwrap();
+    let types: Vec<_> = table_schema
+        .fields()
+        .iter()
+        .map(|f| f.data_type().clone())
+        .collect();
+    assert_eq!(types, [DataType::Int64, DataType::Utf8, DataType::Float64]);
+
+    let batches = engine.collect_query("SELECT * FROM scores").await.unwrap();
+    assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 3);
+    let batches = engine
+        .collect_query("SELECT SUM(score) FROM scores WHERE name IS NOT NULL")
+        .await
+        .unwrap();
+    let total = batches[0]
+        .column(0)
+        .as_any()
+        .downcast_ref::<Float64Array>()
+        .unwrap()
+        .value(0);
+    assert_eq!(total, 5.0);
+}
+
+#[tokio::test]
+async fn test_csv_header_pattern() {
+    let mut engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("preamble.csv");
+    let options = ReadOptions {
+        header_pattern: Some("^id,name".to_string()),
+        ..Default::default()
+    };
+
+    engine
+        .register_file_with(csv_path.to_str().unwrap(), "employees", &options)
+        .await
+        .unwrap();
+
+    let df = engine
+        .execute_query("SELECT name FROM employees WHERE age > 26")
+        .await
+        .unwrap();
+
+    assert_eq!(df.count().await.unwrap(), 2);
+}
+
+#[tokio::test]
+async fn test_csv_header_pattern_not_found() {
+    let mut engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("sample.csv");
+    let options = ReadOptions {
+        header_pattern: Some("^no_such_column".to_string()),
+        ..Default::default()
+    };
+
+    let result = engine
+        .register_file_with(csv_path.to_str().unwrap(), "employees", &options)
+        .await;
+
+    assert!(matches!(result, Err(SqlStreamError::HeaderNotFound { .. })));
+}
+
+#[tokio::test]
+async fn test_correlation() {
+    use datafusion::arrow::array::Float64Array;
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::arrow::record_batch::RecordBatch;
+    use std::sync::Arc;
+
+    let mut engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("sample.csv");
+    engine
+        .register_file(csv_path.to_str().unwrap(), "employees")
+        .await
+        .unwrap();
+
+    let value = analysis::correlation(&engine, "employees", "age", "salary")
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(value > 0.9 && value <= 1.0);
+
+    let result = analysis::correlation(&engine, "employees", "age", "name").await;
+    assert!(matches!(result, Err(SqlStreamError::InvalidOption(_))));
+
+    // Rows where only one of the two values is NULL are left out entirely
+    let pairs = |x: Vec<Option<f64>>, y: Vec<Option<f64>>| {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("x", DataType::Float64, true),
+            Field::new("y", DataType::Float64, true),
+        ]));
+        RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(Float64Array::from(x)),
+                Arc::new(Float64Array::from(y)),
+            ],
+        )
+        .unwrap()
+    };
+    engine
+        .register_batches(
+            "partial pairs",
+            vec![pairs(
+                vec![
+                    Some(1.0),
+                    Some(2.0),
+                    Some(3.0),
+                    Some(4.0),
+                    None,
+                    Some(100.0),
+                ],
+                vec![
+                    Some(2.0),
+                    Some(1.0),
+                    Some(4.0),
+                    Some(3.0),
+                    Some(-50.0),
+                    None,
+                ],
+            )],
+        )
+        .unwrap();
+    engine
+        .register_batches(
+            "complete pairs",
+            vec![pairs(
+                vec![Some(1.0), Some(2.0), Some(3.0), Some(4.0)],
+                vec![Some(2.0), Some(1.0), Some(4.0), Some(3.0)],
+            )],
+        )
+        .unwrap();
+    let partial = analysis::correlation(&engine, "partial pairs", "x", "y")
+        .await
+        .unwrap()
+        .unwrap();
+    let complete = analysis::correlation(&engine, "complete pairs", "x", "y")
+        .await
+        .unwrap()
+        .unwrap();
+    assert!((partial - complete).abs() < 1e-12);
+    assert!((complete - 0.6).abs() < 1e-12);
+}
+
+#[tokio::test]
+async fn test_correlation_matrix() {
+    let mut engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("sample.csv");
+    engine
+        .register_file(csv_path.to_str().unwrap(), "employees")
+        .await
+        .unwrap();
+
+    let matrix = analysis::correlation_matrix(&engine, "employees")
+        .await
+        .unwrap();
+
+    // id, age and salary are numeric: a 3x3 matrix plus the name column
+    assert_eq!(matrix.num_rows(), 3);
+    assert_eq!(matrix.num_columns(), 4);
+}
+
+#[tokio::test]
+async fn test_window_functions_and_ctes() {
+    use datafusion::arrow::array::AsArray;
+    use datafusion::arrow::datatypes::Int64Type;
+
+    async fn scalar(engine: &QueryEngine, sql: &str) -> i64 {
+        let batches = engine.collect_query(sql).await.unwrap();
+        let batch = batches.iter().find(|b| b.num_rows() > 0).unwrap();
+        batch.column(0).as_primitive::<Int64Type>().value(0)
+    }
+
+    let mut engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("sample.csv");
+    engine
+        .register_file(csv_path.to_str().unwrap(), "employees")
+        .await
+        .unwrap();
+
+    // Ian McKellen (id 9) has the top salary
+    let top = "SELECT id FROM (SELECT id, ROW_NUMBER() OVER (ORDER BY salary DESC) AS rn \
+               FROM employees) AS ranked WHERE rn = 1";
+    assert_eq!(scalar(&engine, top).await, 9);
+    let per_group = "SELECT COUNT(*) FROM (SELECT RANK() OVER (PARTITION BY age < 30 \
+                     ORDER BY salary DESC) AS rank FROM employees) AS ranked WHERE rank = 1";
+    assert_eq!(scalar(&engine, per_group).await, 2);
+    let running = "SELECT SUM(salary) OVER (ORDER BY id) FROM employees ORDER BY id DESC LIMIT 1";
+    assert_eq!(scalar(&engine, running).await, 777000);
+
+    let cte = "WITH senior AS (SELECT * FROM employees WHERE age >= 35) \
+               SELECT COUNT(*) FROM senior";
+    assert_eq!(scalar(&engine, cte).await, 3);
+    let recursive = "WITH RECURSIVE counter AS (SELECT 1 AS n UNION ALL \
+                     SELECT n + 1 FROM counter WHERE n < 5) SELECT SUM(n) FROM counter";
+    assert_eq!(scalar(&engine, recursive).await, 15);
+
+    // SQL that parses but DataFusion can't plan names the feature
+    let result = engine
+        .execute_query("SELECT TOP 1 name FROM employees")
+        .await;
+    assert!(
+        matches!(&result, Err(SqlStreamError::UnsupportedFeature(feature)) if feature == "TOP"),
+        "{:?}",
+        result.map(|_| ())
+    );
+}
+
+#[tokio::test]
+async fn test_rename_columns() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("orders.csv");
+    std::fs::write(
+        &path,
+        "Order ID,Customer Name,total\n1,Alice,9.5\n2,Bob,3.0\n",
+    )
+    .unwrap();
+
+    let mut engine = QueryEngine::new().unwrap();
+    engine
+        .register_file(path.to_str().unwrap(), "orders")
+        .await
+        .unwrap();
+    let renames = [
+        ("Order ID".to_string(), "order_id".to_string()),
+        ("Customer Name".to_string(), "customer".to_string()),
+    ];
+    engine.rename_columns("orders", &renames).await.unwrap();
+
+    // Every column is kept, in place, under its new name
+    let schema = engine.table_schema("orders").await.unwrap();
+    let names: Vec<_> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+    assert_eq!(names, ["order_id", "customer", "total"]);
+    let df = engine
+        .execute_query("SELECT customer FROM orders WHERE order_id = 2")
+        .await
+        .unwrap();
+    assert_eq!(df.count().await.unwrap(), 1);
+
+    let missing = [("Order ID".to_string(), "id".to_string())];
+    let result = engine.rename_columns("orders", &missing).await;
+    assert!(matches!(result, Err(SqlStreamError::ColumnNotFound { .. })));
+
+    let clash = [("customer".to_string(), "total".to_string())];
+    let result = engine.rename_columns("orders", &clash).await;
+    assert!(matches!(result, Err(SqlStreamError::InvalidOption(_))));
+}
+
+#[tokio::test]
+async fn test_profile() {
+    use datafusion::arrow::array::{Array, AsArray};
+    use datafusion::arrow::datatypes::{Float64Type, Int64Type};
+
+    let mut engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("sample.csv");
+    engine
+        .register_file(csv_path.to_str().unwrap(), "employees")
+        .await
+        .unwrap();
+
+    let profile = analysis::profile(&engine, "employees").await.unwrap();
+
+    // One row per column of the fixture, in schema order
+    let schema = engine.table_schema("employees").await.unwrap();
+    assert_eq!(profile.num_rows(), schema.fields().len());
+    let names = profile.column(0).as_string::<i32>();
+    for (row, field) in schema.fields().iter().enumerate() {
+        assert_eq!(names.value(row), field.name());
+    }
+
+    // Numeric columns get a mean, text columns don't
+    let avg = profile.column(7).as_primitive::<Float64Type>();
+    let age = schema.index_of("age").unwrap();
+    let name = schema.index_of("name").unwrap();
+    assert!(avg.is_valid(age));
+    assert!(avg.is_null(name));
+    let nulls = profile.column(3).as_primitive::<Int64Type>();
+    assert_eq!(nulls.value(name), 0);
+}
+
+#[tokio::test]
+async fn test_unnest_array_column() {
+    let mut engine = QueryEngine::new().unwrap();
+    let json_path = fixture_path("tags.json");
+    engine
+        .register_file(json_path.to_str().unwrap(), "users")
+        .await
+        .unwrap();
+
+    let df = engine.execute_query("SELECT * FROM users").await.unwrap();
+    let df = transform::unnest(df, "tags").unwrap();
+
+    // Empty and NULL arrays are dropped, the rest expand to one row per element
+    assert_eq!(df.count().await.unwrap(), 3);
+
+    let df = engine.execute_query("SELECT * FROM users").await.unwrap();
+    let result = transform::unnest(df, "name");
+    assert!(matches!(result, Err(SqlStreamError::InvalidOption(_))));
+}
+
+#[tokio::test]
+async fn test_csv_sniff_delimiter() {
+    let mut engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("semicolon.csv");
+    let options = ReadOptions {
+        sniff_delimiter: true,
+        ..Default::default()
+    };
+
+    engine
+        .register_file_with(csv_path.to_str().unwrap(), "payments", &options)
+        .await
+        .unwrap();
+
+    let schema = engine.table_schema("payments").await.unwrap();
+    let names: Vec<_> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+    assert_eq!(names, ["id", "name", "amount"]);
+}
+
+#[tokio::test]
+async fn test_empty_result_outputs() {
+    use tempfile::tempdir;
+
+    let mut engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("sample.csv");
+    engine
+        .register_file(csv_path.to_str().unwrap(), "employees")
+        .await
+        .unwrap();
+
+    let dir = tempdir().unwrap();
+    let query = "SELECT id, name FROM employees WHERE 1 = 0";
+
+    let csv_out = dir.path().join("empty.csv");
+    let df = engine.execute_query(query).await.unwrap();
+    assert_eq!(engine.write_results(df, &csv_out).await.unwrap(), 0);
+    assert_eq!(std::fs::read_to_string(&csv_out).unwrap(), "id,name\n");
+
+    let json_out = dir.path().join("empty.json");
+    let df = engine.execute_query(query).await.unwrap();
+    assert_eq!(engine.write_results(df, &json_out).await.unwrap(), 0);
+    assert_eq!(std::fs::read_to_string(&json_out).unwrap(), "[]");
+
+    // The table printer reports "(0 rows)" instead of failing
+    let df = engine.execute_query(query).await.unwrap();
+    assert!(engine.print_results(df, PrintFormat::Table).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_dictionary_encode() {
+    use datafusion::arrow::datatypes::DataType;
+
+    let mut engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("sample.csv");
+    engine
+        .register_file(csv_path.to_str().unwrap(), "employees")
+        .await
+        .unwrap();
+
+    engine
+        .dictionary_encode("employees", &["city".to_string()])
+        .await
+        .unwrap();
+
+    let schema = engine.table_schema("employees").await.unwrap();
+    assert!(matches!(
+        schema.field_with_name("city").unwrap().data_type(),
+        DataType::Dictionary(_, _)
+    ));
+
+    let df = engine
+        .execute_query("SELECT city, COUNT(*) FROM employees GROUP BY city")
+        .await
+        .unwrap();
+    assert_eq!(df.count().await.unwrap(), 10);
+
+    let result = engine
+        .dictionary_encode("employees", &["salary".to_string()])
+        .await;
+    assert!(matches!(result, Err(SqlStreamError::InvalidOption(_))));
+}
+
+#[tokio::test]
+async fn test_cast_columns() {
+    use datafusion::arrow::array::StringArray;
+    use datafusion::arrow::datatypes::{DataType, Fields};
+
+    let casts = [
+        ("zip".to_string(), DataType::Utf8),
+        ("population".to_string(), DataType::Float64),
+    ];
+    let options = ReadOptions {
+        text_columns: vec!["zip".to_string()],
+        ..Default::default()
+    };
+    let mut engine = QueryEngine::new().unwrap();
+    engine
+        .register_file_with(fixture_path("zips.csv").to_str().unwrap(), "zips", &options)
+        .await
+        .unwrap();
+    engine.cast_columns("zips", &casts).await.unwrap();
+
+    let schema = engine.table_schema("zips").await.unwrap();
+    assert_eq!(
+        schema.field_with_name("zip").unwrap().data_type(),
+        &DataType::Utf8
+    );
+    assert_eq!(
+        schema.field_with_name("population").unwrap().data_type(),
+        &DataType::Float64
+    );
+
+    // The ZIP codes keep their leading zeros
+    let batches = engine
+        .collect_query("SELECT zip FROM zips WHERE city = 'Boston'")
+        .await
+        .unwrap();
+    let zips = batches[0]
+        .column(0)
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .unwrap();
+    assert_eq!(zips.value(0), "02134");
+
+    let result = engine
+        .cast_columns("zips", &[("nope".to_string(), DataType::Utf8)])
+        .await;
+    assert!(matches!(result, Err(SqlStreamError::ColumnNotFound { .. })));
+    let result = engine
+        .cast_columns(
+            "zips",
+            &[("zip".to_string(), DataType::Struct(Fields::empty()))],
+        )
+        .await;
+    assert!(matches!(result, Err(SqlStreamError::InvalidOption(_))));
+}
+
+#[tokio::test]
+async fn test_value_frequencies() {
+    let mut engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("sample.csv");
+    engine
+        .register_file(csv_path.to_str().unwrap(), "employees")
+        .await
+        .unwrap();
+
+    let frequencies = analysis::value_frequencies(&engine, "employees", "city", 3)
+        .await
+        .unwrap();
+    assert_eq!(frequencies.len(), 3);
+    assert!(frequencies.iter().all(|(_, count)| *count == 1));
+
+    let result = analysis::value_frequencies(&engine, "employees", "country", 3).await;
+    assert!(matches!(result, Err(SqlStreamError::ColumnNotFound { .. })));
+}
+
+#[tokio::test]
+async fn test_json_bom_and_blank_lines() {
+    let mut engine = QueryEngine::new().unwrap();
+    let json_path = fixture_path("bom_blank_lines.json");
+
+    engine
+        .register_file(json_path.to_str().unwrap(), "employees")
+        .await
+        .unwrap();
+
+    let df = engine
+        .execute_query("SELECT id, name FROM employees")
+        .await
+        .unwrap();
+    assert_eq!(df.count().await.unwrap(), 3);
+}
+
+#[tokio::test]
+async fn test_empty_files() {
+    for fixture in ["empty.csv", "empty.json"] {
+        let mut engine = QueryEngine::new().unwrap();
+        let path = fixture_path(fixture);
+        let result = engine.register_file(path.to_str().unwrap(), "data").await;
+        assert!(
+            matches!(&result, Err(SqlStreamError::EmptyFile(file)) if file == &path),
+            "{}: {:?}",
+            fixture,
+            result
+        );
+    }
+
+    // A header without data rows is a valid, empty table
+    let mut engine = QueryEngine::new().unwrap();
+    engine
+        .register_file(fixture_path("header_only.csv").to_str().unwrap(), "data")
+        .await
+        .unwrap();
+    let schema = engine.table_schema("data").await.unwrap();
+    let names: Vec<_> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+    assert_eq!(names, ["id", "name", "age", "city", "salary"]);
+
+    let batches = engine
+        .collect_query("SELECT name FROM data WHERE city = 'Paris'")
+        .await
+        .unwrap();
+    assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 0);
+}
+
+#[tokio::test]
+async fn test_csv_bom() {
+    let mut engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("bom.csv");
+
+    engine
+        .register_file(csv_path.to_str().unwrap(), "data")
+        .await
+        .unwrap();
+
+    let schema = engine.table_schema("data").await.unwrap();
+    assert_eq!(schema.field(0).name(), "name");
+
+    let batches = engine
+        .collect_query("SELECT name FROM data WHERE age > 40")
+        .await
+        .unwrap();
+    let rows: usize = batches.iter().map(|b| b.num_rows()).sum();
+    assert_eq!(rows, 2);
+}
+
+async fn parsed_price_total(fixture: &str, locale: NumericLocale) -> f64 {
+    use datafusion::arrow::array::Float64Array;
+
+    let mut engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path(fixture);
+    engine
+        .register_file(csv_path.to_str().unwrap(), "items")
+        .await
+        .unwrap();
+    engine
+        .parse_numeric("items", &["price".to_string()], locale)
+        .await
+        .unwrap();
+
+    let batches = engine
+        .execute_query("SELECT SUM(price) FROM items")
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+    batches[0]
+        .column(0)
+        .as_any()
+        .downcast_ref::<Float64Array>()
+        .unwrap()
+        .value(0)
+}
+
+#[tokio::test]
+async fn test_parse_numeric_us() {
+    let total = parsed_price_total("currency_us.csv", NumericLocale::Us).await;
+    assert!((total - (1234.56 + 99.50 - 2000.0)).abs() < 1e-9);
+
+    // Table names that need quoting in SQL work too
+    let mut engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("currency_us.csv");
+    engine
+        .register_file(csv_path.to_str().unwrap(), "price list")
+        .await
+        .unwrap();
+    engine
+        .parse_numeric("price list", &["price".to_string()], NumericLocale::Us)
+        .await
+        .unwrap();
+    let batches = engine
+        .collect_query("SELECT price FROM \"price list\" WHERE price > 1000")
+        .await
+        .unwrap();
+    assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 1);
+}
+
+#[tokio::test]
+async fn test_parse_numeric_eu() {
+    let total = parsed_price_total("currency_eu.csv", NumericLocale::Eu).await;
+    assert!((total - (1234.56 + 99.50 - 2000.0)).abs() < 1e-9);
+
+    let mut engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("currency_eu.csv");
+    engine
+        .register_file(csv_path.to_str().unwrap(), "items")
+        .await
+        .unwrap();
+    let result = engine
+        .parse_numeric("items", &["cost".to_string()], NumericLocale::Eu)
+        .await;
+    assert!(matches!(result, Err(SqlStreamError::ColumnNotFound { .. })));
+}
+
+#[tokio::test]
+async fn test_declared_schema() {
+    use datafusion::arrow::datatypes::DataType;
+    use sql_stream::schema::parse_schema;
+    use std::sync::Arc;
+
+    let declared = |spec: &str| ReadOptions {
+        schema: Some(Arc::new(parse_schema(spec).unwrap())),
+        ..Default::default()
+    };
+    let mut engine = QueryEngine::new().unwrap();
+
+    // CSV: the declared types replace inference
+    let options = declared("id:Int64,name:Utf8,age:Float64,city:Utf8,salary:Float64");
+    engine
+        .register_file_with(
+            fixture_path("sample.csv").to_str().unwrap(),
+            "csv",
+            &options,
+        )
+        .await
+        .unwrap();
+    let schema = engine.table_schema("csv").await.unwrap();
+    assert_eq!(
+        schema.field_with_name("age").unwrap().data_type(),
+        &DataType::Float64
+    );
+    let batches = engine
+        .collect_query("SELECT AVG(age) FROM csv")
+        .await
+        .unwrap();
+    assert_eq!(batches[0].num_rows(), 1);
+
+    // JSON: a schema file may pick a subset of the fields
+    let schema_file = fixture_path("employee_schema.json");
+    let options = declared(schema_file.to_str().unwrap());
+    engine
+        .register_file_with(
+            fixture_path("sample.json").to_str().unwrap(),
+            "json",
+            &options,
+        )
+        .await
+        .unwrap();
+    let schema = engine.table_schema("json").await.unwrap();
+    let names: Vec<_> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+    assert_eq!(names, ["id", "name", "salary"]);
+
+    // The column count has to match the CSV file
+    let result = engine
+        .register_file_with(
+            fixture_path("sample.csv").to_str().unwrap(),
+            "short",
+            &declared("id:Int64,name:Utf8"),
+        )
+        .await;
+    assert!(matches!(result, Err(SqlStreamError::InvalidOption(_))));
+}
+
+#[tokio::test]
+async fn test_parse_temporal() {
+    use datafusion::arrow::datatypes::{DataType, TimeUnit};
+
+    let mut engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("us_dates.csv");
+    engine
+        .register_file(csv_path.to_str().unwrap(), "users")
+        .await
+        .unwrap();
+    engine
+        .parse_temporal("users", Some("%m/%d/%Y"), Some("%Y/%m/%d %H:%M"))
+        .await
+        .unwrap();
+
+    let schema = engine.table_schema("users").await.unwrap();
+    let data_type = |name: &str| schema.field_with_name(name).unwrap().data_type().clone();
+    assert_eq!(data_type("signup"), DataType::Date32);
+    assert_eq!(
+        data_type("last_seen"),
+        DataType::Timestamp(TimeUnit::Nanosecond, None)
+    );
+    assert_eq!(data_type("id"), DataType::Int64);
+    assert_eq!(data_type("note"), DataType::Utf8);
+
+    let batches = engine
+        .collect_query("SELECT id FROM users WHERE signup > DATE '2024-01-01'")
+        .await
+        .unwrap();
+    assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 2);
+
+    // Table names that need quoting in SQL work too
+    engine
+        .register_file(csv_path.to_str().unwrap(), "user list")
+        .await
+        .unwrap();
+    engine
+        .parse_temporal("user list", Some("%m/%d/%Y"), None)
+        .await
+        .unwrap();
+    let schema = engine.table_schema("user list").await.unwrap();
+    assert_eq!(
+        schema.field_with_name("signup").unwrap().data_type(),
+        &DataType::Date32
+    );
+}
+
+#[tokio::test]
+async fn test_find_duplicates() {
+    let mut engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("duplicates.csv");
+    engine
+        .register_file(csv_path.to_str().unwrap(), "users")
+        .await
+        .unwrap();
+
+    let count_rows = |batches: &[datafusion::arrow::record_batch::RecordBatch]| -> usize {
+        batches.iter().map(|b| b.num_rows()).sum()
+    };
+
+    // Whole rows: only Alice's row is repeated
+    let duplicates = analysis::find_duplicates(&engine, "users", &[])
+        .await
+        .unwrap();
+    assert_eq!(count_rows(&duplicates), 1);
+
+    // By email: alice@example.com appears three times
+    let duplicates = analysis::find_duplicates(&engine, "users", &["email".to_string()])
+        .await
+        .unwrap();
+    assert_eq!(count_rows(&duplicates), 1);
+    assert_eq!(duplicates[0].schema().field(1).name(), "duplicate_count");
+
+    let duplicates = analysis::find_duplicates(&engine, "users", &["name".to_string()])
+        .await
+        .unwrap();
+    assert_eq!(count_rows(&duplicates), 1);
+
+    let sample = fixture_path("sample.csv");
+    engine
+        .register_file(sample.to_str().unwrap(), "employees")
+        .await
+        .unwrap();
+    let duplicates = analysis::find_duplicates(&engine, "employees", &[])
+        .await
+        .unwrap();
+    assert_eq!(count_rows(&duplicates), 0);
+}
+
+/// Write the sample CSV back out through Arrow's IPC writers
+async fn sample_as_arrow_ipc(dir: &std::path::Path) -> (PathBuf, PathBuf) {
+    use datafusion::arrow::ipc::writer::{FileWriter, StreamWriter};
+
+    let mut engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("sample.csv");
+    engine
+        .register_file(csv_path.to_str().unwrap(), "employees")
+        .await
+        .unwrap();
+    let df = engine.table("employees").await.unwrap();
+    let schema = df.schema().inner().clone();
+    let batches = df.collect().await.unwrap();
+
+    let file_path = dir.join("employees.arrow");
+    let mut writer =
+        FileWriter::try_new(std::fs::File::create(&file_path).unwrap(), &schema).unwrap();
+    for batch in &batches {
+        writer.write(batch).unwrap();
+    }
+    writer.finish().unwrap();
+
+    let stream_path = dir.join("employees.arrows");
+    let mut writer =
+        StreamWriter::try_new(std::fs::File::create(&stream_path).unwrap(), &schema).unwrap();
+    for batch in &batches {
+        writer.write(batch).unwrap();
+    }
+    writer.finish().unwrap();
+
+    (file_path, stream_path)
+}
+
+#[tokio::test]
+async fn test_arrow_ipc_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let (file_path, stream_path) = sample_as_arrow_ipc(dir.path()).await;
+
+    let mut csv_engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("sample.csv");
+    csv_engine
+        .register_file(csv_path.to_str().unwrap(), "employees")
+        .await
+        .unwrap();
+    let expected = csv_engine.table_schema("employees").await.unwrap();
+
+    for path in [file_path, stream_path] {
+        let mut engine = QueryEngine::new().unwrap();
+        engine
+            .register_file(path.to_str().unwrap(), "employees")
+            .await
+            .unwrap();
+
+        // The schema is carried through unchanged rather than re-inferred
+        assert_eq!(engine.table_schema("employees").await.unwrap(), expected);
+
+        let query = "SELECT name FROM employees WHERE age > 30";
+        let rows = engine.execute_query(query).await.unwrap().count().await;
+        let expected_rows = csv_engine.execute_query(query).await.unwrap().count().await;
+        assert_eq!(rows.unwrap(), expected_rows.unwrap());
+    }
+}
+
+/// A minimal computed table holding the integers `1..=n`
+#[derive(Debug)]
+struct NumbersTable {
+    n: i64,
+}
+
+#[async_trait::async_trait]
+impl datafusion::datasource::TableProvider for NumbersTable {
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn schema(&self) -> datafusion::arrow::datatypes::SchemaRef {
+        use datafusion::arrow::datatypes::{DataType, Field, Schema};
+        std::sync::Arc::new(Schema::new(vec![Field::new("n", DataType::Int64, false)]))
+    }
+
+    fn table_type(&self) -> datafusion::datasource::TableType {
+        datafusion::datasource::TableType::Base
+    }
+
+    async fn scan(
+        &self,
+        _state: &dyn datafusion::catalog::Session,
+        projection: Option<&Vec<usize>>,
+        _filters: &[datafusion::prelude::Expr],
+        _limit: Option<usize>,
+    ) -> datafusion::error::Result<std::sync::Arc<dyn datafusion::physical_plan::ExecutionPlan>>
+    {
+        use datafusion::arrow::array::Int64Array;
+        use datafusion::arrow::record_batch::RecordBatch;
+        use datafusion::physical_plan::memory::MemoryExec;
+
+        let values = Int64Array::from_iter_values(1..=self.n);
+        let batch = RecordBatch::try_new(self.schema(), vec![std::sync::Arc::new(values)])?;
+        let exec = MemoryExec::try_new(&[vec![batch]], self.schema(), projection.cloned())?;
+        Ok(std::sync::Arc::new(exec))
+    }
+}
+
+#[tokio::test]
+async fn test_register_custom_provider() {
+    use datafusion::arrow::array::Int64Array;
+
+    let mut engine = QueryEngine::new().unwrap();
+    engine
+        .register_provider("numbers", std::sync::Arc::new(NumbersTable { n: 10 }))
+        .unwrap();
+
+    let batches = engine
+        .execute_query("SELECT SUM(n) FROM numbers WHERE n % 2 = 0")
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+    let sum = batches[0]
+        .column(0)
+        .as_any()
+        .downcast_ref::<Int64Array>()
+        .unwrap()
+        .value(0);
+    assert_eq!(sum, 30);
+
+    // Providers compose with the built-in analysis helpers
+    let frequencies = analysis::value_frequencies(&engine, "numbers", "n", 3)
+        .await
+        .unwrap();
+    assert_eq!(frequencies.len(), 3);
+}
+
+#[tokio::test]
+async fn test_parquet_select() {
+    use datafusion::arrow::datatypes::DataType;
+
+    // Produce a small Parquet file from the CSV fixture
+    let dir = tempfile::tempdir().unwrap();
+    let parquet_path = dir.path().join("employees.parquet");
+    let mut csv_engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("sample.csv");
+    csv_engine
+        .register_file(csv_path.to_str().unwrap(), "employees")
+        .await
+        .unwrap();
+    let df = csv_engine.table("employees").await.unwrap();
+    csv_engine.write_results(df, &parquet_path).await.unwrap();
+
+    let mut engine = QueryEngine::new().unwrap();
+    engine
+        .register_file(parquet_path.to_str().unwrap(), "employees")
+        .await
+        .unwrap();
+
+    let schema = engine.table_schema("employees").await.unwrap();
+    assert_eq!(
+        schema.field_with_name("age").unwrap().data_type(),
+        &DataType::Int64
+    );
+
+    let df = engine
+        .execute_query("SELECT * FROM employees WHERE age > 30")
+        .await
+        .unwrap();
+    let expected = csv_engine
+        .execute_query("SELECT * FROM employees WHERE age > 30")
+        .await
+        .unwrap();
+    assert_eq!(df.count().await.unwrap(), expected.count().await.unwrap());
+}
+
+#[tokio::test]
+async fn test_join_csv_and_json() {
+    let mut engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("sample.csv");
+    let json_path = fixture_path("sample.json");
+    engine
+        .register_file(csv_path.to_str().unwrap(), "employees")
+        .await
+        .unwrap();
+    engine
+        .register_file(json_path.to_str().unwrap(), "people")
+        .await
+        .unwrap();
+
+    let df = engine
+        .execute_query("SELECT e.name FROM employees e JOIN people p ON e.name = p.name")
+        .await
+        .unwrap();
+    assert!(df.count().await.unwrap() > 0);
+}
+
+#[tokio::test]
+async fn test_join_parquet_and_csv() {
+    use datafusion::arrow::array::{Int64Array, StringArray};
+    use datafusion::arrow::compute::concat_batches;
+
+    // A fact table split across a directory of Parquet files
+    let dir = tempfile::tempdir().unwrap();
+    let mut csv_engine = QueryEngine::new().unwrap();
+    csv_engine
+        .register_file(fixture_path("sample.csv").to_str().unwrap(), "employees")
+        .await
+        .unwrap();
+    for (part, filter) in [("young", "age <= 30"), ("senior", "age > 30")] {
+        let df = csv_engine
+            .execute_query(&format!(
+                "SELECT id, salary FROM employees WHERE {}",
+                filter
+            ))
+            .await
+            .unwrap();
+        let path = dir.path().join(format!("{}.parquet", part));
+        csv_engine.write_results(df, &path).await.unwrap();
+    }
+
+    // Joined with a gzipped CSV dimension table, each format detected on its own
+    let mut engine = QueryEngine::new().unwrap();
+    let people = fixture_path("sample.csv.gz");
+    let tables = [
+        (dir.path().to_str().unwrap(), "salaries"),
+        (people.to_str().unwrap(), "people"),
+    ];
+    engine
+        .register_all(&tables, &ReadOptions::default())
+        .await
+        .unwrap();
+
+    let batches = engine
+        .collect_query(
+            "SELECT p.name, s.salary FROM salaries s JOIN people p ON s.id = p.id ORDER BY s.id",
+        )
+        .await
+        .unwrap();
+    let batch = concat_batches(&batches[0].schema(), &batches).unwrap();
+    assert_eq!(batch.num_rows(), 10);
+    let names = batch
+        .column(0)
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .unwrap();
+    let salaries = batch
+        .column(1)
+        .as_any()
+        .downcast_ref::<Int64Array>()
+        .unwrap();
+    assert_eq!(names.value(0), "Alice Johnson");
+    assert_eq!(salaries.value(0), 75000);
+    assert_eq!(names.value(1), "Bob Smith");
+    assert_eq!(salaries.value(1), 65000);
+}
+
+#[tokio::test]
+async fn test_register_each() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join("customers.csv"),
+        "id,name\n1,Alice\n2,Bob\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.path().join("orders.csv"),
+        "id,customer_id,total\n10,1,5.5\n11,1,4.5\n12,2,3.0\n",
+    )
+    .unwrap();
+    // Same stem as orders.csv, so it is suffixed
+    std::fs::write(dir.path().join("orders.tsv"), "id\ttotal\n20\t1.0\n").unwrap();
+
+    let mut engine = QueryEngine::new().unwrap();
+    let names = engine
+        .register_each(dir.path().to_str().unwrap(), &ReadOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(names, ["customers", "orders", "orders_2"]);
+
+    let batches = engine
+        .collect_query(
+            "SELECT c.name, SUM(o.total) AS spent FROM orders o \
+             JOIN customers c ON o.customer_id = c.id GROUP BY c.name ORDER BY c.name",
+        )
+        .await
+        .unwrap();
+    let formatted = sql_stream::output::format_table(&batches).unwrap();
+    assert!(formatted.contains("Alice") && formatted.contains("10.0"));
+    assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 2);
+
+    // Names already registered are avoided too
+    let names = engine
+        .register_each(
+            dir.path().join("customers.csv").to_str().unwrap(),
+            &ReadOptions::default(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(names, ["customers_2"]);
+}
+
+#[tokio::test]
+async fn test_print_formats_are_parseable() {
+    let mut engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("sample.csv");
+    engine
+        .register_file(csv_path.to_str().unwrap(), "employees")
+        .await
+        .unwrap();
+
+    let query = "SELECT id, name, salary FROM employees ORDER BY id LIMIT 4";
+    let render = |format| {
+        let engine = &engine;
+        async move {
+            let df = engine.execute_query(query).await.unwrap();
+            let mut buffer = Vec::new();
+            let rows = engine
+                .write_formatted(df, format, &mut buffer)
+                .await
+                .unwrap();
+            assert_eq!(rows, 4);
+            String::from_utf8(buffer).unwrap()
+        }
+    };
+
+    let csv = render(PrintFormat::Csv).await;
+    let mut reader = csv::Reader::from_reader(csv.as_bytes());
+    let headers: Vec<_> = reader.headers().unwrap().iter().map(String::from).collect();
+    assert_eq!(headers, vec!["id", "name", "salary"]);
+    assert_eq!(reader.records().count(), 4);
+
+    let json = render(PrintFormat::Json).await;
+    let rows: Vec<serde_json::Value> = serde_json::from_str(&json).unwrap();
+    assert_eq!(rows.len(), 4);
+    assert_eq!(rows[0]["name"], "Alice Johnson");
+
+    let ndjson = render(PrintFormat::Ndjson).await;
+    let rows: Vec<serde_json::Value> = ndjson
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(rows.len(), 4);
+    assert_eq!(rows[3]["id"], 4);
+
+    let table = render(PrintFormat::Table).await;
+    assert!(table.contains("Alice Johnson"));
+}
+
+#[tokio::test]
+async fn test_streamed_output_many_rows() {
+    let engine = QueryEngine::new().unwrap();
+    let query = "SELECT value, value * 2 AS doubled FROM generate_series(1, 50000)";
+
+    let df = engine.execute_query(query).await.unwrap();
+    let mut buffer = Vec::new();
+    let rows = engine
+        .write_formatted(df, PrintFormat::Ndjson, &mut buffer)
+        .await
+        .unwrap();
+    assert_eq!(rows, 50000);
+    assert_eq!(String::from_utf8(buffer).unwrap().lines().count(), 50000);
+
+    let df = engine.execute_query(query).await.unwrap();
+    let mut buffer = Vec::new();
+    let rows = engine
+        .write_formatted(df, PrintFormat::Table, &mut buffer)
+        .await
+        .unwrap();
+    assert_eq!(rows, 50000);
+}
+
+#[tokio::test]
+async fn test_register_reader() {
+    use sql_stream::InputFormat;
+
+    let mut engine = QueryEngine::new().unwrap();
+    let csv = std::fs::read(fixture_path("sample.csv")).unwrap();
+    engine
+        .register_reader(
+            std::io::Cursor::new(csv),
+            InputFormat::Csv,
+            "employees",
+            &ReadOptions::default(),
+        )
+        .await
+        .unwrap();
+
+    let json = std::fs::read(fixture_path("sample.json")).unwrap();
+    engine
+        .register_reader(
+            std::io::Cursor::new(json),
+            InputFormat::Json,
+            "people",
+            &ReadOptions::default(),
+        )
+        .await
+        .unwrap();
+
+    // TSV input is split on tabs without setting a delimiter
+    let tsv = std::fs::read(fixture_path("tabs.tsv")).unwrap();
+    engine
+        .register_reader(
+            std::io::Cursor::new(tsv),
+            InputFormat::Tsv,
+            "cities",
+            &ReadOptions::default(),
+        )
+        .await
+        .unwrap();
+    let schema = engine.table_schema("cities").await.unwrap();
+    assert_eq!(schema.fields().len(), 3);
+
+    let df = engine
+        .execute_query("SELECT * FROM employees JOIN people USING (id)")
+        .await
+        .unwrap();
+    assert_eq!(df.count().await.unwrap(), 10);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_named_pipe() {
+    let dir = tempfile::tempdir().unwrap();
+    let fifo = dir.path().join("employees.csv.gz");
+    let status = std::process::Command::new("mkfifo")
+        .arg(&fifo)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // The writer blocks until the pipe is opened for reading
+    let writer = {
+        let fifo = fifo.clone();
+        std::thread::spawn(move || {
+            let data = std::fs::read(fixture_path("sample.csv.gz")).unwrap();
+            std::fs::write(fifo, data).unwrap();
+        })
+    };
+
+    let mut engine = QueryEngine::new().unwrap();
+    engine
+        .register_file(fifo.to_str().unwrap(), "employees")
+        .await
+        .unwrap();
+    writer.join().unwrap();
+
+    // The copy can be scanned any number of times
+    for _ in 0..2 {
+        let batches = engine
+            .collect_query("SELECT COUNT(*) FROM employees WHERE age > 30")
+            .await
+            .unwrap();
+        let count = batches[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<datafusion::arrow::array::Int64Array>()
+            .unwrap();
+        assert_eq!(count.value(0), 5);
+    }
+
+    // Other special files are rejected
+    let socket = dir.path().join("data.csv");
+    let _listener = std::os::unix::net::UnixListener::bind(&socket).unwrap();
+    let result = engine.register_file(socket.to_str().unwrap(), "data").await;
+    assert!(matches!(
+        result,
+        Err(SqlStreamError::UnsupportedSpecialFile(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_collect_query() {
+    let mut engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("sample.csv");
+    engine
+        .register_file(csv_path.to_str().unwrap(), "employees")
+        .await
+        .unwrap();
+
+    let batches: Vec<sql_stream::RecordBatch> = engine
+        .collect_query("SELECT name FROM employees WHERE age > 30")
+        .await
+        .unwrap();
+    let rows: usize = batches.iter().map(|b| b.num_rows()).sum();
+    assert!(rows > 0);
+    assert_eq!(batches[0].schema().field(0).name(), "name");
+
+    let result = engine.collect_query("SELECT * FROM missing").await;
+    assert!(matches!(result, Err(SqlStreamError::TableNotFound { .. })));
+}
+
+#[tokio::test]
+async fn test_tsv_delimiter() {
+    let mut engine = QueryEngine::new().unwrap();
+    let tsv_path = fixture_path("tabs.tsv");
+    let options = ReadOptions {
+        delimiter: Some(b'\t'),
+        ..Default::default()
+    };
+    engine
+        .register_file_with(tsv_path.to_str().unwrap(), "people", &options)
+        .await
+        .unwrap();
+
+    let schema = engine.table_schema("people").await.unwrap();
+    assert_eq!(schema.fields().len(), 3);
+
+    let df = engine
+        .execute_query("SELECT city FROM people WHERE name = 'Brown, Charlie'")
+        .await
+        .unwrap();
+    assert_eq!(df.count().await.unwrap(), 1);
+
+    // .tsv files default to a tab delimiter
+    engine
+        .register_file(tsv_path.to_str().unwrap(), "defaults")
+        .await
+        .unwrap();
+    let schema = engine.table_schema("defaults").await.unwrap();
+    assert_eq!(schema.fields().len(), 3);
+
+    // An explicit delimiter still wins over the extension
+    let dir = tempfile::tempdir().unwrap();
+    let semicolons = dir.path().join("semicolons.tsv");
+    std::fs::write(&semicolons, "id;name\n1;Alice\n").unwrap();
+    let options = ReadOptions {
+        delimiter: Some(b';'),
+        ..Default::default()
+    };
+    engine
+        .register_file_with(semicolons.to_str().unwrap(), "semicolons", &options)
+        .await
+        .unwrap();
+    let schema = engine.table_schema("semicolons").await.unwrap();
+    assert_eq!(schema.fields().len(), 2);
+}
+
+#[tokio::test]
+async fn test_csv_no_header() {
+    use datafusion::arrow::array::Int64Array;
+
+    let mut engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("no_header.csv");
+    let options = ReadOptions {
+        no_header: true,
+        ..Default::default()
+    };
+    engine
+        .register_file_with(csv_path.to_str().unwrap(), "data", &options)
+        .await
+        .unwrap();
+
+    let batches = engine
+        .collect_query("SELECT column_1 FROM data ORDER BY column_1")
+        .await
+        .unwrap();
+    let ids: Vec<i64> = batches
+        .iter()
+        .flat_map(|batch| {
+            let ids = batch
+                .column(0)
+                .as_any()
+                .downcast_ref::<Int64Array>()
+                .unwrap();
+            ids.values().to_vec()
+        })
+        .collect();
+    assert_eq!(ids, vec![1, 2, 3]);
+}
+
+#[tokio::test]
+async fn test_repl_session_survives_errors() {
+    let mut engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("sample.csv");
+    engine
+        .register_file(csv_path.to_str().unwrap(), "employees")
+        .await
+        .unwrap();
+
+    let input =
+        "SELECT COUNT(*)\nFROM employees;\nSELECT * FROM missing;\nSELECT 1;\n\\q\nSELECT 2;\n";
+    let mut output = Vec::new();
+    let result = sql_stream::repl::run(
+        &mut engine,
+        input.as_bytes(),
+        &mut output,
+        PrintFormat::Csv,
+        false,
+        &ReadOptions::default(),
+    )
+    .await;
+    assert!(result.is_ok());
+
+    // The failing statement doesn't stop the next one, and \q ends the session
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("count(*)\n10\n"));
+    assert!(output.contains("Int64(1)\n1\n"));
+    assert!(!output.contains("Int64(2)"));
+}
+
+#[tokio::test]
+async fn test_replace_table() {
+    let csv_path = fixture_path("sample.csv");
+    let zips_path = fixture_path("zips.csv");
+    let mut engine = QueryEngine::new().unwrap();
+    engine
+        .register_file(csv_path.to_str().unwrap(), "data")
+        .await
+        .unwrap();
+
+    // Registering the name again fails and leaves the table alone
+    let result = engine
+        .register_file(zips_path.to_str().unwrap(), "data")
+        .await;
+    assert!(matches!(
+        result,
+        Err(SqlStreamError::TableRegistration(_, ref detail)) if detail.contains("already registered")
+    ));
+    let schema = engine.table_schema("data").await.unwrap();
+    assert_eq!(schema.fields().len(), 5);
+
+    // With replace the new file takes its place
+    let replace = ReadOptions {
+        replace: true,
+        ..Default::default()
+    };
+    engine
+        .register_file_with(zips_path.to_str().unwrap(), "DATA", &replace)
+        .await
+        .unwrap();
+    assert_eq!(engine.list_tables(), ["data"]);
+    let schema = engine.table_schema("data").await.unwrap();
+    assert_eq!(schema.fields().len(), 3);
+
+    // A replacement that fails keeps the old table
+    let result = engine
+        .register_file_with("missing.csv", "data", &replace)
+        .await;
+    assert!(matches!(result, Err(SqlStreamError::FileNotFound(_))));
+    let schema = engine.table_schema("data").await.unwrap();
+    assert_eq!(schema.fields().len(), 3);
+
+    // The shell's \load reloads tables the same way
+    let input = format!(
+        "\\load {} data\nSELECT COUNT(*) FROM data;\n",
+        csv_path.display()
+    );
+    let mut output = Vec::new();
+    sql_stream::repl::run(
+        &mut engine,
+        input.as_bytes(),
+        &mut output,
+        PrintFormat::Csv,
+        false,
+        &replace,
+    )
+    .await
+    .unwrap();
+    let schema = engine.table_schema("data").await.unwrap();
+    assert_eq!(schema.fields().len(), 5);
+}
+
+#[tokio::test]
+async fn test_table_names_and_schemas() {
+    use datafusion::arrow::datatypes::DataType;
+
+    let mut engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("sample.csv");
+    let json_path = fixture_path("sample.json");
+    engine
+        .register_file(csv_path.to_str().unwrap(), "employees")
+        .await
+        .unwrap();
+    engine
+        .register_file(json_path.to_str().unwrap(), "people")
+        .await
+        .unwrap();
+
+    assert_eq!(engine.list_tables(), vec!["employees", "people"]);
+
+    let schema = engine.table_schema("employees").await.unwrap();
+    let description = analysis::schema_table(&schema).unwrap();
+    assert_eq!(description.num_rows(), 5);
+
+    let types = description
+        .column(1)
+        .as_any()
+        .downcast_ref::<datafusion::arrow::array::StringArray>()
+        .unwrap();
+    assert_eq!(types.value(2), DataType::Int64.to_string());
+}
+
+#[tokio::test]
+async fn test_infer_rows() {
+    use datafusion::arrow::datatypes::DataType;
+
+    let csv_path = fixture_path("late_float.csv");
+
+    // The first float appears after DataFusion's default inference window
+    let mut engine = QueryEngine::new().unwrap();
+    engine
+        .register_file(csv_path.to_str().unwrap(), "data")
+        .await
+        .unwrap();
+    let schema = engine.table_schema("data").await.unwrap();
+    assert_eq!(
+        schema.field_with_name("value").unwrap().data_type(),
+        &DataType::Int64
+    );
+
+    for infer_rows in [0, 2000] {
+        let mut engine = QueryEngine::new().unwrap();
+        let options = ReadOptions {
+            infer_rows: Some(infer_rows),
+            ..Default::default()
+        };
+        engine
+            .register_file_with(csv_path.to_str().unwrap(), "data", &options)
+            .await
+            .unwrap();
+        let schema = engine.table_schema("data").await.unwrap();
+        assert_eq!(
+            schema.field_with_name("value").unwrap().data_type(),
+            &DataType::Float64
+        );
+
+        let df = engine.execute_query("SELECT SUM(value) FROM data").await;
+        assert!(df.unwrap().collect().await.is_ok());
+    }
+}
+
+#[tokio::test]
+async fn test_explain_and_analyze() {
+    let mut engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("sample.csv");
+    engine
+        .register_file(csv_path.to_str().unwrap(), "employees")
+        .await
+        .unwrap();
+
+    let query = "SELECT city, AVG(salary) FROM employees GROUP BY city";
+    for analyze in [false, true] {
+        let batches = engine
+            .collect_query(&sql_stream::sql::explain(query, analyze))
+            .await
+            .unwrap();
+        let plan = datafusion::arrow::util::pretty::pretty_format_batches(&batches)
+            .unwrap()
+            .to_string();
+        assert!(plan.contains("AggregateExec"));
+        // Only EXPLAIN ANALYZE runs the plan and reports metrics
+        assert_eq!(plan.contains("output_rows"), analyze);
+    }
+}
+
+#[tokio::test]
+async fn test_write_options() {
+    let mut engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("sample.csv");
+    engine
+        .register_file(csv_path.to_str().unwrap(), "employees")
+        .await
+        .unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("reports/2024/names.txt");
+    let query = "SELECT name FROM employees ORDER BY id LIMIT 2";
+    let mut options = WriteOptions {
+        format: Some(OutputFormat::Ndjson),
+        ..Default::default()
+    };
+
+    // Parent directories are created and the format overrides the extension
+    let df = engine.execute_query(query).await.unwrap();
+    assert_eq!(
+        engine
+            .write_results_with(df, &output, &options)
+            .await
+            .unwrap(),
+        2
+    );
+    let written = std::fs::read_to_string(&output).unwrap();
+    assert_eq!(written.lines().count(), 2);
+    assert!(written.starts_with("{\"name\":"));
+
+    // An existing file is only replaced when overwriting is allowed
+    let df = engine.execute_query(query).await.unwrap();
+    let err = engine.write_results_with(df, &output, &options).await;
+    assert!(matches!(err, Err(SqlStreamError::Output(_))));
+    assert_eq!(std::fs::read_to_string(&output).unwrap(), written);
+
+    options.format = Some(OutputFormat::Csv);
+    options.overwrite = true;
+    let df = engine.execute_query(query).await.unwrap();
+    engine
+        .write_results_with(df, &output, &options)
+        .await
+        .unwrap();
+    assert!(std::fs::read_to_string(&output)
+        .unwrap()
+        .starts_with("name\n"));
+}
+
+#[tokio::test]
+async fn test_multi_file_table() {
+    let parts = fixture_path("parts");
+    let pattern = parts.join("*.csv");
+    for path in [pattern.to_str().unwrap(), parts.to_str().unwrap()] {
+        let mut engine = QueryEngine::new().unwrap();
+        engine.register_file(path, "events").await.unwrap();
+        let batches = engine.collect_query("SELECT * FROM events").await.unwrap();
+        let rows: usize = batches.iter().map(|b| b.num_rows()).sum();
+        assert_eq!(rows, 3);
+    }
+
+    // Files whose columns diverge are rejected rather than silently merged
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("a.csv"), "id,event\n1,click\n").unwrap();
+    std::fs::write(dir.path().join("b.csv"), "id,kind\n2,view\n").unwrap();
+    let mut engine = QueryEngine::new().unwrap();
+    let result = engine
+        .register_file(dir.path().to_str().unwrap(), "events")
+        .await;
+    assert!(matches!(result, Err(SqlStreamError::SchemaMismatch { .. })));
+
+    let missing = parts.join("*.json");
+    let result = engine
+        .register_file(missing.to_str().unwrap(), "events")
+        .await;
+    assert!(matches!(result, Err(SqlStreamError::FileNotFound(_))));
+}
+
+#[tokio::test]
+async fn test_partitioned_directory() {
+    use datafusion::arrow::array::Int64Array;
+    use datafusion::arrow::datatypes::DataType;
+
+    let mut engine = QueryEngine::new().unwrap();
+    engine
+        .register_file(fixture_path("sales").to_str().unwrap(), "sales")
+        .await
+        .unwrap();
+
+    let schema = engine.table_schema("sales").await.unwrap();
+    let columns: Vec<_> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+    assert_eq!(columns, ["id", "amount", "year", "month"]);
+    assert_eq!(
+        schema.field_with_name("year").unwrap().data_type(),
+        &DataType::Int64
+    );
+
+    let batches = engine
+        .collect_query("SELECT COUNT(*) AS n FROM sales WHERE year = 2024 AND month = 1")
+        .await
+        .unwrap();
+    let count = batches[0]
+        .column(0)
+        .as_any()
+        .downcast_ref::<Int64Array>()
+        .unwrap()
+        .value(0);
+    assert_eq!(count, 3);
+
+    // Filtering on a partition column skips the other directories entirely
+    let all = engine.scanned_files("SELECT * FROM sales").await.unwrap();
+    assert_eq!(all.len(), 3);
+    let pruned = engine
+        .scanned_files("SELECT * FROM sales WHERE year = 2024")
+        .await
+        .unwrap();
+    assert_eq!(pruned.len(), 2);
+    assert!(pruned.iter().all(|file| file.contains("year=2024")));
+}
+
+#[tokio::test]
+async fn test_compressed_inputs() {
+    use datafusion::arrow::array::Int64Array;
+
+    for (plain, compressed) in [
+        ("sample.csv", "sample.csv.gz"),
+        ("sample.json", "sample.json.zst"),
+    ] {
+        let mut engine = QueryEngine::new().unwrap();
+        for (file, table) in [(plain, "plain"), (compressed, "compressed")] {
+            engine
+                .register_file(fixture_path(file).to_str().unwrap(), table)
+                .await
+                .unwrap();
+        }
+
+        let batches = engine
+            .collect_query(
+                "SELECT (SELECT COUNT(*) FROM plain) AS plain, \
+                 (SELECT COUNT(*) FROM compressed) AS compressed",
+            )
+            .await
+            .unwrap();
+        let count = |i: usize| {
+            batches[0]
+                .column(i)
+                .as_any()
+                .downcast_ref::<Int64Array>()
+                .unwrap()
+                .value(0)
+        };
+        assert!(count(0) > 0);
+        assert_eq!(count(0), count(1), "{}", compressed);
+    }
+}
+
+#[tokio::test]
+async fn test_cancelled_write_removes_partial_output() {
+    // Several partitions, so the scan runs in tasks that yield to the timeout
+    let config = EngineConfig {
+        target_partitions: Some(4),
+        ..Default::default()
+    };
+    let engine = QueryEngine::with_config(&config).unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("big.csv");
+
+    let df = engine
+        .execute_query("SELECT value, value * 2 AS doubled FROM generate_series(1, 100000000)")
+        .await
+        .unwrap();
+    let options = WriteOptions::default();
+    let write = engine.write_results_with(df, &output, &options);
+    let result = tokio::time::timeout(std::time::Duration::from_millis(50), write).await;
+
+    assert!(result.is_err(), "write should still be running");
+    assert!(!output.exists());
+}
+
+#[tokio::test]
+async fn test_cancel_query() {
+    // Several partitions, so the scan runs in tasks that yield to the canceller
+    let config = EngineConfig {
+        target_partitions: Some(4),
+        ..Default::default()
+    };
+    let engine = QueryEngine::with_config(&config).unwrap();
+    let token = CancellationToken::new();
+
+    let canceller = token.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        canceller.cancel();
+    });
+
+    // Far too many rows to finish before the token is cancelled
+    let query = engine.execute_query_cancellable(
+        "SELECT SUM(value) FROM generate_series(1, 100000000000)",
+        &token,
+    );
+    let result = tokio::time::timeout(std::time::Duration::from_secs(30), query)
+        .await
+        .expect("the query should stop once cancelled");
+    assert!(
+        matches!(result, Err(SqlStreamError::Cancelled)),
+        "{:?}",
+        result
+    );
+
+    // A cancelled token stays cancelled
+    let result = engine.execute_query_cancellable("SELECT 1", &token).await;
+    assert!(matches!(result, Err(SqlStreamError::Cancelled)));
+}
+
+#[tokio::test]
+async fn test_result_cache() {
+    let config = EngineConfig {
+        cache_entries: Some(2),
+        ..Default::default()
+    };
+    let mut engine = QueryEngine::with_config(&config).unwrap();
+    let csv_path = fixture_path("sample.csv");
+    engine
+        .register_file(csv_path.to_str().unwrap(), "employees")
+        .await
+        .unwrap();
+
+    let query = "SELECT name FROM employees WHERE age > 30 ORDER BY name";
+    let first = engine
+        .execute_cached(query)
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+    assert_eq!(engine.cache_stats().misses, 1);
+
+    // Layout differences still hit, and the result is the same
+    let second = engine
+        .execute_cached("SELECT name\n  FROM employees WHERE age > 30   ORDER BY name;")
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+    let stats = engine.cache_stats();
+    assert_eq!((stats.hits, stats.misses, stats.entries), (1, 1, 1));
+    assert_eq!(first, second);
+
+    // Non-queries always run and are not kept
+    engine
+        .execute_cached("CREATE VIEW seniors AS SELECT * FROM employees WHERE age > 30")
+        .await
+        .unwrap();
+    assert_eq!(engine.cache_stats().entries, 1);
+
+    // The status names the normalized key, and the age of reused results
+    let (_, status) = engine.execute_cached_status(query).await.unwrap();
+    assert!(matches!(status, CacheStatus::Hit { ref key, .. } if key == query));
+    let (_, status) = engine
+        .execute_cached_status("SET datafusion.execution.batch_size = 1024")
+        .await
+        .unwrap();
+    assert_eq!(status, CacheStatus::Bypassed);
+
+    // The least recently used result is dropped when full
+    engine.execute_cached("SELECT 1").await.unwrap();
+    engine.execute_cached("SELECT 2").await.unwrap();
+    let (_, status) = engine.execute_cached_status(query).await.unwrap();
+    assert_eq!(
+        status,
+        CacheStatus::Miss {
+            key: query.to_string()
+        }
+    );
+    let stats = engine.cache_stats();
+    assert_eq!((stats.hits, stats.misses, stats.entries), (2, 4, 2));
+
+    // Without a cache nothing is kept
+    let engine = QueryEngine::new().unwrap();
+    engine.execute_cached("SELECT 1").await.unwrap();
+    engine.execute_cached("SELECT 1").await.unwrap();
+    assert_eq!(engine.cache_stats(), Default::default());
+}
+
+#[tokio::test]
+async fn test_memory_limit() {
+    let config = EngineConfig {
+        memory_limit: Some(64 * 1024),
+        ..Default::default()
+    };
+    let engine = QueryEngine::with_config(&config).unwrap();
+
+    // Small queries run normally under the limit
+    let batches = engine.collect_query("SELECT 1 + 1").await.unwrap();
+    assert_eq!(batches[0].num_rows(), 1);
+
+    // A hash join's build side cannot spill, so it exhausts the budget
+    let result = engine
+        .collect_query(
+            "SELECT COUNT(*) FROM generate_series(1, 200000) a \
+             JOIN generate_series(1, 200000) b ON a.value = b.value",
+        )
+        .await;
+    assert!(
+        matches!(result, Err(SqlStreamError::MemoryExhausted(_))),
+        "{:?}",
+        result
+    );
+}
+
+#[tokio::test]
+async fn test_memory_report() {
+    use futures::StreamExt;
+
+    let config = EngineConfig {
+        memory_limit: Some(32 * 1024 * 1024),
+        target_partitions: Some(1),
+        ..Default::default()
+    };
+    async fn drain(engine: &QueryEngine, sql: &str) {
+        let mut stream = engine.stream_query(sql).await.unwrap();
+        while let Some(batch) = stream.next().await {
+            batch.unwrap();
+        }
+    }
+
+    // A small query fits in memory
+    let engine = QueryEngine::with_config(&config).unwrap();
+    drain(
+        &engine,
+        "SELECT value FROM generate_series(1, 1000) ORDER BY value DESC",
+    )
+    .await;
+    let stats = engine.memory_stats();
+    assert_eq!(stats.limit, Some(32 * 1024 * 1024));
+    assert!(stats.peak > 0);
+    assert_eq!((stats.spills, stats.spilled_bytes), (0, 0));
+    assert!(
+        stats.summary().ends_with("; no spilling occurred"),
+        "{}",
+        stats.summary()
+    );
+
+    // Sorting 40 MiB of values has to spill
+    let engine = QueryEngine::with_config(&config).unwrap();
+    drain(
+        &engine,
+        "SELECT value FROM generate_series(1, 5000000) ORDER BY value DESC",
+    )
+    .await;
+    let stats = engine.memory_stats();
+    assert!(stats.spills > 0 && stats.spilled_bytes > 0, "{:?}", stats);
+    assert!(stats.peak > 0, "{:?}", stats);
+    assert!(
+        stats.summary().contains("; spilled "),
+        "{}",
+        stats.summary()
+    );
+
+    // Without a limit nothing is tracked
+    let engine = QueryEngine::new().unwrap();
+    drain(&engine, "SELECT 1").await;
+    assert_eq!(engine.memory_stats(), Default::default());
+}
+
+#[tokio::test]
+async fn test_session_options() {
+    use datafusion::arrow::array::{Int64Array, StringArray};
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use std::sync::Arc;
+
+    let config = EngineConfig {
+        information_schema: true,
+        ..Default::default()
+    };
+    let mut engine = QueryEngine::with_config(&config).unwrap();
+    engine
+        .register_file(fixture_path("sample.csv").to_str().unwrap(), "employees")
+        .await
+        .unwrap();
+
+    let batches = engine
+        .collect_query(
+            "SELECT column_name FROM information_schema.columns \
+             WHERE table_name = 'employees' ORDER BY ordinal_position",
+        )
+        .await
+        .unwrap();
+    let columns: Vec<String> = batches
+        .iter()
+        .flat_map(|batch| {
+            let names = batch
+                .column(0)
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .unwrap();
+            names
+                .iter()
+                .map(|name| name.unwrap().to_string())
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    assert_eq!(columns, ["id", "name", "age", "city", "salary"]);
+
+    // Without the option the schema is not exposed
+    let engine = QueryEngine::new().unwrap();
+    assert!(engine
+        .collect_query("SELECT * FROM information_schema.tables")
+        .await
+        .is_err());
+
+    // Mixed-case column names match unquoted only when case is preserved
+    let schema = Arc::new(Schema::new(vec![Field::new(
+        "UserId",
+        DataType::Int64,
+        false,
+    )]));
+    let batch =
+        sql_stream::RecordBatch::try_new(schema, vec![Arc::new(Int64Array::from(vec![1, 2]))])
+            .unwrap();
+    let config = EngineConfig {
+        preserve_case: true,
+        ..Default::default()
+    };
+    let mut engine = QueryEngine::with_config(&config).unwrap();
+    engine
+        .register_batches("users", vec![batch.clone()])
+        .unwrap();
+    assert!(engine
+        .collect_query("SELECT UserId FROM users")
+        .await
+        .is_ok());
+
+    let mut engine = QueryEngine::new().unwrap();
+    engine.register_batches("users", vec![batch]).unwrap();
+    assert!(engine
+        .collect_query("SELECT UserId FROM users")
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_stream_query() {
+    use futures::StreamExt;
+
+    let mut engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("sample.csv");
+    engine
+        .register_file(csv_path.to_str().unwrap(), "employees")
+        .await
+        .unwrap();
+
+    let mut stream = engine
+        .stream_query("SELECT name FROM employees")
+        .await
+        .unwrap();
+    assert_eq!(stream.schema().field(0).name(), "name");
+
+    let mut rows = 0;
+    while let Some(batch) = stream.next().await {
+        rows += batch.unwrap().num_rows();
+    }
+    assert_eq!(rows, 10);
+    assert_eq!(engine.stream_stats().rows, 10);
+
+    assert!(engine
+        .stream_query("SELECT missing FROM employees")
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_benchmark_query() {
+    let mut engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("sample.csv");
+    engine
+        .register_file(csv_path.to_str().unwrap(), "employees")
+        .await
+        .unwrap();
+
+    let timings = engine
+        .benchmark_query(
+            "SELECT city, AVG(salary) FROM employees GROUP BY city",
+            1,
+            3,
+        )
+        .await
+        .unwrap();
+    assert_eq!(timings.runs.len(), 3);
+    assert!(timings.min() <= timings.median() && timings.median() <= timings.max());
+    assert_eq!(engine.stream_stats().rows, 0);
+
+    assert!(engine
+        .benchmark_query("SELECT missing FROM employees", 0, 1)
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_stream_stats() {
+    let mut engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("sample.csv");
+    engine
+        .register_file(csv_path.to_str().unwrap(), "employees")
+        .await
+        .unwrap();
+    assert_eq!(engine.stream_stats().rows, 0);
+
+    let df = engine
+        .execute_query("SELECT * FROM employees")
+        .await
+        .unwrap();
+    let rows = engine
+        .write_formatted(df, PrintFormat::Csv, Vec::new())
+        .await
+        .unwrap();
+
+    let stats = engine.stream_stats();
+    assert_eq!(stats.rows, rows);
+    assert!(stats.batches >= 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_deadline() {
+    use std::time::{Duration, Instant};
+
+    // Results stop at the deadline, keeping the rows produced before it
+    let engine = QueryEngine::new().unwrap();
+    let options = WriteOptions {
+        deadline: Some(Instant::now() + Duration::from_millis(300)),
+        ..Default::default()
+    };
+    let df = engine
+        .execute_query("SELECT value FROM generate_series(1, 100000000000) WHERE value % 1000 = 0")
+        .await
+        .unwrap();
+    let rows = engine
+        .write_formatted_with(df, PrintFormat::Csv, Vec::new(), &options)
+        .await
+        .unwrap();
+    let stats = engine.stream_stats();
+    assert!(stats.timed_out);
+    assert_eq!(stats.rows, rows);
+
+    // A query finishing in time is complete
+    let engine = QueryEngine::new().unwrap();
+    let options = WriteOptions {
+        deadline: Some(Instant::now() + Duration::from_secs(60)),
+        ..Default::default()
+    };
+    let df = engine
+        .execute_query("SELECT value FROM generate_series(1, 10)")
+        .await
+        .unwrap();
+    let rows = engine
+        .write_formatted_with(df, PrintFormat::Csv, Vec::new(), &options)
+        .await
+        .unwrap();
+    assert_eq!(rows, 10);
+    assert!(!engine.stream_stats().timed_out);
+}
+
+#[tokio::test]
+async fn test_max_rows() {
+    let csv_path = fixture_path("sample.csv");
+    let options = WriteOptions {
+        max_rows: Some(3),
+        ..Default::default()
+    };
+
+    for (query, truncated) in [
+        ("SELECT * FROM employees", true),
+        ("SELECT * FROM employees LIMIT 5", true),
+        ("SELECT * FROM employees LIMIT 3", false),
+    ] {
+        let mut engine = QueryEngine::new().unwrap();
+        engine
+            .register_file(csv_path.to_str().unwrap(), "employees")
+            .await
+            .unwrap();
+
+        let df = engine.execute_query(query).await.unwrap();
+        let rows = engine
+            .write_formatted_with(df, PrintFormat::Csv, Vec::new(), &options)
+            .await
+            .unwrap();
+
+        assert_eq!(rows, 3, "{}", query);
+        let stats = engine.stream_stats();
+        assert_eq!(stats.rows, 3, "{}", query);
+        assert_eq!(stats.truncated, truncated, "{}", query);
+    }
+}
+
+#[tokio::test]
+async fn test_register_udf() {
+    use datafusion::arrow::array::Int64Array;
+    use datafusion::arrow::datatypes::DataType;
+    use datafusion::common::cast::as_int64_array;
+    use sql_stream::{create_udf, ColumnarValue, Volatility};
+    use std::sync::Arc;
+
+    let double = create_udf(
+        "double",
+        vec![DataType::Int64],
+        DataType::Int64,
+        Volatility::Immutable,
+        Arc::new(
+            |args: &[ColumnarValue]| -> datafusion::error::Result<ColumnarValue> {
+                let args = ColumnarValue::values_to_arrays(args)?;
+                let doubled: Int64Array = as_int64_array(&args[0])?
+                    .iter()
+                    .map(|v| v.map(|v| v * 2))
+                    .collect();
+                Ok(ColumnarValue::Array(Arc::new(doubled)))
+            },
+        ),
+    );
+
+    let mut engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("sample.csv");
+    engine
+        .register_file(csv_path.to_str().unwrap(), "employees")
+        .await
+        .unwrap();
+    engine.register_udf(double);
+
+    let batches = engine
+        .collect_query("SELECT id, double(id) AS doubled FROM employees ORDER BY id LIMIT 3")
+        .await
+        .unwrap();
+    let ids = as_int64_array(batches[0].column(0)).unwrap();
+    let doubled = as_int64_array(batches[0].column(1)).unwrap();
+    assert_eq!(doubled.len(), 3);
+    for i in 0..doubled.len() {
+        assert_eq!(doubled.value(i), ids.value(i) * 2);
+    }
+}
+
+#[tokio::test]
+async fn test_execute_script() {
+    let mut engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("sample.csv");
+    engine
+        .register_file(csv_path.to_str().unwrap(), "employees")
+        .await
+        .unwrap();
+
+    let df = engine
+        .execute_script(
+            "CREATE VIEW ny AS SELECT * FROM employees WHERE city = 'New York';\n\
+             -- the last statement's results are returned\n\
+             SELECT name FROM ny;",
+        )
+        .await
+        .unwrap();
+    let view_rows: usize = df
+        .collect()
+        .await
+        .unwrap()
+        .iter()
+        .map(|b| b.num_rows())
+        .sum();
+
+    let direct = engine
+        .collect_query("SELECT name FROM employees WHERE city = 'New York'")
+        .await
+        .unwrap();
+    let direct_rows: usize = direct.iter().map(|b| b.num_rows()).sum();
+    assert!(view_rows > 0);
+    assert_eq!(view_rows, direct_rows);
+
+    // A failing leading statement stops the script
+    let result = engine
+        .execute_script("SELECT * FROM missing; SELECT 1")
+        .await;
+    assert!(matches!(result, Err(SqlStreamError::TableNotFound { .. })));
+    assert!(engine.execute_script("  -- nothing here").await.is_err());
+}
+
+#[tokio::test]
+async fn test_json_array_and_ndjson() {
+    let mut engine = QueryEngine::new().unwrap();
+    for (file, table) in [("sample.json", "lines"), ("sample_array.json", "array")] {
+        engine
+            .register_file(fixture_path(file).to_str().unwrap(), table)
+            .await
+            .unwrap();
+    }
+
+    let query = |table: &str| format!("SELECT id, name, salary FROM {} ORDER BY id", table);
+    let lines = engine.collect_query(&query("lines")).await.unwrap();
+    let array = engine.collect_query(&query("array")).await.unwrap();
+    let format = |batches: &[sql_stream::RecordBatch]| {
+        datafusion::arrow::util::pretty::pretty_format_batches(batches)
+            .unwrap()
+            .to_string()
+    };
+    assert!(!lines.is_empty());
+    assert_eq!(format(&lines), format(&array));
+
+    // A JSON array of non-objects is reported as such, not as a schema failure
+    let dir = tempfile::tempdir().unwrap();
+    let numbers = dir.path().join("numbers.json");
+    std::fs::write(&numbers, "[1, 2, 3]").unwrap();
+    let result = engine
+        .register_file(numbers.to_str().unwrap(), "numbers")
+        .await;
+    assert!(matches!(result, Err(SqlStreamError::JsonArray { .. })));
+}
+
+#[tokio::test]
+async fn test_json_modes() {
+    use sql_stream::preprocess::JsonMode;
+
+    let with_mode = |json_mode| ReadOptions {
+        json_mode,
+        ..Default::default()
+    };
+    let mut engine = QueryEngine::new().unwrap();
+    let count = |table: &str| format!("SELECT COUNT(*) AS n FROM {}", table);
+
+    // Every mode reads the files in its own layout, compressed or not
+    let cases = [
+        ("sample.json", JsonMode::Ndjson, "ndjson"),
+        ("sample.json.zst", JsonMode::Ndjson, "ndjson_zst"),
+        ("sample.json", JsonMode::Auto, "auto_lines"),
+        ("sample_array.json", JsonMode::Auto, "auto_array"),
+        ("sample_array.json", JsonMode::Array, "array"),
+    ];
+    let mut counts = Vec::new();
+    for (file, mode, table) in cases {
+        engine
+            .register_file_with(
+                fixture_path(file).to_str().unwrap(),
+                table,
+                &with_mode(mode),
+            )
+            .await
+            .unwrap();
+        let batches = engine.collect_query(&count(table)).await.unwrap();
+        counts.push(
+            batches[0]
+                .column(0)
+                .as_any()
+                .downcast_ref::<datafusion::arrow::array::Int64Array>()
+                .unwrap()
+                .value(0),
+        );
+    }
+    assert!(counts[0] > 0);
+    assert!(counts.iter().all(|&n| n == counts[0]), "{:?}", counts);
+
+    // A declared layout is not second-guessed
+    let result = engine
+        .register_file_with(
+            fixture_path("sample.json").to_str().unwrap(),
+            "not_array",
+            &with_mode(JsonMode::Array),
+        )
+        .await;
+    assert!(matches!(result, Err(SqlStreamError::JsonArray { .. })));
+    let result = engine
+        .register_file_with(
+            fixture_path("sample_array.json").to_str().unwrap(),
+            "not_lines",
+            &with_mode(JsonMode::Ndjson),
+        )
+        .await;
+    let failed = match result {
+        Err(_) => true,
+        Ok(()) => engine.collect_query(&count("not_lines")).await.is_err(),
+    };
+    assert!(failed);
+}
+
+#[tokio::test]
+async fn test_parse_error_names_file() {
+    let mut engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("bad_row.csv");
+    let result = engine
+        .register_file(csv_path.to_str().unwrap(), "bad")
+        .await;
+
+    match result {
+        Err(SqlStreamError::ParseError { file, detail }) => {
+            assert_eq!(file, csv_path);
+            assert!(!detail.is_empty());
+        }
+        other => panic!("expected a parse error, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_precision() {
+    let engine = QueryEngine::new().unwrap();
+    let query = "SELECT CAST(1 AS DOUBLE) / 3 AS third, 7 AS n";
+    let options = WriteOptions {
+        precision: Some(2),
+        ..Default::default()
+    };
+
+    let mut csv = Vec::new();
+    let dataframe = engine.execute_query(query).await.unwrap();
+    engine
+        .write_formatted_with(dataframe, PrintFormat::Csv, &mut csv, &options)
+        .await
+        .unwrap();
+    assert_eq!(String::from_utf8(csv).unwrap(), "third,n\n0.33,7\n");
+
+    // JSON keeps the exact value
+    let mut json = Vec::new();
+    let dataframe = engine.execute_query(query).await.unwrap();
+    engine
+        .write_formatted_with(dataframe, PrintFormat::Ndjson, &mut json, &options)
+        .await
+        .unwrap();
+    assert!(String::from_utf8(json)
+        .unwrap()
+        .contains("0.3333333333333333"));
+}
+
+#[tokio::test]
+async fn test_sample_query() {
+    let mut engine = QueryEngine::new().unwrap();
+    engine
+        .register_file(fixture_path("sample.csv").to_str().unwrap(), "employees")
+        .await
+        .unwrap();
+
+    // A sample larger than the table returns every row
+    for (rows, expected) in [(1, 1), (3, 3), (100, 10)] {
+        let result = engine
+            .run(&sql_stream::sql::sample_query("employees", rows))
+            .await
+            .unwrap();
+        assert_eq!(result.row_count, expected, "--sample {}", rows);
+        assert_eq!(result.schema.fields().len(), 5);
+    }
+}
+
+#[tokio::test]
+async fn test_select_columns() {
+    let mut engine = QueryEngine::new().unwrap();
+    engine
+        .register_file(fixture_path("sample.csv").to_str().unwrap(), "employees")
+        .await
+        .unwrap();
+    let columns = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+
+    // Columns come out in the requested order
+    let query = sql_stream::sql::table_query("employees");
+    let schema = engine
+        .plan_query(&query)
+        .await
+        .unwrap()
+        .schema()
+        .as_arrow()
+        .clone();
+    let projected =
+        transform::select_columns(&query, &schema, &columns(&["salary", "name"])).unwrap();
+    let result = engine.run(&projected).await.unwrap();
+    let names: Vec<_> = result
+        .schema
+        .fields()
+        .iter()
+        .map(|f| f.name().as_str())
+        .collect();
+    assert_eq!(names, ["salary", "name"]);
+    assert_eq!(result.row_count, 10);
+
+    // Only the query's output columns can be picked
+    let query = "SELECT name, age FROM employees WHERE age > 30";
+    let schema = engine
+        .plan_query(query)
+        .await
+        .unwrap()
+        .schema()
+        .as_arrow()
+        .clone();
+    let err = transform::select_columns(query, &schema, &columns(&["age", "city"])).unwrap_err();
+    assert!(matches!(err, SqlStreamError::ColumnNotFound { .. }));
+    assert_eq!(
+        err.to_string(),
+        "Column 'city' not found. Available columns: name, age"
+    );
+}
+
+#[tokio::test]
+async fn test_count_results() {
+    let mut engine = QueryEngine::new().unwrap();
+    engine
+        .register_file(fixture_path("sample.csv").to_str().unwrap(), "data")
+        .await
+        .unwrap();
+
+    let count = |sql: &'static str| {
+        let engine = &engine;
+        async move {
+            let dataframe = engine.execute_query(sql).await.unwrap();
+            engine.count_results(dataframe).await.unwrap()
+        }
+    };
+    assert_eq!(count("SELECT * FROM data").await, 10);
+    assert_eq!(count("SELECT name FROM data WHERE age > 30").await, 5);
+    assert_eq!(
+        count("SELECT age > 30 AS senior, COUNT(*) FROM data GROUP BY age > 30").await,
+        2
+    );
+    assert_eq!(count("SELECT * FROM data LIMIT 3").await, 3);
+    assert_eq!(count("SELECT * FROM data WHERE age > 100").await, 0);
+}
+
+#[tokio::test]
+async fn test_plan_query() {
+    let mut engine = QueryEngine::new().unwrap();
+    engine
+        .register_file(fixture_path("sample.csv").to_str().unwrap(), "data")
+        .await
+        .unwrap();
+
+    let dataframe = engine
+        .plan_query("SELECT name, salary * 2 AS double_salary FROM data")
+        .await
+        .unwrap();
+    let names: Vec<_> = dataframe
+        .schema()
+        .fields()
+        .iter()
+        .map(|f| f.name().clone())
+        .collect();
+    assert_eq!(names, ["name", "double_salary"]);
+
+    assert!(engine.plan_query("SELECT missing FROM data").await.is_err());
+    assert!(engine.plan_query("SELEC name FROM data").await.is_err());
+
+    // DDL is planned but not run
+    engine
+        .plan_query("CREATE VIEW adults AS SELECT * FROM data WHERE age >= 18")
+        .await
+        .unwrap();
+    assert!(!engine.list_tables().contains(&"adults".to_string()));
+}
+
+#[tokio::test]
+async fn test_null_value() {
+    let mut engine = QueryEngine::new().unwrap();
+    let read_options = ReadOptions {
+        null_regex: Some("NA".to_string()),
+        ..Default::default()
+    };
+    engine
+        .register_file_with(
+            fixture_path("na_values.csv").to_str().unwrap(),
+            "scores",
+            &read_options,
+        )
+        .await
+        .unwrap();
+
+    let count =
+        |batches: Vec<sql_stream::RecordBatch>| batches.iter().map(|b| b.num_rows()).sum::<usize>();
+    // "NAME" does not match: the regex must cover the whole field
+    let names = engine
+        .collect_query("SELECT id FROM scores WHERE name IS NULL")
+        .await
+        .unwrap();
+    assert_eq!(count(names), 1);
+    // Empty fields stay NULL alongside the sentinel, and the column is numeric
+    let scores = engine
+        .collect_query("SELECT id FROM scores WHERE score IS NULL OR score > 20")
+        .await
+        .unwrap();
+    assert_eq!(count(scores), 3);
+
+    let dir = tempfile::tempdir().unwrap();
+    let output_path = dir.path().join("out.csv");
+    let dataframe = engine
+        .execute_query("SELECT * FROM scores ORDER BY id")
+        .await
+        .unwrap();
+    let write_options = WriteOptions {
+        null_value: Some("NA".to_string()),
+        ..Default::default()
+    };
+    engine
+        .write_results_with(dataframe, &output_path, &write_options)
+        .await
+        .unwrap();
+    let written = std::fs::read_to_string(&output_path).unwrap();
+    assert!(written.contains("2,NA,NA\n"), "{}", written);
+    assert!(written.contains("3,gamma,NA\n"), "{}", written);
+}
+
+#[tokio::test]
+async fn test_table_not_found() {
+    let mut engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("sample.csv");
+    for table in ["data", "orders"] {
+        engine
+            .register_file(csv_path.to_str().unwrap(), table)
+            .await
+            .unwrap();
+    }
+
+    let result = engine.execute_query("SELECT * FROM employees").await;
+    match result {
+        Err(SqlStreamError::TableNotFound {
+            requested,
+            available,
+        }) => {
+            assert_eq!(requested, "employees");
+            assert_eq!(available, ["data", "orders"]);
+        }
+        other => panic!("expected TableNotFound, got {:?}", other.err()),
+    }
+
+    // Planning and table lookups report it the same way
+    let result = engine
+        .plan_query("SELECT o.id FROM orders o JOIN customers c ON o.id = c.id")
+        .await;
+    assert!(matches!(
+        result,
+        Err(SqlStreamError::TableNotFound { requested, .. }) if requested == "customers"
+    ));
+    assert!(matches!(
+        engine.table("nope").await,
+        Err(SqlStreamError::TableNotFound { .. })
+    ));
+
+    // Other planning errors are left alone
+    let result = engine.execute_query("SELECT nope FROM data").await;
+    assert!(matches!(result, Err(SqlStreamError::QueryExecution(_))));
+}
+
+#[tokio::test]
+async fn test_table_name_case_folding() {
+    let mut engine = QueryEngine::new().unwrap();
+    let csv_path = fixture_path("sample.csv");
+    engine
+        .register_file(csv_path.to_str().unwrap(), "MyData")
+        .await
+        .unwrap();
+    assert_eq!(engine.list_tables(), vec!["mydata".to_string()]);
+    for query in [
+        "SELECT * FROM MyData",
+        "SELECT * FROM mydata",
+        "SELECT * FROM \"mydata\"",
+    ] {
+        assert!(engine.collect_query(query).await.is_ok(), "{}", query);
+    }
+    assert!(engine
+        .collect_query("SELECT * FROM \"MyData\"")
+        .await
+        .is_err());
+
+    // Names that aren't plain identifiers are kept verbatim and must be quoted
+    engine
+        .register_file(csv_path.to_str().unwrap(), "My Data")
+        .await
+        .unwrap();
+    assert!(engine
+        .collect_query("SELECT * FROM \"My Data\"")
+        .await
+        .is_ok());
+
+    // Reserved words register too, but only work quoted
+    engine
+        .register_file(csv_path.to_str().unwrap(), "Order")
+        .await
+        .unwrap();
+    assert!(engine
+        .collect_query("SELECT * FROM \"order\"")
+        .await
+        .is_ok());
+
+    for blank in ["", "  "] {
+        let result = engine
+            .register_file(csv_path.to_str().unwrap(), blank)
+            .await;
+        assert!(matches!(result, Err(SqlStreamError::TableRegistration(..))));
+    }
+}
+
+/// Serve every request with `status` and `body` from a local HTTP server
+async fn serve_http(status: &'static str, body: &'static str) -> std::net::SocketAddr {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                match socket.read(&mut buffer).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => request.extend_from_slice(&buffer[..n]),
+                }
+            }
+
+            let head = format!(
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nContent-Type: text/csv\r\n\
+                 Last-Modified: Mon, 01 Jan 2024 00:00:00 GMT\r\nETag: \"v1\"\r\n\
+                 Connection: close\r\n\r\n",
+                status,
+                body.len()
+            );
+            let mut response = head.into_bytes();
+            if !request.starts_with(b"HEAD") {
+                response.extend_from_slice(body.as_bytes());
+            }
+            let _ = socket.write_all(&response).await;
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn test_remote_csv() {
+    let addr = serve_http("200 OK", "id,name\n1,alpha\n2,beta\n").await;
+    let mut engine = QueryEngine::new().unwrap();
+    engine
+        .register_file(&format!("http://{}/export/items.csv", addr), "items")
+        .await
+        .unwrap();
+    let batches = engine
+        .collect_query("SELECT name FROM items WHERE id = 2")
+        .await
+        .unwrap();
+    assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 1);
+
+    let addr = serve_http("404 Not Found", "").await;
+    let result = engine
+        .register_file(&format!("http://{}/missing.csv", addr), "missing")
+        .await;
+    assert!(
+        matches!(result, Err(SqlStreamError::RemoteFetch { .. })),
+        "{:?}",
+        result
+    );
+}
+
+#[tokio::test]
+async fn test_remote_timeout() {
+    use std::time::Duration;
+
+    // Accept connections but never answer them
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut sockets = Vec::new();
+        while let Ok((socket, _)) = listener.accept().await {
+            sockets.push(socket);
+        }
+    });
+
+    let mut engine = QueryEngine::new().unwrap();
+    let options = ReadOptions {
+        request_timeout: Some(Duration::from_millis(200)),
+        open_retries: Some(1),
+        ..Default::default()
+    };
+    let result = engine
+        .register_file_with(&format!("http://{}/slow.csv", addr), "slow", &options)
+        .await;
+    match result {
+        Err(SqlStreamError::RemoteFetch { detail, .. }) => {
+            assert!(
+                detail.starts_with("timed out after 1 retries"),
+                "{}",
+                detail
+            );
+            assert!(detail.contains("request timeout 0.2s"), "{}", detail);
+        }
+        other => panic!("expected a fetch timeout, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_cli_stdout_formats() {
+    use std::process::Command;
+
+    // Logs go to stderr, so stdout holds nothing but the results
+    let run = |format: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_sql-stream"))
+            .args(["-f", fixture_path("sample.csv").to_str().unwrap()])
+            .args(["-o", format])
+            .args(["-q", "SELECT id, name FROM data WHERE id <= 2 ORDER BY id"])
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let csv = run("csv");
+    let mut reader = csv::Reader::from_reader(csv.as_bytes());
+    assert_eq!(reader.headers().unwrap(), vec!["id", "name"]);
+    assert_eq!(reader.records().count(), 2);
+
+    let json: serde_json::Value = serde_json::from_str(&run("json")).unwrap();
+    assert_eq!(json[1]["name"], "Bob Smith");
+
+    let ndjson = run("ndjson");
+    let rows: Vec<serde_json::Value> = ndjson
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0]["id"], 1);
+}